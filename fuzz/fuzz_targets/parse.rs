@@ -0,0 +1,46 @@
+//! Throw arbitrary bytes at all the row and temperature parsers. The fast ones skip nearly all
+//! validation, so the only thing being checked here is that they never panic.
+
+#![no_main]
+
+use std::num::NonZeroU8;
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::fuzzing::{parse_row, parse_row_strict, parse_row_with};
+use onebrc::{Config, FieldOrder, Temperature};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Temperature::parse(data);
+    let _ = Temperature::parse_allow_integer(data);
+    let _ = Temperature::parse_strict(data);
+    let _ = Temperature::parse_int_degrees(data);
+    let _ = Temperature::parse_strict_int_degrees(data);
+
+    if let Some((city, _)) = parse_row(data) {
+        assert!(!city.contains(&b';'));
+    }
+
+    for order in [FieldOrder::CityTemp, FieldOrder::TempCity] {
+        for allow_integer in [false, true] {
+            let config = Config {
+                order,
+                allow_integer,
+                ..Config::default()
+            };
+            let _ = parse_row_with(data, &config);
+            let _ = parse_row_strict(data, &config);
+        }
+    }
+
+    // quoted CSV fields, with the city after the temperature
+    let csv = Config {
+        delimiter: b',',
+        quoted: true,
+        temp_field: NonZeroU8::new(2),
+        city_field: NonZeroU8::new(3),
+        ..Config::default()
+    };
+    let _ = parse_row_with(data, &csv);
+    let _ = parse_row_strict(data, &csv);
+    let _ = csv.csv_columns(data, "city", "temperature");
+});
@@ -0,0 +1,14 @@
+//! Anything that the strict temperature parser accepts should come out exactly the same from the
+//! fast parsers.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::Temperature;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(strict) = Temperature::parse_strict(data) {
+        assert_eq!(Temperature::parse(data), strict);
+        assert_eq!(Temperature::parse_allow_integer(data), strict);
+    }
+});
@@ -0,0 +1,6 @@
+//! Compile src/temperature.rs in a `#![no_std]` crate to make sure it stays usable without std.
+//! (The unit tests in there need std though, so allow it for `cargo clippy --all-targets`)
+#![cfg_attr(not(test), no_std)]
+
+#[path = "../../src/temperature.rs"]
+pub mod temperature;
@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+
+use bstr::BString;
+
+use crate::{Count, FinalStats, Temperature, Total};
+
+const MAGIC: &[u8; 4] = b"1BRB";
+/// stand-in for a missing mode
+const NO_MODE: i32 = i32::MIN;
+
+/// Write `summary` in a compact binary format, for handing it to another program without
+/// formatting and re-parsing text. `read_binary` decodes it.
+///
+/// Everything is little-endian. The stream starts with the 4 magic bytes `1BRB`, followed by one
+/// record per city in the same order as the summary (sorted by name):
+///
+/// | type   | field                                                   |
+/// |--------|---------------------------------------------------------|
+/// | `u32`  | length of the city name in bytes                        |
+/// | bytes  | city name, not necessarily UTF-8                        |
+/// | `i32`  | min, in tenths of a degree Celsius                      |
+/// | `i32`  | mean, in tenths of a degree Celsius                     |
+/// | `i32`  | max, in tenths of a degree Celsius                      |
+/// | `i32`  | mode, in tenths of a degree Celsius, `i32::MIN` if none |
+/// | `u64`  | count                                                   |
+/// | `i128` | total of all the measurements, in tenths                |
+///
+/// The total is there so that a reader can work out the mean to any precision, like
+/// `FinalStats::precise_mean` does. Temperatures are always Celsius, regardless of the unit used
+/// for text output. Quantile estimates and the first and last temperatures aren't included.
+// these casts are no-ops with the wide-total feature
+#[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+pub fn write_binary(out: &mut impl Write, summary: &[(BString, FinalStats)]) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    for (city, stats) in summary {
+        let len = u32::try_from(city.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "city name too long"))?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(city)?;
+        let mode = stats.mode.map_or(NO_MODE, Temperature::to_tenths);
+        for temp in [
+            stats.min.to_tenths(),
+            stats.mean.to_tenths(),
+            stats.max.to_tenths(),
+            mode,
+        ] {
+            out.write_all(&temp.to_le_bytes())?;
+        }
+        out.write_all(&(stats.count as u64).to_le_bytes())?;
+        out.write_all(&(stats.total as i128).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Decode results written by `write_binary`
+pub fn read_binary(data: &[u8]) -> Result<Vec<(BString, FinalStats)>, &'static str> {
+    let mut data = data.strip_prefix(MAGIC).ok_or("not binary results")?;
+    let mut summary = Vec::new();
+    while !data.is_empty() {
+        let len = u32::from_le_bytes(take(&mut data)?);
+        let city = take_slice(&mut data, len as usize)?.into();
+        let mut temp = || take(&mut data).map(|b| Temperature::from_tenths(i32::from_le_bytes(b)));
+        let (min, mean, max, mode) = (temp()?, temp()?, temp()?, temp()?);
+        let count = Count::try_from(u64::from_le_bytes(take(&mut data)?))
+            .map_err(|_| "count too big for this build, see the wide-total feature")?;
+        let total = Total::try_from(i128::from_le_bytes(take(&mut data)?))
+            .map_err(|_| "total too big for this build, see the wide-total feature")?;
+        let stats = FinalStats {
+            mean,
+            min,
+            max,
+            mode: (mode.to_tenths() != NO_MODE).then_some(mode),
+            quantile: None,
+            first: None,
+            last: None,
+            total,
+            count,
+        };
+        summary.push((city, stats));
+    }
+    Ok(summary)
+}
+
+/// split off the first `N` bytes of `data`
+fn take<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], &'static str> {
+    let bytes = take_slice(data, N)?;
+    Ok(bytes.try_into().expect("took N bytes"))
+}
+
+/// split off the first `len` bytes of `data`
+fn take_slice<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], &'static str> {
+    if data.len() < len {
+        return Err("truncated record");
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+#[test]
+fn test_binary() {
+    use crate::{process_data, summarize, Config};
+
+    let data = include_bytes!("../testdata/mode.txt");
+    for histogram in [false, true] {
+        let config = Config {
+            histogram,
+            ..Config::default()
+        };
+        // without the first and last temperatures, which don't get encoded
+        let summary: Vec<_> = summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| {
+                let stats = FinalStats {
+                    first: None,
+                    last: None,
+                    ..stats
+                };
+                (city, stats)
+            })
+            .collect();
+        let mut encoded = Vec::new();
+        write_binary(&mut encoded, &summary).unwrap();
+        assert_eq!(read_binary(&encoded).unwrap(), summary);
+
+        // cutting it short between records just loses cities, anywhere else is an error
+        let mut boundaries = 0;
+        for len in 0..encoded.len() {
+            if let Ok(decoded) = read_binary(&encoded[..len]) {
+                assert_eq!(decoded, summary[..decoded.len()]);
+                boundaries += 1;
+            }
+        }
+        assert_eq!(boundaries, summary.len());
+    }
+
+    // spot check the layout
+    let summary = [(
+        BString::from("Oslo"),
+        FinalStats {
+            mean: Temperature::from_tenths(-5),
+            min: Temperature::from_tenths(-30),
+            max: Temperature::from_tenths(45),
+            mode: None,
+            quantile: None,
+            first: None,
+            last: None,
+            total: -15,
+            count: 3,
+        },
+    )];
+    let mut encoded = Vec::new();
+    write_binary(&mut encoded, &summary).unwrap();
+    let mut expected = b"1BRB\x04\0\0\0Oslo".to_vec();
+    for n in [-30i32, -5, 45, i32::MIN] {
+        expected.extend(n.to_le_bytes());
+    }
+    expected.extend(3u64.to_le_bytes());
+    expected.extend((-15i128).to_le_bytes());
+    assert_eq!(encoded, expected);
+
+    assert_eq!(read_binary(MAGIC).unwrap(), []);
+    assert!(read_binary(b"").is_err());
+    assert!(read_binary(b"{Oslo=-3.0/-0.5/4.5}").is_err());
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The names of the values in each entry, in order
+const FIELDS: [&str; 4] = ["min", "mean", "max", "mode"];
+
+/// `onebrc diff A B`: compare two brace-format output files and list every city that differs,
+/// is missing from B, or is extra in B. Returns the exit code: 0 if they're the same, 1 if not,
+/// and 2 for bad arguments or unreadable files.
+pub fn run_diff(args: &[String], out: &mut impl Write) -> i32 {
+    let [a_path, b_path] = args else {
+        eprintln!("usage: onebrc diff A B");
+        return 2;
+    };
+    let read = |path: &String| {
+        let data = std::fs::read(path).map_err(|err| format!("failed to read '{path}': {err}"))?;
+        parse_brace(&String::from_utf8_lossy(&data)).map_err(|err| format!("{path}: {err}"))
+    };
+    let (a, b) = match (read(a_path), read(b_path)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("error: {err}");
+            return 2;
+        }
+    };
+    match diff(&a, &b, out) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("error: failed to write output: {err}");
+            2
+        }
+    }
+}
+
+/// Parse `{city=min/mean/max, ...}` output (with or without a mode) into each city's values,
+/// kept as the original strings so that a change in precision still counts as a difference.
+fn parse_brace(s: &str) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let inner = s
+        .trim_end()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or("not brace format output")?;
+    let mut results = BTreeMap::new();
+    if inner.is_empty() {
+        return Ok(results);
+    }
+
+    // A city name can have ", " in it too, so glue pieces back together until there's a whole
+    // entry ending in its values.
+    let mut entry = String::new();
+    for piece in inner.split(", ") {
+        if !entry.is_empty() {
+            entry.push_str(", ");
+        }
+        entry.push_str(piece);
+        if let Some((city, values)) = parse_entry(&entry) {
+            results.insert(city.to_string(), values);
+            entry.clear();
+        }
+    }
+    if entry.is_empty() {
+        Ok(results)
+    } else {
+        Err(format!("invalid entry '{entry}'"))
+    }
+}
+
+/// split one `city=min/mean/max` entry, or None if it doesn't end with 3 or 4 numbers
+fn parse_entry(entry: &str) -> Option<(&str, Vec<String>)> {
+    let (city, values) = entry.rsplit_once('=')?;
+    let values: Vec<String> = values.split('/').map(String::from).collect();
+    let valid = (3..=4).contains(&values.len()) && values.iter().all(|v| v.parse::<f64>().is_ok());
+    valid.then_some((city, values))
+}
+
+/// Write a line to `out` for each city that's different between `a` and `b`, and return whether
+/// they're the same
+fn diff(
+    a: &BTreeMap<String, Vec<String>>,
+    b: &BTreeMap<String, Vec<String>>,
+    out: &mut impl Write,
+) -> std::io::Result<bool> {
+    let mut same = true;
+    for (city, a_values) in a {
+        let Some(b_values) = b.get(city) else {
+            writeln!(out, "{city}: missing")?;
+            same = false;
+            continue;
+        };
+        let changes: Vec<String> = FIELDS
+            .iter()
+            .zip(0..a_values.len().max(b_values.len()))
+            .filter_map(|(name, i)| change(name, a_values.get(i), b_values.get(i)))
+            .collect();
+        if !changes.is_empty() {
+            writeln!(out, "{city}: {}", changes.join(", "))?;
+            same = false;
+        }
+    }
+    for city in b.keys().filter(|city| !a.contains_key(*city)) {
+        writeln!(out, "{city}: extra")?;
+        same = false;
+    }
+    Ok(same)
+}
+
+/// describe how one value changed, like `mean 12.3 -> 12.5 (+0.2)`, or None if it didn't
+fn change(name: &str, a: Option<&String>, b: Option<&String>) -> Option<String> {
+    match (a, b) {
+        (Some(a), Some(b)) if a == b => None,
+        (Some(a), Some(b)) => {
+            // as many decimals as the more precise of the two, so there's no float noise
+            let decimals = |s: &str| s.split_once('.').map_or(0, |(_, frac)| frac.len());
+            let precision = decimals(a).max(decimals(b));
+            let delta = b.parse::<f64>().ok()? - a.parse::<f64>().ok()?;
+            Some(format!("{name} {a} -> {b} ({delta:+.precision$})"))
+        }
+        (a, b) => {
+            let show = |v: Option<&String>| v.map_or("none".to_string(), String::clone);
+            Some(format!("{name} {} -> {}", show(a), show(b)))
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = |name: &str, contents: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let run = |a: &String, b: &String| {
+        let mut out = Vec::new();
+        let code = run_diff(&[a.clone(), b.clone()], &mut out);
+        (code, String::from_utf8(out).unwrap())
+    };
+
+    let base = file("base.txt", "{Oslo=-3.0/-0.5/4.5, Paris=9.9/13.6/20.0}\n");
+    assert_eq!(run(&base, &base), (0, String::new()));
+    let copy = file("copy.txt", "{Oslo=-3.0/-0.5/4.5, Paris=9.9/13.6/20.0}\n");
+    assert_eq!(run(&base, &copy), (0, String::new()));
+
+    let changed = file("changed.txt", "{Oslo=-3.0/-0.5/4.5, Paris=9.9/13.8/20.1}\n");
+    assert_eq!(
+        run(&base, &changed),
+        (
+            1,
+            "Paris: mean 13.6 -> 13.8 (+0.2), max 20.0 -> 20.1 (+0.1)\n".into()
+        )
+    );
+
+    let missing = file("missing.txt", "{Paris=9.9/13.6/20.0, Rome=1.0/1.0/1.0}\n");
+    assert_eq!(
+        run(&base, &missing),
+        (1, "Oslo: missing\nRome: extra\n".into())
+    );
+
+    // extra precision and a mode are differences too
+    let precise = file(
+        "precise.txt",
+        "{Oslo=-3.0/-0.50/4.5/-3.0, Paris=9.9/13.6/20.0}\n",
+    );
+    assert_eq!(
+        run(&base, &precise),
+        (
+            1,
+            "Oslo: mean -0.5 -> -0.50 (+0.00), mode none -> -3.0\n".into()
+        )
+    );
+
+    // city names can have anything in them
+    let odd = "{A, B=1.0/1.0/1.0, x=y=2.0/2.0/2.0}";
+    let parsed = parse_brace(odd).unwrap();
+    assert_eq!(parsed.keys().collect::<Vec<_>>(), ["A, B", "x=y"]);
+    assert_eq!(parse_brace("{}\n").unwrap().len(), 0);
+    assert!(parse_brace("city,min,mean,max\n").is_err());
+    assert!(parse_brace("{Oslo=cold}").is_err());
+
+    let csv = file("out.csv", "city,min,mean,max\n");
+    assert_eq!(run(&base, &csv).0, 2);
+    assert_eq!(
+        run(&base, &dir.path().join("nope").to_str().unwrap().into()).0,
+        2
+    );
+    assert_eq!(run_diff(&[base], &mut Vec::new()), 2);
+}
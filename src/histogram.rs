@@ -0,0 +1,179 @@
+use crate::temperature::Temperature;
+
+/// Lowest temperature (in tenths) with its own bin. Anything colder gets lumped in here.
+const MIN_TENTHS: i32 = -999;
+/// Highest temperature (in tenths) with its own bin. Anything hotter gets lumped in here.
+const MAX_TENTHS: i32 = 999;
+const BIN_COUNT: usize = (MAX_TENTHS - MIN_TENTHS + 1) as usize;
+
+/// A bounded histogram of temperatures, one bin per tenth of a degree covering the challenge's
+/// full -99.9 to 99.9 range. Out of range values are clamped into the first/last bin.
+///
+/// This is 8K of counters, so it's only allocated when a statistic that needs it was requested.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bins: Box<[u32]>,
+}
+
+impl std::fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // 1999 mostly-zero counters isn't useful debug output, print only the non-empty ones
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            bins: vec![0; BIN_COUNT].into_boxed_slice(),
+        }
+    }
+
+    fn index(temp: Temperature) -> usize {
+        (temp.to_tenths().clamp(MIN_TENTHS, MAX_TENTHS) - MIN_TENTHS) as usize
+    }
+
+    /// count one observation of `temp`
+    pub fn record(&mut self, temp: Temperature) {
+        self.record_weighted(temp, 1);
+    }
+
+    /// count `weight` observations of `temp` at once
+    pub fn record_weighted(&mut self, temp: Temperature, weight: u32) {
+        self.bins[Self::index(temp)] += weight;
+    }
+
+    /// add all of `other`'s counts into this one
+    pub fn merge(&mut self, other: &Histogram) {
+        for (mine, theirs) in self.bins.iter_mut().zip(other.bins.iter()) {
+            *mine += *theirs;
+        }
+    }
+
+    /// whether `record_weighted` wouldn't overflow `temp`'s bin
+    pub fn can_record(&self, temp: Temperature, weight: u32) -> bool {
+        self.bins[Self::index(temp)].checked_add(weight).is_some()
+    }
+
+    /// whether `merge` wouldn't overflow any bin
+    pub fn can_merge(&self, other: &Histogram) -> bool {
+        (self.bins.iter().zip(other.bins.iter()))
+            .all(|(mine, theirs)| mine.checked_add(*theirs).is_some())
+    }
+
+    /// The most frequently observed temperature, or None if nothing was recorded. Ties go to the
+    /// lower temperature.
+    pub fn mode(&self) -> Option<Temperature> {
+        let mut best = None;
+        let mut best_count = 0;
+        for (i, count) in self.bins.iter().copied().enumerate() {
+            // strictly greater, so the first (lowest) bin wins a tie
+            if count > best_count {
+                best = Some(i);
+                best_count = count;
+            }
+        }
+        best.map(|i| Temperature::from_tenths(i as i32 + MIN_TENTHS))
+    }
+
+    /// iterate over (temperature, count) for all non-empty bins, from coldest to hottest
+    pub fn iter(&self) -> impl Iterator<Item = (Temperature, u32)> + '_ {
+        self.bins
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .map(|(i, count)| (Temperature::from_tenths(i as i32 + MIN_TENTHS), count))
+    }
+}
+
+/// Histogram of every temperature in the whole input, regardless of city. Same bins as
+/// [`Histogram`], but with 64-bit counts since a single bin can easily see billions of rows.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GlobalHistogram {
+    bins: Box<[u64; BIN_COUNT]>,
+}
+
+impl std::fmt::Debug for GlobalHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Default for GlobalHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GlobalHistogram {
+    pub fn new() -> Self {
+        Self {
+            bins: Box::new([0; BIN_COUNT]),
+        }
+    }
+
+    /// count `weight` observations of `temp` at once
+    pub fn record_weighted(&mut self, temp: Temperature, weight: u32) {
+        self.bins[Histogram::index(temp)] += u64::from(weight);
+    }
+
+    /// add all of `other`'s counts into this one
+    pub fn merge(&mut self, other: &GlobalHistogram) {
+        for (mine, theirs) in self.bins.iter_mut().zip(other.bins.iter()) {
+            *mine += *theirs;
+        }
+    }
+
+    /// total number of observations
+    pub fn total(&self) -> u64 {
+        self.bins.iter().sum()
+    }
+
+    /// iterate over (temperature, count) for all non-empty bins, from coldest to hottest
+    pub fn iter(&self) -> impl Iterator<Item = (Temperature, u64)> + '_ {
+        self.bins
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .map(|(i, count)| (Temperature::from_tenths(i as i32 + MIN_TENTHS), count))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_histogram() {
+    let mut hist = Histogram::new();
+    assert_eq!(hist.mode(), None);
+
+    for s in ["1.0", "2.0", "2.0", "3.0", "3.0", "-150.0"] {
+        hist.record(Temperature::parse(s));
+    }
+    // 2.0 and 3.0 are tied, lower one wins
+    assert_eq!(hist.mode(), Some(Temperature::parse("2.0")));
+
+    let mut other = Histogram::new();
+    other.record(Temperature::parse("3.0"));
+    hist.merge(&other);
+    assert_eq!(hist.mode(), Some(Temperature::parse("3.0")));
+
+    // out of range values are clamped into the edge bins
+    let bins: Vec<_> = hist.iter().collect();
+    assert_eq!(bins[0], (Temperature::parse("-99.9"), 1));
+    assert_eq!(bins.iter().map(|(_, c)| c).sum::<u32>(), 7);
+
+    // a bin that's already full can't take any more
+    let mut full = Histogram::new();
+    full.record_weighted(Temperature::parse("1.0"), u32::MAX);
+    assert!(!full.can_record(Temperature::parse("1.0"), 1));
+    assert!(full.can_record(Temperature::parse("2.0"), u32::MAX));
+    assert!(!full.can_merge(&hist));
+    assert!(full.can_merge(&Histogram::new()));
+}
@@ -0,0 +1,153 @@
+use bstr::ByteSlice;
+
+use crate::{Config, Row};
+
+/// How much of the start of each input `dataset_info` looks at
+const PREFIX_LEN: usize = 1 << 20;
+
+/// Quick estimates of an input's shape, from `dataset_info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatasetInfo {
+    /// total input size in bytes, always exact
+    pub size: usize,
+    /// Estimated number of lines. Exact if the whole input fit in the sample, otherwise it's
+    /// extrapolated from the average line length.
+    pub rows: u64,
+    /// Estimated number of unique cities in the sample. A city that only shows up past the
+    /// sample isn't counted, so for huge inputs this is a lower bound.
+    pub cities: u64,
+}
+
+/// Estimate the size, row count, and unique city count of the inputs without a full pass. Only
+/// the first 1MiB of each buffer is read, so this takes about the same time no matter how big
+/// the input is.
+pub fn dataset_info(buffers: &[&[u8]], config: Config) -> DatasetInfo {
+    dataset_info_with_prefix(buffers, config, PREFIX_LEN)
+}
+
+fn dataset_info_with_prefix(buffers: &[&[u8]], config: Config, prefix_len: usize) -> DatasetInfo {
+    let mut info = DatasetInfo {
+        size: 0,
+        rows: 0,
+        cities: 0,
+    };
+    let mut cities = HyperLogLog::new();
+    for data in buffers {
+        info.size += data.len();
+        // whole records only, unless it's all one giant record
+        let prefix = if data.len() <= prefix_len {
+            *data
+        } else {
+            let prefix = &data[..prefix_len];
+            match prefix.rfind_byte(config.record_sep) {
+                Some(pos) => &prefix[..=pos],
+                None => prefix,
+            }
+        };
+
+        let mut lines = 0;
+        for line in prefix.split(|b| *b == config.record_sep) {
+            if line.is_empty() {
+                continue;
+            }
+            lines += 1;
+            if let Some(row) = Row::parse_with(line.as_bstr(), &config) {
+                cities.insert(row.city);
+            }
+        }
+        info.rows += if prefix.len() == data.len() {
+            lines
+        } else {
+            (data.len() as f64 * lines as f64 / prefix.len() as f64).round() as u64
+        };
+    }
+    info.cities = cities.estimate();
+    info
+}
+
+/// log2 of the number of HyperLogLog registers. 4096 registers gives about 1.6% standard error.
+const HLL_BITS: u32 = 12;
+
+/// Minimal HyperLogLog cardinality estimator (Flajolet et al.), with the usual linear counting
+/// correction for small cardinalities.
+struct HyperLogLog {
+    registers: Box<[u8]>,
+    hasher: ahash::RandomState,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; 1 << HLL_BITS].into_boxed_slice(),
+            // fixed seeds so that the estimate is the same every run
+            hasher: ahash::RandomState::with_seeds(1, 2, 3, 4),
+        }
+    }
+
+    fn insert(&mut self, item: &[u8]) {
+        let hash = self.hasher.hash_one(item);
+        let index = (hash >> (64 - HLL_BITS)) as usize;
+        // position of the first 1 bit in the rest of the hash, with a sentinel bit so that it's
+        // never all zeros
+        let rank = ((hash << HLL_BITS) | (1 << (HLL_BITS - 1))).leading_zeros() + 1;
+        self.registers[index] = self.registers[index].max(rank as u8);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-i32::from(*r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_dataset_info() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let within = |estimate: u64, actual: u64, tolerance: f64| {
+        (estimate as f64 - actual as f64).abs() / (actual as f64) < tolerance
+    };
+
+    // everything fits in the sample, so the row count is exact
+    let info = dataset_info(&[data], Config::default());
+    assert_eq!(info.size, data.len());
+    assert_eq!(info.rows, 10_000);
+    assert!(within(info.cities, 10_000, 0.05), "{info:?}");
+
+    // only a small prefix
+    let info = dataset_info_with_prefix(&[data], Config::default(), 32 << 10);
+    assert_eq!(info.size, data.len());
+    assert!(within(info.rows, 10_000, 0.1), "{info:?}");
+
+    // sizes and rows add up over several buffers, cities are counted once
+    let small = include_bytes!("../testdata/measurements-20.txt");
+    let info = dataset_info(&[small, small], Config::default());
+    assert_eq!(info.size, small.len() * 2);
+    assert_eq!(info.rows, 40);
+    let cities = small
+        .lines()
+        .map(|l| l.split_str(";").next())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(info.cities, cities.len() as u64);
+
+    assert_eq!(
+        dataset_info(&[], Config::default()),
+        DatasetInfo {
+            size: 0,
+            rows: 0,
+            cities: 0
+        }
+    );
+}
@@ -0,0 +1,117 @@
+use bstr::BStr;
+
+use crate::{Config, GlobalHistogram, HashMap, ResultsMap, Row, Stats};
+
+/// Aggregated stats for one chunk of the input, with every city name interned into a small
+/// integer id. The hash map only holds (name, id) pairs and the stats live in a dense Vec indexed
+/// by id, so the map's table is less than half the size of a `ResultsMap`'s.
+///
+/// The ids only mean something within one chunk, so this gets turned back into a `ResultsMap`
+/// keyed by name with `into_results` before merging.
+///
+/// In practice that doesn't buy anything. Every row still has to hash its city name to find the
+/// id, and then there's a second random access into the stats Vec. On 20M rows with 100k cities
+/// this was within noise of the plain chunked strategy (3.52s vs 3.57s best of 8) and used about
+/// 10% more peak memory (reading stdin, so the input itself isn't counted), since both maps exist
+/// at once in `into_results`. It's kept as an opt-in `Config::intern` so it's easy to try again
+/// on other hardware.
+#[derive(Debug)]
+pub(crate) struct InternedResults<'a> {
+    ids: HashMap<&'a BStr, u32>,
+    /// each city's stats, indexed by id
+    stats: Vec<Stats>,
+    config: Config,
+    global: Option<GlobalHistogram>,
+}
+
+impl<'a> InternedResults<'a> {
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            ids: HashMap::with_capacity(config.expected_cities as usize),
+            stats: Vec::with_capacity(config.expected_cities as usize),
+            config,
+            global: config.global_histogram.then(GlobalHistogram::new),
+        }
+    }
+
+    /// add a single row to these results
+    #[inline(always)]
+    pub(crate) fn ingest(&mut self, row: Row<'a>) {
+        if let Some(global) = &mut self.global {
+            global.record_weighted(row.temp, row.weight);
+        }
+        if let Some(&id) = self.ids.get(row.city) {
+            self.stats[id as usize].update_row(row.temp, row.weight);
+        } else {
+            // A city name takes at least a couple of bytes, and chunks are much smaller than
+            // 8GiB, so there's no way to run out of ids.
+            let id = self.stats.len() as u32;
+            self.ids.insert(row.city, id);
+            self.stats
+                .push(Stats::new(row.temp, row.weight, id, self.config));
+        }
+    }
+
+    /// translate the ids back into city names
+    pub(crate) fn into_results(self) -> ResultsMap<&'a BStr> {
+        // The names aren't kept in their own Vec while aggregating, since they're only needed
+        // here, so put them back in id order first.
+        let mut names = vec![BStr::new(""); self.stats.len()];
+        for (name, id) in self.ids {
+            names[id as usize] = name;
+        }
+        ResultsMap {
+            map: names.into_iter().zip(self.stats).collect(),
+            config: self.config,
+            global: self.global,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_interned_results() {
+    use crate::{process_data, summarize, Strategy};
+
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let run = |config| format!("{:?}", summarize(process_data(data, config, None).unwrap()));
+    let expected = run(Config::default());
+    for single_threaded in [false, true] {
+        for strategy in [Strategy::Lines, Strategy::Chunked] {
+            let config = Config {
+                intern: true,
+                single_threaded,
+                strategy,
+                ..Config::default()
+            };
+            assert_eq!(run(config), expected);
+        }
+    }
+
+    // histograms still end up with the right cities
+    let config = Config {
+        histogram: true,
+        ..Config::default()
+    };
+    assert_eq!(
+        run(Config {
+            intern: true,
+            ..config
+        }),
+        run(config)
+    );
+
+    // ids go back to the right names
+    let mut results = InternedResults::new(Config::default());
+    for line in ["b;1.0", "a;2.0", "b;3.0", "c;-4.0"] {
+        results.ingest(Row::parse(BStr::new(line)).unwrap());
+    }
+    assert_eq!(results.stats.len(), 3);
+    let results = results.into_results();
+    assert_eq!(results.map[BStr::new("a")].count, 1);
+    assert_eq!(results.map[BStr::new("b")].count, 2);
+    assert_eq!(
+        results.map[BStr::new("c")].min,
+        crate::Temperature::from_tenths(-40)
+    );
+}
@@ -0,0 +1,4105 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{BufReader, Read};
+use std::num::{NonZeroU16, NonZeroU32, NonZeroU8};
+use std::ops::Range;
+
+use bstr::{BStr, BString, ByteSlice};
+
+mod binary;
+mod histogram;
+mod info;
+mod intern;
+#[cfg(all(feature = "rayon", target_os = "linux"))]
+mod numa;
+mod progress;
+mod reader;
+#[cfg(feature = "tdigest")]
+mod tdigest;
+mod temperature;
+#[cfg(feature = "tracing")]
+mod trace;
+mod unit;
+pub use binary::{read_binary, write_binary};
+pub use histogram::{GlobalHistogram, Histogram};
+pub use info::{dataset_info, DatasetInfo};
+use intern::InternedResults;
+pub use progress::Progress;
+use reader::ChunkReader;
+#[cfg(feature = "tdigest")]
+pub use tdigest::Quantile;
+#[cfg(feature = "tdigest")]
+use tdigest::TDigest;
+pub use temperature::{CompactDisplay, Temperature};
+pub use unit::{Rounding, Unit};
+
+type HashMap<K, V> = ahash::AHashMap<K, V>;
+
+/// One parsed line of input, as handed to the visitor in `process_data_visit`
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    city: &'a BStr,
+    temp: Temperature,
+    /// how many observations this row stands for, always 1 unless `Config::weighted`
+    weight: u32,
+}
+
+impl<'a> Row<'a> {
+    pub fn city(&self) -> &'a BStr {
+        self.city
+    }
+
+    pub fn temp(&self) -> Temperature {
+        self.temp
+    }
+
+    /// how many observations this row stands for, always 1 unless `Config::weighted`
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Parse a single row. SPICY HOT!
+    fn parse(s: &'a BStr) -> Option<Self> {
+        // split at the location of the ';'. This means the first character of what we send to
+        // Temperature::parse is ';' but that's fine, it'll be ignored there (and saves us
+        // extra bounds checks manually slicing that away here).
+        // And since lines are short (only a few dozen bytes) it's faster to use a basic naive
+        // linear byte-by-byte search that s.iter().position() compiles down to rather than
+        // something like memchr.
+        let (city, temp_s) = s.split_at(s.iter().position(|b| *b == b';')?);
+        let temp = Temperature::parse(temp_s);
+        Some(Self {
+            city: BStr::new(city),
+            temp,
+            weight: 1,
+        })
+    }
+
+    /// Parse a single row, taking into account all the format settings in `config`. Still
+    /// SPICY HOT for oddball inputs, but it's not worth making a separately optimized version for
+    /// every combination of settings.
+    fn parse_with(s: &'a BStr, config: &Config) -> Option<Self> {
+        if config.sample.is_some_and(|sample| !sample.includes(s)) {
+            return None;
+        }
+        let mut s: &[u8] = s;
+        if let Some(window) = config.time_window {
+            // the timestamp is cheap to check, so skip rows outside the window before the rest
+            let timestamp;
+            (timestamp, s) = config.split_timestamp(s)?;
+            if !window.contains(Self::parse_timestamp(timestamp)?) {
+                return None;
+            }
+        }
+        let (city, temp_s, weight_s) = config.split_fields(s)?;
+        let city = config.group_by.key(city);
+        if config.skip_empty_city && city.is_empty() {
+            return None;
+        }
+        let weight = if config.weighted {
+            Self::parse_weight(weight_s)?
+        } else {
+            1
+        };
+        let temp = if config.int_degrees {
+            Temperature::parse_int_degrees(temp_s)
+        } else if config.allow_integer {
+            Temperature::parse_allow_integer_decimal(temp_s, config.decimal.separator())
+        } else {
+            Temperature::parse(temp_s)
+        };
+        if !config.includes_temp(temp) {
+            return None;
+        }
+        Some(Self {
+            city: BStr::new(city),
+            temp,
+            weight,
+        })
+    }
+
+    /// Parse and validate a single row for `--strict` mode. Blank lines are skipped, anything
+    /// else must be a city and a valid temperature.
+    fn parse_strict(s: &'a BStr, config: &Config) -> Result<Option<Self>, ParseError> {
+        // blank, or nothing left after the suffix is dropped
+        if s.len() <= usize::from(config.line_suffix_bytes) {
+            return Ok(None);
+        }
+        let err = |reason| ParseError {
+            line: s.into(),
+            reason,
+        };
+        let (timestamp, rest) = match config.time_window {
+            Some(_) => {
+                let (timestamp, rest) = config
+                    .split_timestamp(s)
+                    .ok_or_else(|| err("missing ';'"))?;
+                let timestamp =
+                    Self::parse_timestamp(timestamp).ok_or_else(|| err("invalid timestamp"))?;
+                (Some(timestamp), rest)
+            }
+            None => (None, s.as_bytes()),
+        };
+        let (city, temp_s, weight_s) = config
+            .split_fields(rest)
+            .ok_or_else(|| err("missing ';'"))?;
+        // Only the city can have the delimiter in it, and only when splitting at the last one, so
+        // anything else is an empty or extra field.
+        let has_delimiter = |field: &[u8]| field.iter().any(|b| config.is_delimiter(*b));
+        let has_delimiter = has_delimiter(temp_s) || (!config.split_last && has_delimiter(city));
+        if has_delimiter && !config.quoted {
+            return Err(err("wrong number of fields"));
+        }
+        if city.is_empty() {
+            return Err(err("empty city"));
+        }
+        let city = config.group_by.key(city);
+        let temp = if config.int_degrees {
+            Temperature::parse_strict_int_degrees(temp_s)
+        } else {
+            Temperature::parse_strict_decimal(temp_s, config.decimal.separator())
+        }
+        .map_err(err)?;
+        let weight = if config.weighted {
+            Self::parse_weight(weight_s).ok_or_else(|| err("invalid weight"))?
+        } else {
+            1
+        };
+        // every line still gets validated, even ones left out of the sample or temperature range
+        if config.sample.is_some_and(|sample| !sample.includes(s))
+            || !config.includes_temp(temp)
+            || config
+                .time_window
+                .zip(timestamp)
+                .is_some_and(|(window, timestamp)| !window.contains(timestamp))
+        {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            city: BStr::new(city),
+            temp,
+            weight,
+        }))
+    }
+
+    /// Parse a row's weight, which has to be a positive whole number. A weight of 0 would leave a
+    /// city with no observations and no mean.
+    fn parse_weight(s: &[u8]) -> Option<u32> {
+        if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        s.to_str().ok()?.parse().ok().filter(|weight| *weight > 0)
+    }
+
+    /// Parse a row's timestamp, which has to be a whole number of seconds. Huge ones saturate,
+    /// which puts them outside any `TimeWindow`.
+    fn parse_timestamp(s: &[u8]) -> Option<u64> {
+        if s.is_empty() {
+            return None;
+        }
+        s.iter().try_fold(0u64, |timestamp, b| {
+            b.is_ascii_digit().then(|| {
+                timestamp
+                    .saturating_mul(10)
+                    .saturating_add(u64::from(b - b'0'))
+            })
+        })
+    }
+}
+
+/// Hooks for the fuzz targets in `fuzz/`, which need to get at the private row parsers. Only
+/// built with `--cfg fuzzing`, which cargo-fuzz sets.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing {
+    use super::*;
+
+    /// `Row::parse`, returning the city and temperature
+    pub fn parse_row(line: &[u8]) -> Option<(&[u8], Temperature)> {
+        Row::parse(line.as_bstr()).map(|row| (row.city.as_bytes(), row.temp))
+    }
+
+    /// `Row::parse_with`, returning the city and temperature
+    pub fn parse_row_with<'a>(line: &'a [u8], config: &Config) -> Option<(&'a [u8], Temperature)> {
+        Row::parse_with(line.as_bstr(), config).map(|row| (row.city.as_bytes(), row.temp))
+    }
+
+    /// `Row::parse_strict`, returning the city and temperature
+    pub fn parse_row_strict<'a>(
+        line: &'a [u8],
+        config: &Config,
+    ) -> Result<Option<(&'a [u8], Temperature)>, ParseError> {
+        Row::parse_strict(line.as_bstr(), config)
+            .map(|row| row.map(|row| (row.city.as_bytes(), row.temp)))
+    }
+}
+
+/// An invalid line found in `--strict` mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// the full contents of the bad line
+    pub line: BString,
+    /// what's wrong with it
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid line '{}': {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Errors from `process_data`, which only happen with `strict` or `safe` enabled in the config,
+/// and from reading the input with `process_reader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// an invalid line, in strict mode
+    Parse(ParseError),
+    /// a city's total or count overflowed, in safe mode
+    Overflow {
+        /// the city that overflowed
+        city: BString,
+    },
+    /// failed to read the input, with the error message
+    Io(String),
+    /// the input is compressed, which can't be read
+    Compressed(Compression),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(err) => err.fmt(f),
+            Self::Overflow { city } => write!(f, "too many measurements for city '{city}'"),
+            Self::Io(msg) => write!(f, "failed to read input: {msg}"),
+            Self::Compressed(compression) => {
+                write!(
+                    f,
+                    "input is {compression} compressed, which isn't supported"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// Which order the fields in each line come in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FieldOrder {
+    /// `city;temp`, like the challenge specifies
+    #[default]
+    CityTemp,
+    /// `temp;city`
+    TempCity,
+}
+
+impl std::str::FromStr for FieldOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "city-temp" => Ok(Self::CityTemp),
+            "temp-city" => Ok(Self::TempCity),
+            _ => Err(format!("invalid field order '{s}'")),
+        }
+    }
+}
+
+/// Which character separates the whole and fractional parts of temperatures
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Decimal {
+    /// `12.3`
+    #[default]
+    Point,
+    /// `12,3`, as used in much of Europe
+    Comma,
+}
+
+impl Decimal {
+    fn separator(self) -> u8 {
+        match self {
+            Self::Point => b'.',
+            Self::Comma => b',',
+        }
+    }
+}
+
+impl std::str::FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "point" => Ok(Self::Point),
+            "comma" => Ok(Self::Comma),
+            _ => Err(format!("invalid decimal separator '{s}'")),
+        }
+    }
+}
+
+/// Which lines to aggregate when only looking at a random sample of the input. Lines are picked
+/// by a seeded hash of their contents rather than an RNG, so the same input, fraction, and seed
+/// always give the same results no matter how the work gets split between threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Lines whose hash (the top 32 bits of it) is at most this are included. Never zero, since
+    /// that would be a fraction of 0, so the niche keeps Option<Sample> (and Config) smaller.
+    threshold: NonZeroU32,
+    /// Only 32 bits, also to keep Config small. Longer seeds are folded down to this.
+    seed: u32,
+}
+
+impl Sample {
+    /// Sample about `fraction` of all lines, which must be more than 0 and at most 1. A fraction
+    /// of 1 includes every line.
+    pub fn new(fraction: f64, seed: u64) -> Option<Self> {
+        (fraction > 0.0 && fraction <= 1.0).then_some(Self {
+            // float to int casts saturate, so 1.0 is u32::MAX. A fraction too small to show up
+            // at all still gets the smallest possible sample.
+            threshold: NonZeroU32::new((fraction * u32::MAX as f64) as u32)
+                .unwrap_or(NonZeroU32::MIN),
+            seed: (seed ^ (seed >> 32)) as u32,
+        })
+    }
+
+    /// whether `line` is part of the sample
+    fn includes(&self, line: &[u8]) -> bool {
+        // FNV-1a, then the splitmix64 finalizer so that similar lines get unrelated hashes
+        let mut hash = 0xcbf29ce484222325 ^ u64::from(self.seed);
+        for b in line {
+            hash = (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3);
+        }
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+        hash ^= hash >> 31;
+        (hash >> 32) as u32 <= self.threshold.get()
+    }
+}
+
+/// Only aggregate rows whose timestamp is in this range, for input with a leading timestamp
+/// field like `1700000000;Paris;12.3`. Timestamps are whole seconds since the Unix epoch, up to
+/// `u32::MAX` (in 2106).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    since: u32,
+    /// Exclusive, so never zero, which gives Option<TimeWindow> a niche like `Sample`'s threshold
+    until: NonZeroU32,
+}
+
+impl TimeWindow {
+    /// Rows from `since` (inclusive, or from the start of time if None) until `until`
+    /// (exclusive, or until `u32::MAX` if None). None if that's empty.
+    pub fn new(since: Option<u32>, until: Option<u32>) -> Option<Self> {
+        let since = since.unwrap_or(0);
+        let until = NonZeroU32::new(until.unwrap_or(u32::MAX))?;
+        (since < until.get()).then_some(Self { since, until })
+    }
+
+    /// whether a row with this timestamp should be aggregated
+    fn contains(&self, timestamp: u64) -> bool {
+        (u64::from(self.since)..u64::from(self.until.get())).contains(&timestamp)
+    }
+}
+
+/// What to aggregate rows by. Rather than every city separately, they can be rolled up into
+/// coarser groups derived from the name, which are then reported as if they were cities.
+/// Working out a row's group only slices its city name, so it doesn't allocate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    /// the whole city name
+    #[default]
+    City,
+    /// The first this many bytes of the city name, or all of it if it's shorter. A multi-byte
+    /// UTF-8 character that straddles the end is kept whole.
+    Prefix(NonZeroU8),
+    /// everything before the first occurrence of this byte, or all of the city name if there
+    /// isn't one, e.g. `Europe` for `Europe/Paris` with `/`
+    Before(u8),
+}
+
+impl GroupBy {
+    /// the group that `city` belongs to
+    ///
+    /// ```
+    /// use onebrc::GroupBy;
+    /// use std::num::NonZeroU8;
+    ///
+    /// let prefix = GroupBy::Prefix(NonZeroU8::new(2).unwrap());
+    /// assert_eq!(prefix.key(b"Paris"), b"Pa");
+    /// assert_eq!(prefix.key(b"P"), b"P");
+    /// assert_eq!(GroupBy::Before(b'/').key(b"Europe/Paris"), b"Europe");
+    /// assert_eq!(GroupBy::Before(b'/').key(b"Paris"), b"Paris");
+    /// ```
+    pub fn key(self, city: &[u8]) -> &[u8] {
+        match self {
+            Self::City => city,
+            Self::Prefix(len) => {
+                let mut end = usize::from(len.get()).min(city.len());
+                // UTF-8 continuation bytes are all 0b10xxxxxx
+                while city.get(end).is_some_and(|b| b & 0xc0 == 0x80) {
+                    end += 1;
+                }
+                &city[..end]
+            }
+            Self::Before(sep) => match city.iter().position(|b| *b == sep) {
+                Some(end) => &city[..end],
+                None => city,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid grouping '{s}'");
+        match s.split_once(':') {
+            None if s == "city" => Ok(Self::City),
+            Some(("prefix", len)) => len.parse().map(Self::Prefix).map_err(|_| err()),
+            Some(("before", sep)) if sep.len() == 1 => Ok(Self::Before(sep.as_bytes()[0])),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// How to divide up the input between threads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Let rayon split the input line by line however it likes. Fastest, but creates a huge
+    /// number of intermediate results maps.
+    #[default]
+    Lines,
+    /// Split the input into a few big chunks of lines up front, and process each chunk serially.
+    Chunked,
+    /// Like `Chunked`, but with a thread pinned to each CPU, and each NUMA node's threads
+    /// working through one contiguous region of the input, see `numa::process_numa`. For
+    /// multi-socket machines where memory bandwidth is the limit. Linux only, elsewhere (and
+    /// without rayon) this is the same as `Chunked`.
+    Numa,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(Self::Lines),
+            "chunked" => Ok(Self::Chunked),
+            "numa" => Ok(Self::Numa),
+            _ => Err(format!("invalid strategy '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lines => "lines",
+            Self::Chunked => "chunked",
+            Self::Numa => "numa",
+        })
+    }
+}
+
+/// Settings which control what gets collected during aggregation.
+///
+/// Every `ResultsMap` carries a copy of this, and the lines strategy makes thousands of them, so
+/// keep it within 64 bytes: going over made the default path about 15% slower. That's why
+/// `min_chunk_bytes` and `Sample`'s threshold are NonZero, their niches save the Options' tags,
+/// and why `line_suffix_bytes`, `expected_cities`, `max_cities`, `min_chunk_bytes` and `Sample`'s
+/// and `TimeWindow`'s fields are smaller than a usize. Going to 80 bytes still made it 45% slower
+/// (1.25s vs 0.86s median on 20M rows).
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// keep a full histogram for every city (needed for the mode)
+    pub histogram: bool,
+    /// which order the city and temperature come in on each line
+    pub order: FieldOrder,
+    /// How to parallelize the work. The lines strategy is turned into chunked if `histogram`,
+    /// `intern`, `expected_cities`, or `quantile` is set.
+    pub strategy: Strategy,
+    /// Treat numbers without a decimal point as whole degrees rather than tenths. Can't be
+    /// combined with `strict`, which rejects them.
+    pub allow_integer: bool,
+    /// Validate every line and stop at the first invalid one. That includes lines with an empty
+    /// or extra field, like `Paris;;12.3` or `Paris;12.3;extra`. Without this, those aren't
+    /// detected: the temperature is read from whatever digits come after the first delimiter,
+    /// which happens to be 12.3 for both of those.
+    pub strict: bool,
+    /// Do everything on the calling thread, even when built with rayon. Mostly useful for
+    /// checking whether a problem has anything to do with parallelism.
+    pub single_threaded: bool,
+    /// Check every city's total and count for overflow, and stop with an error rather than
+    /// silently wrapping. Slower, since it can't use the fully inlined hot loop.
+    pub safe: bool,
+    /// Decimal separator in temperatures. The fast parser doesn't care which one is used, this
+    /// only matters with `strict` or `allow_integer`. A comma is only ambiguous if it's also the
+    /// `delimiter`.
+    pub decimal: Decimal,
+    /// Split fields at the last delimiter in each line rather than the first, for city names
+    /// which contain one themselves.
+    pub split_last: bool,
+    /// byte between the city and temperature fields, ';' by default
+    pub delimiter: u8,
+    /// Split fields at runs of ASCII whitespace instead of `delimiter`, for whitespace-aligned
+    /// input like `Paris    12.3`. Leading and trailing whitespace on a line is ignored. City
+    /// names can't have any whitespace in them, since the first space would end the name (or
+    /// with `split_last`, the last one would start the temperature).
+    pub whitespace: bool,
+    /// Drop this many bytes from the end of every line (after the '\n' is removed) before
+    /// parsing it, e.g. 1 for CRLF line endings. Lines shorter than this are skipped.
+    pub line_suffix_bytes: u8,
+    /// Only aggregate a random sample of lines, for a quick approximate look at a huge input.
+    /// Counts are for the sampled lines only, not scaled up. The mean is a fair estimate, but
+    /// the sample will usually miss the most extreme readings, so min and max are only bounds:
+    /// the real min is at most the sampled one, and the real max at least the sampled one.
+    pub sample: Option<Sample>,
+    /// With the lines strategy, split the input into pieces of at least this many bytes (snapped
+    /// to line boundaries) rather than letting `par_split` divide it line by line. Each piece
+    /// gets its own `ResultsMap`, so this caps how many maps get made and merged. None keeps
+    /// rayon's own splitting. Does nothing without rayon.
+    ///
+    /// Around 1MiB is a good place to start: on a 50k city input that cut a third off the runtime,
+    /// with no difference either way for the usual ~400 cities.
+    pub min_chunk_bytes: Option<NonZeroU32>,
+    /// Also collect a histogram of every temperature regardless of city, see
+    /// `ResultsMap::take_global_histogram`.
+    pub global_histogram: bool,
+    /// Only aggregate rows whose temperature is at least this, e.g. to get stats for just the
+    /// hot days. `Temperature::MIN` (the default) includes everything.
+    pub min_temp: Temperature,
+    /// Only aggregate rows whose temperature is at most this. `Temperature::MAX` (the default)
+    /// includes everything.
+    pub max_temp: Temperature,
+    /// Aggregate each chunk with its city names interned into small integer ids, see
+    /// `InternedResults`. Only worth it with many thousands of cities.
+    pub intern: bool,
+    /// Byte at the end of each record, '\n' by default. Something like NUL lets city names
+    /// contain newlines.
+    pub record_sep: u8,
+    /// How many cities to make room for up front in each map that rows get aggregated into, so
+    /// that they don't have to grow (and rehash everything) along the way. 0 starts them empty.
+    pub expected_cities: u32,
+    /// Every line has a third field after the temperature, a positive whole number of
+    /// observations the row stands for, like `Paris;12.3;5`. The mean and count are weighted by
+    /// it (as are histograms), min and max are still just the extremes. A single row can stand
+    /// for billions of observations, so this implies `safe`.
+    pub weighted: bool,
+    /// Temperatures are all whole degrees with no decimal point, like `Paris;12` for 12.0. Unlike
+    /// `allow_integer`, which still reads `12.3` as 12.3, there are no tenths at all.
+    pub int_degrees: bool,
+    /// Read the temperature from this field (counting from 1, where the city is field 1 unless
+    /// `city_field` says otherwise) and ignore any others, for lines with extra columns like
+    /// `city;temp;station;flags`. Has to be a different field from the city's, and `order` and
+    /// `split_last` don't apply. None means the temperature is everything after the city's
+    /// delimiter, which is the last field of a normal line.
+    pub temp_field: Option<NonZeroU8>,
+    /// Read the city from this field rather than the first, counting from 1. Only used along
+    /// with `temp_field`, see `csv_columns` to find both from a CSV header.
+    pub city_field: Option<NonZeroU8>,
+    /// With `temp_field`, fields can be in double quotes like CSV, so that they can have the
+    /// delimiter in them (but not the record separator). The quotes are taken off, but a `""`
+    /// inside a city name is left as it is rather than turned into one `"`, since the name is
+    /// borrowed straight out of the input.
+    pub quoted: bool,
+    /// Also sum up every city's temperatures as f64 degrees, and work out the mean from that
+    /// rather than the exact integer total. That's what reference implementations which use
+    /// doubles do, and over millions of rows their rounding error can tip a mean that's close to
+    /// a half tenth the other way, so this is for matching their output exactly. f64 addition
+    /// depends on the order it's done in, so this implies `single_threaded`.
+    pub float_accum: bool,
+    /// Keep track of every city's first and last temperatures in input order, see
+    /// `FinalStats::first`. Those are only exact with `single_threaded`.
+    pub endpoints: bool,
+    /// Round means that are exactly halfway between two tenths up towards +∞, like Java's
+    /// `Math.round` in the official 1BRC, rather than away from zero. Only negative means are
+    /// any different, like -1.25 which comes out as -1.2 rather than -1.3. Along with
+    /// `float_accum`, this is exactly what the reference implementation does. It doesn't apply
+    /// to `FinalStats::precise_mean`. For output in another unit, pass `Rounding::HalfUp` to
+    /// `FinalStats::to_unit_rounded` to match.
+    pub reference_rounding: bool,
+    /// Skip rows with an empty city name, like `;12.3`, rather than aggregating them as a city
+    /// called "". Strict mode always treats them as invalid.
+    pub skip_empty_city: bool,
+    /// Keep separate stats for at most this many cities, and lump any others together under
+    /// `OTHER_CITY`, to bound memory on input with far more cities than expected. Which cities get
+    /// their own stats depends on the order they're seen in, so this implies `single_threaded`
+    /// (and turns off `intern`), and then it's the first ones in the input. A real city called
+    /// `(other)` ends up in the same bucket. Only a u16 to keep this struct small.
+    pub max_cities: Option<NonZeroU16>,
+    /// Every line starts with a timestamp field, like `1700000000;Paris;12.3`, and only rows
+    /// within this window are aggregated. The other fields (and `temp_field`) are counted from
+    /// after the timestamp.
+    pub time_window: Option<TimeWindow>,
+    /// Aggregate rows by a group derived from each city name, like its first few bytes, rather
+    /// than by the city itself. Everything downstream sees the group names as city names.
+    pub group_by: GroupBy,
+    /// Keep a t-digest of every city's temperatures, and estimate this quantile from it, see
+    /// `FinalStats::quantile`. Like histograms, the digests are too big to have one per city in
+    /// every map of the lines strategy. The estimate depends on the order the digests are merged
+    /// in, so this implies `single_threaded`.
+    #[cfg(feature = "tdigest")]
+    pub quantile: Option<Quantile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            histogram: false,
+            order: FieldOrder::default(),
+            strategy: Strategy::default(),
+            allow_integer: false,
+            strict: false,
+            single_threaded: false,
+            safe: false,
+            decimal: Decimal::default(),
+            split_last: false,
+            delimiter: b';',
+            whitespace: false,
+            line_suffix_bytes: 0,
+            sample: None,
+            min_chunk_bytes: None,
+            global_histogram: false,
+            min_temp: Temperature::MIN,
+            max_temp: Temperature::MAX,
+            intern: false,
+            record_sep: b'\n',
+            expected_cities: 0,
+            weighted: false,
+            int_degrees: false,
+            temp_field: None,
+            city_field: None,
+            quoted: false,
+            float_accum: false,
+            endpoints: false,
+            reference_rounding: false,
+            skip_empty_city: false,
+            max_cities: None,
+            time_window: None,
+            group_by: GroupBy::City,
+            #[cfg(feature = "tdigest")]
+            quantile: None,
+        }
+    }
+}
+
+/// Type of each city's running total (in tenths) and count. The defaults are plenty for any
+/// realistic input, and going wider slows down the hot loop, but the `wide-total` feature makes
+/// overflow practically impossible for inputs with trillions of rows.
+#[cfg(not(feature = "wide-total"))]
+type Total = i64;
+#[cfg(not(feature = "wide-total"))]
+type Count = u32;
+#[cfg(feature = "wide-total")]
+type Total = i128;
+#[cfg(feature = "wide-total")]
+type Count = u64;
+
+/// Running statistics for one city
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// sum of all temperatures, in tenths
+    total: Total,
+    count: Count,
+    min: Temperature,
+    max: Temperature,
+    /// when this city was first seen, relative to the others in the same `ResultsMap`, see
+    /// `summarize_as_seen`
+    first_seen: u32,
+    /// only with `Config::float_accum` or `Config::endpoints`, boxed so that it's just a null
+    /// pointer otherwise
+    ordered: Option<Box<OrderedStats>>,
+    hist: Option<Histogram>,
+    #[cfg(feature = "tdigest")]
+    digest: Option<Box<TDigest>>,
+}
+
+/// The parts of `Stats` that depend on the order rows are seen in. They're only kept when
+/// they're wanted, so that the normal hot loop doesn't pay for them.
+#[derive(Debug, Clone)]
+struct OrderedStats {
+    /// sum of all temperatures as f64 degrees, for `Config::float_accum`
+    float_total: f64,
+    /// the first and last temperatures in input order, for `Config::endpoints`
+    first: Temperature,
+    last: Temperature,
+}
+
+/// Final statistics for one city
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinalStats {
+    mean: Temperature,
+    min: Temperature,
+    max: Temperature,
+    mode: Option<Temperature>,
+    quantile: Option<Temperature>,
+    first: Option<Temperature>,
+    last: Option<Temperature>,
+    total: Total,
+    count: Count,
+}
+
+impl fmt::Display for FinalStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.min, self.mean, self.max)?;
+        if let Some(mode) = self.mode {
+            write!(f, "/{mode}")?;
+        }
+        if let Some(quantile) = self.quantile {
+            write!(f, "/{quantile}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FinalStats {
+    /// Make final stats directly, for testing code that uses them without aggregating any rows.
+    /// There's no mode or quantile, and the total is worked out from the mean, so
+    /// `precise_mean` is only as precise as `mean`. Needs `min <= max` and a nonzero `count`,
+    /// which is only checked in debug builds. Without the `wide-total` feature, `count`
+    /// saturates at `u32::MAX`.
+    ///
+    /// ```
+    /// use onebrc::{FinalStats, Temperature};
+    ///
+    /// let t = Temperature::from_tenths;
+    /// let stats = FinalStats::new(t(-31), t(52), t(123), 4);
+    /// assert_eq!(stats.to_string(), "-3.1/5.2/12.3");
+    /// assert_eq!(stats.count(), 4);
+    ///
+    /// // min above max
+    /// let bad = std::panic::catch_unwind(|| FinalStats::new(t(123), t(52), t(-31), 4));
+    /// assert_eq!(bad.is_err(), cfg!(debug_assertions));
+    /// ```
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn new(min: Temperature, mean: Temperature, max: Temperature, count: u64) -> Self {
+        debug_assert!(min <= max, "min {min} is above max {max}");
+        debug_assert!(count > 0, "no measurements");
+        let count = Count::try_from(count).unwrap_or(Count::MAX);
+        Self {
+            mean,
+            min,
+            max,
+            mode: None,
+            quantile: None,
+            first: None,
+            last: None,
+            total: mean.to_tenths() as Total * count as Total,
+            count,
+        }
+    }
+
+    pub fn min(&self) -> Temperature {
+        self.min
+    }
+
+    pub fn mean(&self) -> Temperature {
+        self.mean
+    }
+
+    pub fn max(&self) -> Temperature {
+        self.max
+    }
+
+    /// the most common temperature, if a histogram was kept
+    pub fn mode(&self) -> Option<Temperature> {
+        self.mode
+    }
+
+    /// The estimate of `Config::quantile`, if the `tdigest` feature is enabled and it was set.
+    /// It's usually within 0.1% of the true quantile by rank, rounded to a tenth.
+    pub fn quantile(&self) -> Option<Temperature> {
+        self.quantile
+    }
+
+    /// The first temperature in input order, if `Config::endpoints` was set. That's only exact
+    /// with `Config::single_threaded`, otherwise it depends on how the input got split up between
+    /// threads. None for stats which didn't come from aggregating rows, like from
+    /// `FinalStats::new`, `Stats::from_parts` or `read_binary`.
+    pub fn first(&self) -> Option<Temperature> {
+        self.first
+    }
+
+    /// The last temperature in input order, with the same caveats as `first`
+    pub fn last(&self) -> Option<Temperature> {
+        self.last
+    }
+
+    /// how many measurements there were
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn count(&self) -> u64 {
+        self.count as u64
+    }
+
+    /// The mean in degrees Celsius as an f64, worked out from the exact total and count with no
+    /// rounding to tenths, for seeing how close `mean` was to rounding the other way. That's
+    /// only as precise as an f64 division, so it's for looking at rather than for rounding
+    /// again, which is what `precise_mean` is for.
+    ///
+    /// ```
+    /// use onebrc::{Config, Stats, Temperature};
+    ///
+    /// let t = Temperature::from_tenths;
+    /// // 12.3, 10.0, 10.1, and 10.1
+    /// let stats = Stats::from_parts(425, 4, t(100), t(123)).finalize(Config::default());
+    /// assert_eq!(stats.mean(), t(106));
+    /// assert_eq!(stats.exact_mean(), 10.625);
+    /// ```
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn exact_mean(&self) -> f64 {
+        self.total as f64 / self.count as f64 / 10.0
+    }
+
+    /// Sanity check that there's at least one measurement and `min <= mean <= max`, which could
+    /// only fail if something went badly wrong, like the total overflowing.
+    pub fn self_check(&self) -> Result<(), &'static str> {
+        if self.count == 0 {
+            Err("no measurements")
+        } else if self.mean < self.min {
+            Err("mean is below min")
+        } else if self.mean > self.max {
+            Err("mean is above max")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Convert min/mean/max/mode/first/last from Celsius to `unit`. The raw total isn't
+    /// converted, so call `precise_mean` on the original stats instead.
+    pub fn to_unit(self, unit: Unit) -> Self {
+        self.to_unit_rounded(unit, Rounding::default())
+    }
+
+    /// `to_unit`, with every temperature rounded to tenths in `unit` according to `rounding`.
+    /// The mean is worked out from the total in `unit`, so that it's only rounded the once too,
+    /// rather than rounded in Celsius and then again after converting. Celsius stats are
+    /// returned as they are, since they're already rounded (see `Config::reference_rounding`).
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn to_unit_rounded(self, unit: Unit, rounding: Rounding) -> Self {
+        if unit == Unit::Celsius {
+            return self;
+        }
+        let convert = |temp| unit.convert_rounded(temp, rounding);
+        let (a, b, c) = unit.coefficients();
+        let (total, count) = (self.total as i128, self.count as u64);
+        let mean = if count == 0 {
+            convert(self.mean)
+        } else {
+            rounding.divide(
+                i128::from(a) * total + i128::from(b) * i128::from(count),
+                c as u64 * count,
+            )
+        };
+        Self {
+            mean,
+            min: convert(self.min),
+            max: convert(self.max),
+            mode: self.mode.map(convert),
+            quantile: self.quantile.map(convert),
+            first: self.first.map(convert),
+            last: self.last.map(convert),
+            ..self
+        }
+    }
+
+    /// The mean in `unit`, rounded to `decimals` digits after the decimal point. This is computed
+    /// exactly from the total and count, so unlike `mean` (and `to_unit`) there's no intermediate
+    /// rounding to tenths before unit conversion. With 1 decimal in Celsius it's the same as
+    /// `mean`.
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn precise_mean(&self, unit: Unit, decimals: u32) -> PreciseMean {
+        let (a, b, c) = unit.coefficients();
+        let total = self.total as i128;
+        let count = self.count as i128;
+        // mean in this unit = (a * total + b * count) / (c * count) tenths, shifted to the
+        // requested number of decimal places.
+        let num = (a as i128 * total + b as i128 * count) * 10i128.pow(decimals);
+        let den = c as i128 * count * 10;
+        // round half away from zero, same as Temperature's Div
+        let quotient = (num.abs() * 2 + den) / (den * 2);
+        PreciseMean {
+            value: if num < 0 { -quotient } else { quotient },
+            decimals,
+        }
+    }
+}
+
+/// A mean with an arbitrary number of decimal places, from `FinalStats::precise_mean`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreciseMean {
+    /// the mean multiplied by 10^decimals
+    value: i128,
+    decimals: u32,
+}
+
+impl fmt::Display for PreciseMean {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let scale = 10u128.pow(self.decimals);
+        let whole = self.value.unsigned_abs() / scale;
+        let frac = self.value.unsigned_abs() % scale;
+        write!(
+            f,
+            "{sign}{whole}.{frac:0width$}",
+            width = self.decimals as usize
+        )
+    }
+}
+
+impl Stats {
+    /// Make running stats directly from a total (in tenths of a degree) and count of
+    /// measurements and their min and max, for testing code that uses them without aggregating
+    /// any rows. Needs `min <= max` and a nonzero `count`, which is only checked in debug builds.
+    /// Without the `wide-total` feature, `total` and `count` wrap to fit in an i64 and u32 like
+    /// they would while aggregating. There are no first and last temperatures, even with
+    /// `Config::endpoints`.
+    ///
+    /// ```
+    /// use onebrc::{Config, Stats, Temperature};
+    ///
+    /// let t = Temperature::from_tenths;
+    /// let stats = Stats::from_parts(123 + 45, 2, t(45), t(123));
+    /// assert_eq!(stats.finalize(Config::default()).to_string(), "4.5/8.4/12.3");
+    ///
+    /// // no measurements
+    /// let bad = std::panic::catch_unwind(|| Stats::from_parts(0, 0, t(0), t(0)));
+    /// assert_eq!(bad.is_err(), cfg!(debug_assertions));
+    /// ```
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn from_parts(total: i128, count: u64, min: Temperature, max: Temperature) -> Self {
+        debug_assert!(min <= max, "min {min} is above max {max}");
+        debug_assert!(count > 0, "no measurements");
+        Self {
+            total: total as Total,
+            count: count as Count,
+            min,
+            max,
+            first_seen: 0,
+            ordered: None,
+            hist: None,
+            #[cfg(feature = "tdigest")]
+            digest: None,
+        }
+    }
+
+    fn new(temp: Temperature, weight: u32, first_seen: u32, config: Config) -> Self {
+        let hist = config.histogram.then(|| {
+            let mut hist = Histogram::new();
+            hist.record_weighted(temp, weight);
+            hist
+        });
+        Self {
+            total: temp.to_tenths() as Total * Total::from(weight),
+            count: Count::from(weight),
+            min: temp,
+            max: temp,
+            first_seen,
+            ordered: (config.float_accum || config.endpoints).then(|| {
+                Box::new(OrderedStats {
+                    float_total: temp.as_celsius_f64() * f64::from(weight),
+                    first: temp,
+                    last: temp,
+                })
+            }),
+            hist,
+            #[cfg(feature = "tdigest")]
+            digest: config.quantile.map(|_| {
+                let mut digest = Box::new(TDigest::new());
+                digest.record_weighted(temp, weight);
+                digest
+            }),
+        }
+    }
+
+    /// Work out the final stats, with the mean rounded however `config` says
+    // these casts are no-ops with the wide-total feature
+    #[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+    pub fn finalize(mut self, config: Config) -> FinalStats {
+        let mean = if config.float_accum {
+            // stats from from_parts don't have a float total, so make one from the exact total
+            let float_total = match &self.ordered {
+                Some(ordered) => ordered.float_total,
+                None => self.total as f64 / 10.0,
+            };
+            let scaled = float_total / self.count as f64 * 10.0;
+            // f64::round is half away from zero, the same as Temperature::mean
+            let tenths = if config.reference_rounding {
+                (scaled + 0.5).floor()
+            } else {
+                scaled.round()
+            };
+            Temperature::from_tenths(tenths as i32)
+        } else if config.reference_rounding {
+            Temperature::mean_half_up(self.total as i128, self.count as u64)
+        } else {
+            Temperature::mean(self.total as i128, self.count as u64)
+        };
+        let endpoints = self
+            .ordered
+            .as_deref()
+            .filter(|_| config.endpoints)
+            .map(|ordered| (ordered.first, ordered.last));
+        FinalStats {
+            mean,
+            min: self.min,
+            max: self.max,
+            mode: self.hist.as_ref().and_then(Histogram::mode),
+            quantile: self.quantile(config),
+            first: endpoints.map(|(first, _)| first),
+            last: endpoints.map(|(_, last)| last),
+            total: self.total,
+            count: self.count,
+        }
+    }
+
+    /// the estimate of `config.quantile` from the t-digest, if there is one
+    #[cfg(feature = "tdigest")]
+    fn quantile(&mut self, config: Config) -> Option<Temperature> {
+        let digest = self.digest.as_mut()?;
+        digest.quantile(config.quantile?)
+    }
+
+    #[cfg(not(feature = "tdigest"))]
+    fn quantile(&mut self, _config: Config) -> Option<Temperature> {
+        None
+    }
+
+    // The total and count wrap on overflow, which takes billions of rows for one city. Safe mode
+    // uses the checked_ versions of these instead.
+
+    fn update_row(&mut self, temp: Temperature, weight: u32) {
+        self.total = self
+            .total
+            .wrapping_add(temp.to_tenths() as Total * Total::from(weight));
+        self.count = self.count.wrapping_add(Count::from(weight));
+        if let Some(ordered) = &mut self.ordered {
+            ordered.float_total += temp.as_celsius_f64() * f64::from(weight);
+            ordered.last = temp;
+        }
+        if temp < self.min {
+            self.min = temp;
+        }
+        if temp > self.max {
+            self.max = temp;
+        }
+        if let Some(hist) = &mut self.hist {
+            hist.record_weighted(temp, weight);
+        }
+        #[cfg(feature = "tdigest")]
+        if let Some(digest) = &mut self.digest {
+            digest.record_weighted(temp, weight);
+        }
+    }
+
+    fn update_stats(&mut self, other: Stats) {
+        self.total = self.total.wrapping_add(other.total);
+        self.count = self.count.wrapping_add(other.count);
+        if let (Some(ordered), Some(other_ordered)) = (&mut self.ordered, &other.ordered) {
+            ordered.float_total += other_ordered.float_total;
+            // other's rows come after self's
+            ordered.last = other_ordered.last;
+        }
+        if other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+        if let (Some(hist), Some(other_hist)) = (&mut self.hist, &other.hist) {
+            hist.merge(other_hist);
+        }
+        #[cfg(feature = "tdigest")]
+        if let (Some(digest), Some(other_digest)) = (&mut self.digest, &other.digest) {
+            digest.merge(other_digest);
+        }
+    }
+
+    /// `update_row`, unless the total or count would overflow. Returns whether it was updated.
+    fn checked_update_row(&mut self, temp: Temperature, weight: u32) -> bool {
+        let ok = self
+            .total
+            .checked_add(temp.to_tenths() as Total * Total::from(weight))
+            .is_some()
+            && self.count.checked_add(Count::from(weight)).is_some()
+            && self
+                .hist
+                .as_ref()
+                .is_none_or(|hist| hist.can_record(temp, weight));
+        if ok {
+            self.update_row(temp, weight);
+        }
+        ok
+    }
+
+    /// `update_stats`, unless the total or count would overflow. Returns whether it was updated.
+    fn checked_update_stats(&mut self, other: Stats) -> bool {
+        let ok = self.total.checked_add(other.total).is_some()
+            && self.count.checked_add(other.count).is_some()
+            && match (&self.hist, &other.hist) {
+                (Some(hist), Some(other_hist)) => hist.can_merge(other_hist),
+                _ => true,
+            };
+        if ok {
+            self.update_stats(other);
+        }
+        ok
+    }
+}
+
+/// Where rows for cities beyond `Config::max_cities` end up
+pub const OTHER_CITY: &str = "(other)";
+
+/// Aggregated stats for every city seen so far.
+///
+/// While processing a buffer, the keys are `&BStr` borrowed straight out of it, so that each new
+/// city doesn't cost an allocation in every one of the thousands of intermediate maps. Only the
+/// final merged results get owned keys.
+#[derive(Debug, Clone)]
+pub struct ResultsMap<K = BString> {
+    map: HashMap<K, Stats>,
+    config: Config,
+    /// every row's temperature regardless of city, only with `Config::global_histogram`
+    global: Option<GlobalHistogram>,
+}
+
+impl<K> Default for ResultsMap<K> {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl<K> ResultsMap<K> {
+    fn new(config: Config) -> Self {
+        Self {
+            map: HashMap::default(),
+            config,
+            global: config.global_histogram.then(GlobalHistogram::new),
+        }
+    }
+
+    /// how many cities there are
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// whether there are no cities
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// An empty map to ingest rows into, with room for `config.expected_cities`. Maps that are
+    /// only merged into use `new`, since they just take over the first map merged into them.
+    fn for_ingest(config: Config) -> Self {
+        Self {
+            map: HashMap::with_capacity(config.expected_cities as usize),
+            config,
+            global: config.global_histogram.then(GlobalHistogram::new),
+        }
+    }
+}
+
+impl<'a, K> ResultsMap<K>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr>,
+{
+    /// add a single row to these results
+    fn ingest(&mut self, row: Row<'a>) {
+        if let Some(global) = &mut self.global {
+            global.record_weighted(row.temp, row.weight);
+        }
+        if let Some(stats) = self.map.get_mut(row.city) {
+            stats.update_row(row.temp, row.weight);
+        } else if self.is_full() {
+            self.ingest_other(row);
+        } else {
+            let first_seen = self.map.len() as u32;
+            self.map.insert(
+                row.city.into(),
+                Stats::new(row.temp, row.weight, first_seen, self.config),
+            );
+        }
+    }
+
+    /// add a row for a city there's no room for to `OTHER_CITY`
+    #[cold]
+    fn ingest_other(&mut self, row: Row<'a>) {
+        let city = BStr::new(OTHER_CITY);
+        if let Some(stats) = self.map.get_mut(city) {
+            stats.update_row(row.temp, row.weight);
+        } else {
+            let first_seen = self.map.len() as u32;
+            self.map.insert(
+                city.into(),
+                Stats::new(row.temp, row.weight, first_seen, self.config),
+            );
+        }
+    }
+
+    /// `ingest` with overflow checking, for safe mode
+    fn try_ingest(&mut self, row: Row<'a>) -> Result<(), Error> {
+        if let Some(global) = &mut self.global {
+            global.record_weighted(row.temp, row.weight);
+        }
+        let city = if self.map.contains_key(row.city) || !self.is_full() {
+            row.city
+        } else {
+            BStr::new(OTHER_CITY)
+        };
+        if let Some(stats) = self.map.get_mut(city) {
+            if !stats.checked_update_row(row.temp, row.weight) {
+                return Err(Error::Overflow { city: city.into() });
+            }
+        } else {
+            let first_seen = self.map.len() as u32;
+            self.map.insert(
+                city.into(),
+                Stats::new(row.temp, row.weight, first_seen, self.config),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ResultsMap<&BStr> {
+    /// copy all the borrowed city names to get standalone results
+    fn into_owned(self) -> ResultsMap {
+        ResultsMap {
+            map: self
+                .map
+                .into_iter()
+                .map(|(city, stats)| (city.into(), stats))
+                .collect(),
+            config: self.config,
+            global: self.global,
+        }
+    }
+}
+
+impl ResultsMap {
+    /// Empty results with room for `capacity` cities, so that adding them with `ingest_raw`
+    /// never has to grow the map
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::for_ingest(Config {
+            expected_cities: capacity.try_into().unwrap_or(u32::MAX),
+            ..Config::default()
+        })
+    }
+
+    /// Get the histogram of all temperatures across every city, if `config.global_histogram` is
+    /// set. It's only there the first time.
+    pub fn take_global_histogram(&mut self) -> Option<GlobalHistogram> {
+        self.global.take()
+    }
+
+    /// Add a single measurement that's already been parsed, e.g. from a columnar format, without
+    /// going through any text parsing. The city is still grouped by `config.group_by`.
+    pub fn ingest_raw(&mut self, city: &[u8], temp: Temperature) {
+        self.ingest(Row {
+            city: self.config.group_by.key(city).as_bstr(),
+            temp,
+            weight: 1,
+        });
+    }
+
+    /// Collect already-parsed `(city, temperature)` pairs, with the default config.
+    ///
+    /// ```
+    /// use onebrc::{summarize, ResultsMap, Temperature};
+    ///
+    /// let rows: Vec<(&[u8], i32)> = vec![(b"Paris", 123), (b"Oslo", -30), (b"Paris", 151)];
+    /// let results = ResultsMap::from_rows(
+    ///     rows.iter()
+    ///         .map(|&(city, tenths)| (city, Temperature::from_tenths(tenths))),
+    /// );
+    ///
+    /// let summary = summarize(results);
+    /// assert_eq!(summary[0].0, "Oslo");
+    /// assert_eq!(summary[1].0, "Paris");
+    /// assert_eq!(summary[1].1.count(), 2);
+    /// assert_eq!(summary[1].1.to_string(), "12.3/13.7/15.1");
+    /// ```
+    pub fn from_rows<'a, I>(rows: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a [u8], Temperature)>,
+    {
+        let mut results = Self::default();
+        for (city, temp) in rows {
+            results.ingest_raw(city, temp);
+        }
+        results
+    }
+}
+
+impl<'a, K> ResultsMap<K>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr>,
+{
+    /// Whether there's no room for any more cities, with `config.max_cities`. `OTHER_CITY` itself
+    /// doesn't count, so there can be one more entry than the limit.
+    fn is_full(&self) -> bool {
+        self.config.max_cities.is_some_and(|max| {
+            let max = usize::from(max.get());
+            let len = self.map.len();
+            len > max || (len == max && !self.map.contains_key(BStr::new(OTHER_CITY)))
+        })
+    }
+
+    /// `city` for a city that isn't in the map yet, or `OTHER_CITY` if there's no room for it
+    fn new_city_key(&self, city: K) -> K {
+        if self.is_full() {
+            BStr::new(OTHER_CITY).into()
+        } else {
+            city
+        }
+    }
+
+    /// A `Stats::first_seen` after every city already in the map. This isn't kept in the map
+    /// itself since it only matters when merging in new cities, which is rare once the maps
+    /// have warmed up, and the maps get moved around for every row in the hot loop.
+    fn next_seen(&self) -> u32 {
+        self.map
+            .values()
+            .map(|stats| stats.first_seen.saturating_add(1))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Combine with all of `other`'s results. Cities that are new to this map are taken to be
+    /// seen after all of this map's, so merge in input order to keep `summarize_as_seen` right.
+    fn merge(&mut self, other: Self) {
+        // special case if we're merging into an empty map, we can just assume the other map
+        // in-place
+        if self.map.is_empty() {
+            *self = other;
+            return;
+        }
+        // an empty map hasn't seen any rows, so its histogram is empty too
+        if let (Some(global), Some(other_global)) = (&mut self.global, &other.global) {
+            global.merge(other_global);
+        }
+
+        let mut offset = None;
+        for (city, mut stats) in other {
+            if let Some(my_stats) = self.map.get_mut::<K>(&city) {
+                my_stats.update_stats(stats);
+                continue;
+            }
+            let city = self.new_city_key(city);
+            if let Some(my_stats) = self.map.get_mut::<K>(&city) {
+                my_stats.update_stats(stats);
+            } else {
+                let offset = *offset.get_or_insert_with(|| self.next_seen());
+                stats.first_seen = offset.saturating_add(stats.first_seen);
+                self.map.insert(city, stats);
+            }
+        }
+    }
+}
+
+impl<'a, K> ResultsMap<K>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr> + AsRef<[u8]>,
+{
+    /// `merge` with overflow checking, for safe mode
+    fn try_merge(mut self, other: Self) -> Result<Self, Error> {
+        if self.map.is_empty() {
+            return Ok(other);
+        }
+        if let (Some(global), Some(other_global)) = (&mut self.global, &other.global) {
+            global.merge(other_global);
+        }
+
+        let mut offset = None;
+        for (city, mut stats) in other {
+            let city = if self.map.contains_key::<K>(&city) {
+                city
+            } else {
+                self.new_city_key(city)
+            };
+            if let Some(my_stats) = self.map.get_mut::<K>(&city) {
+                if !my_stats.checked_update_stats(stats) {
+                    return Err(Error::Overflow {
+                        city: city.as_ref().into(),
+                    });
+                }
+            } else {
+                let offset = *offset.get_or_insert_with(|| self.next_seen());
+                stats.first_seen = offset.saturating_add(stats.first_seen);
+                self.map.insert(city, stats);
+            }
+        }
+        Ok(self)
+    }
+}
+
+impl Config {
+    /// whether the plain fast `Row::parse` can be used with these settings
+    fn is_default_format(&self) -> bool {
+        self.order == FieldOrder::CityTemp
+            && !self.allow_integer
+            && !self.int_degrees
+            && self.temp_field.is_none()
+            && !self.split_last
+            && self.delimiter == b';'
+            && !self.whitespace
+            && self.line_suffix_bytes == 0
+            && self.sample.is_none()
+            && self.time_window.is_none()
+            && !self.filters_temps()
+            && !self.weighted
+            && !self.skip_empty_city
+            && self.group_by == GroupBy::City
+    }
+
+    /// whether `min_temp` or `max_temp` leave out anything
+    fn filters_temps(&self) -> bool {
+        self.min_temp != Temperature::MIN || self.max_temp != Temperature::MAX
+    }
+
+    /// whether a row with this temperature should be aggregated, according to `min_temp` and
+    /// `max_temp`
+    fn includes_temp(&self, temp: Temperature) -> bool {
+        (self.min_temp..=self.max_temp).contains(&temp)
+    }
+
+    /// Split a line into (city, temperature, weight) fields, without the delimiters between them.
+    /// The weight is always the last field, and is empty unless `weighted` is set. None if there
+    /// aren't enough delimiters, or the line is shorter than `line_suffix_bytes`.
+    fn split_fields<'a>(&self, s: &'a [u8]) -> Option<(&'a [u8], &'a [u8], &'a [u8])> {
+        let mut s = &s[..s.len().checked_sub(usize::from(self.line_suffix_bytes))?];
+        if self.whitespace {
+            s = s.trim_ascii();
+        }
+        let mut weight: &[u8] = &[];
+        if self.weighted {
+            let pos = s.iter().rposition(|b| self.is_delimiter(*b))?;
+            (s, weight) = (self.trim_field(&s[..pos]), &s[pos + 1..]);
+        }
+        if let Some(field) = self.temp_field {
+            let city_index = self.city_field.map_or(0, |f| usize::from(f.get()) - 1);
+            let temp_index = usize::from(field.get()) - 1;
+            let (mut city, mut temp) = (None, None);
+            for (i, field) in self
+                .fields(s)
+                .enumerate()
+                .take(city_index.max(temp_index) + 1)
+            {
+                if i == city_index {
+                    city = Some(field);
+                } else if i == temp_index {
+                    temp = Some(field);
+                }
+            }
+            return Some((city?, temp?, weight));
+        }
+        let pos = if self.split_last {
+            s.iter().rposition(|b| self.is_delimiter(*b))?
+        } else {
+            s.iter().position(|b| self.is_delimiter(*b))?
+        };
+        let (first, second) = (self.trim_field(&s[..pos]), self.trim_field(&s[pos + 1..]));
+        Some(match self.order {
+            FieldOrder::CityTemp => (first, second, weight),
+            FieldOrder::TempCity => (second, first, weight),
+        })
+    }
+
+    /// Split the timestamp field off the start of a line for `time_window`, returning it and the
+    /// rest of the line. None if there's no delimiter.
+    fn split_timestamp<'a>(&self, s: &'a [u8]) -> Option<(&'a [u8], &'a [u8])> {
+        let s = if self.whitespace {
+            s.trim_ascii_start()
+        } else {
+            s
+        };
+        let pos = s.iter().position(|b| self.is_delimiter(*b))?;
+        Some((&s[..pos], &s[pos + 1..]))
+    }
+
+    /// whether `b` separates fields
+    #[inline(always)]
+    fn is_delimiter(&self, b: u8) -> bool {
+        if self.whitespace {
+            b.is_ascii_whitespace()
+        } else {
+            b == self.delimiter
+        }
+    }
+
+    /// With `whitespace`, take the rest of a run of whitespace off either side of a field
+    fn trim_field<'a>(&self, field: &'a [u8]) -> &'a [u8] {
+        if self.whitespace {
+            field.trim_ascii()
+        } else {
+            field
+        }
+    }
+
+    /// split `line` at every delimiter, taking `quoted` into account
+    fn fields<'a>(&self, line: &'a [u8]) -> Fields<'a> {
+        Fields {
+            rest: Some(line),
+            delimiter: self.delimiter,
+            quoted: self.quoted,
+        }
+    }
+
+    /// Find the fields named `city` and `temp` in a CSV header line, for `city_field` and
+    /// `temp_field`. The header is split with this config's delimiter, and quoted names can
+    /// have anything in them.
+    ///
+    /// ```
+    /// use onebrc::Config;
+    ///
+    /// let config = Config {
+    ///     delimiter: b',',
+    ///     quoted: true,
+    ///     ..Config::default()
+    /// };
+    /// let (city, temp) = config
+    ///     .csv_columns(b"id,\"temp, C\",station", "station", "temp, C")
+    ///     .unwrap();
+    /// assert_eq!((city.get(), temp.get()), (3, 2));
+    /// ```
+    pub fn csv_columns(
+        &self,
+        header: &[u8],
+        city: &str,
+        temp: &str,
+    ) -> Result<(NonZeroU8, NonZeroU8), String> {
+        let header = &header[..header
+            .len()
+            .saturating_sub(usize::from(self.line_suffix_bytes))];
+        let names: Vec<BString> = self
+            .fields(header)
+            .map(|name| name.replace(b"\"\"", b"\"").into())
+            .collect();
+        let find = |name: &str| {
+            let pos = names
+                .iter()
+                .position(|n| n == name)
+                .ok_or_else(|| format!("no '{name}' column in the header"))?;
+            u8::try_from(pos + 1)
+                .ok()
+                .and_then(NonZeroU8::new)
+                .ok_or_else(|| format!("the '{name}' column is too far along the line"))
+        };
+        let (city_field, temp_field) = (find(city)?, find(temp)?);
+        if city_field == temp_field {
+            return Err("the city and temperature columns can't be the same".into());
+        }
+        Ok((city_field, temp_field))
+    }
+
+    /// Resolve any settings which imply others
+    fn normalize(mut self) -> Self {
+        // Histograms (and t-digests) are big, and we can't afford to have one per city in each of
+        // the thousands of maps that the lines strategy creates, and the same goes for maps with
+        // room for lots of cities already. Interning is done a chunk at a time, so that needs
+        // chunks too.
+        let needs_chunks = self.histogram || self.intern || self.expected_cities > 0;
+        #[cfg(feature = "tdigest")]
+        let needs_chunks = needs_chunks || self.quantile.is_some();
+        if needs_chunks && self.strategy == Strategy::Lines {
+            self.strategy = Strategy::Chunked;
+        }
+        // Everything else adds up the same whichever order rows and maps are merged in, but f64
+        // totals and t-digests don't, so those are done in input order on one thread to get the
+        // same result every time.
+        let ordered = self.float_accum || self.max_cities.is_some();
+        #[cfg(feature = "tdigest")]
+        let ordered = ordered || self.quantile.is_some();
+        if ordered {
+            self.single_threaded = true;
+        }
+        // with weights up to u32::MAX, the counts can overflow after a couple of rows rather than
+        // billions of them
+        if self.weighted {
+            self.safe = true;
+        }
+        // interning gives every city an id before anything gets merged
+        if self.max_cities.is_some() {
+            self.intern = false;
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.strategy == Strategy::Numa {
+            self.strategy = Strategy::Chunked;
+        }
+        self
+    }
+}
+
+/// The fields of one line, see `Config::fields`. With `quoted`, a field that starts with a `"`
+/// runs to the next `"` that isn't doubled, and comes out without the quotes. If there's no
+/// closing quote, it's the rest of the line.
+struct Fields<'a> {
+    /// None once the last field is done
+    rest: Option<&'a [u8]>,
+    delimiter: u8,
+    quoted: bool,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.rest?;
+        let (field, after) = match s.strip_prefix(b"\"") {
+            Some(inner) if self.quoted => {
+                let mut end = 0;
+                loop {
+                    match inner[end..].find_byte(b'"') {
+                        // a doubled quote is part of the field
+                        Some(pos) if inner.get(end + pos + 1) == Some(&b'"') => end += pos + 2,
+                        Some(pos) => break (&inner[..end + pos], &inner[end + pos + 1..]),
+                        None => break (inner, &[][..]),
+                    }
+                }
+            }
+            _ => match s.find_byte(self.delimiter) {
+                Some(pos) => (&s[..pos], &s[pos..]),
+                None => (s, &[][..]),
+            },
+        };
+        // skip anything between a closing quote and the delimiter
+        self.rest = after.find_byte(self.delimiter).map(|pos| &after[pos + 1..]);
+        Some(field)
+    }
+}
+
+/// Streaming interface to feed in lines one at a time, for when the input isn't available as
+/// one big buffer. Aggregation happens all on the current thread.
+///
+/// ```
+/// use bstr::BStr;
+/// use onebrc::Aggregator;
+///
+/// let mut agg = Aggregator::new();
+/// for line in ["Paris;12.3", "Oslo;-3.0", "Paris;15.1"] {
+///     agg.ingest_line(BStr::new(line));
+/// }
+///
+/// // results are sorted by city name
+/// let results = agg.finish();
+/// assert_eq!(results.len(), 2);
+/// assert_eq!(results[0].0, "Oslo");
+/// assert_eq!(results[0].1.to_string(), "-3.0/-3.0/-3.0");
+/// assert_eq!(results[1].0, "Paris");
+/// assert_eq!(results[1].1.to_string(), "12.3/13.7/15.1");
+/// ```
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    results: ResultsMap,
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make an aggregator with non-default settings. The strategy is ignored.
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            results: ResultsMap::for_ingest(config),
+        }
+    }
+
+    /// Parse and ingest a single line, which shouldn't include the trailing newline. Invalid
+    /// lines are skipped, even in strict mode.
+    pub fn ingest_line(&mut self, line: &BStr) {
+        let config = &self.results.config;
+        let row = if config.is_default_format() {
+            Row::parse(line)
+        } else {
+            Row::parse_with(line, config)
+        };
+        if let Some(row) = row {
+            self.results.ingest(row);
+        }
+    }
+
+    /// Finish up and get the final stats, sorted by city name
+    pub fn finish(self) -> Vec<(BString, FinalStats)> {
+        summarize(self.results)
+    }
+}
+
+impl<'a, K> std::ops::Add for ResultsMap<K>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr>,
+{
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self.merge(rhs);
+        self
+    }
+}
+
+impl<'a, K> std::iter::Sum for ResultsMap<K>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr>,
+{
+    fn sum<I: Iterator<Item = Self>>(mut iter: I) -> Self {
+        let first = match iter.next() {
+            Some(x) => x,
+            None => return Self::default(),
+        };
+        iter.fold(first, std::ops::Add::add)
+    }
+}
+
+impl<K> IntoIterator for ResultsMap<K> {
+    type Item = (K, Stats);
+    type IntoIter = <HashMap<K, Stats> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+/// Given a buffer containing input file contents (possibly mmap'd), collect all of the
+/// measurement results together. If `progress` is given, it's updated as each chunk of input is
+/// finished (which requires the chunked strategy).
+///
+/// Errors are only possible in strict or safe mode, otherwise invalid lines are silently skipped
+/// and overflows wrap.
+///
+/// The row parser is picked once up front here, and everything below is generic over it so that
+/// the default format gets its own fully inlined copy of the hot loop with no extra branching.
+pub fn process_data(
+    data: &[u8],
+    config: Config,
+    progress: Option<&Progress>,
+) -> Result<ResultsMap, Error> {
+    process_data_visit(data, config, progress, |_| ())
+}
+
+/// `process_data`, also calling `visit` with every row as it gets aggregated, for tapping into
+/// the parsed rows without a second pass. Rows which are skipped (invalid, left out of the sample
+/// or temperature range) aren't visited. The row's city name is only borrowed for the duration
+/// of the call, copy it to keep it.
+///
+/// With rayon, `visit` is called concurrently from all of rayon's worker threads, in no
+/// particular order, so it has to be `Sync` and anything it collects needs a `Mutex` or atomics.
+/// It's only called on the current thread without rayon or with `config.single_threaded`. Either
+/// way it's right in the hot loop, so keep it cheap. In strict or safe mode an error stops
+/// processing early, and `visit` may have already seen some rows from after the bad one.
+///
+/// ```
+/// use std::sync::atomic::{AtomicI64, Ordering};
+/// use onebrc::{process_data_visit, Config};
+///
+/// let hot = AtomicI64::new(0);
+/// let data = b"Paris;31.2\nOslo;-3.0\nParis;12.3\n";
+/// let results = process_data_visit(data, Config::default(), None, |row| {
+///     if row.temp().to_tenths() > 300 {
+///         hot.fetch_add(1, Ordering::Relaxed);
+///     }
+/// })
+/// .unwrap();
+/// assert_eq!(hot.into_inner(), 1);
+/// assert_eq!(results.into_iter().count(), 2);
+/// ```
+pub fn process_data_visit<V>(
+    data: &[u8],
+    config: Config,
+    progress: Option<&Progress>,
+    visit: V,
+) -> Result<ResultsMap, Error>
+where
+    V: Fn(Row) + Sync,
+{
+    #[cfg(feature = "tracing")]
+    let _timer = trace::Timer::new(tracing::info_span!("process_data", bytes = data.len()));
+    let config = config.normalize();
+    let visit = &visit;
+    let results = if config.strict || config.safe {
+        let results = process_checked(data, config, visit);
+        if let Some(progress) = progress {
+            progress.add(data.len());
+        }
+        results.map(ResultsMap::into_owned)
+    } else if config.is_default_format() {
+        Ok(process_data_with(data, config, progress, |line| {
+            Row::parse(line).inspect(|row| visit(*row))
+        })
+        .into_owned())
+    } else {
+        Ok(process_data_with(data, config, progress, |line| {
+            Row::parse_with(line, &config).inspect(|row| visit(*row))
+        })
+        .into_owned())
+    };
+    #[cfg(feature = "tracing")]
+    if let Ok(results) = &results {
+        trace::results(results);
+    }
+    results
+}
+
+/// `process_data` for several buffers at once, e.g. one per input file, merging all of their
+/// results together. With rayon, the buffers are processed in parallel as well as the lines
+/// within each one.
+pub fn process_buffers(
+    buffers: &[&[u8]],
+    config: Config,
+    progress: Option<&Progress>,
+) -> Result<ResultsMap, Error> {
+    if let [data] = buffers {
+        return process_data(data, config, progress);
+    }
+
+    #[cfg(feature = "rayon")]
+    if !config.single_threaded {
+        use rayon::prelude::*;
+        return buffers
+            .par_iter()
+            .map(|data| process_data(data, config, progress))
+            .try_reduce(
+                || ResultsMap::new(config),
+                |a, b| merge_checked(a, b, config),
+            );
+    }
+
+    buffers
+        .iter()
+        .map(|data| process_data(data, config, progress))
+        .try_fold(ResultsMap::new(config), |a, b| merge_checked(a, b?, config))
+}
+
+/// merge two sets of results, checking for overflow in safe mode
+fn merge_checked<'a, K>(
+    a: ResultsMap<K>,
+    b: ResultsMap<K>,
+    config: Config,
+) -> Result<ResultsMap<K>, Error>
+where
+    K: Eq + Hash + Borrow<BStr> + From<&'a BStr> + AsRef<[u8]>,
+{
+    #[cfg(feature = "tracing")]
+    let _timer = trace::Timer::new(tracing::info_span!(
+        "merge",
+        cities_a = a.map.len(),
+        cities_b = b.map.len()
+    ));
+    let merged = if config.safe {
+        a.try_merge(b)
+    } else {
+        Ok(a + b)
+    };
+    #[cfg(feature = "tracing")]
+    if let Ok(merged) = &merged {
+        trace::results(merged);
+    }
+    merged
+}
+
+/// How much input `process_reader` reads at a time by default
+pub const READER_CHUNK_SIZE: usize = 16 << 20;
+
+/// The longest line `process_reader` allows, which is far longer than any real measurement
+pub const MAX_LINE_BYTES: usize = 1 << 20;
+
+/// `process_data` for input which isn't available as one big buffer, like stdin. `reader` is
+/// read in chunks of about `chunk_size` bytes.
+///
+/// With rayon, one thread reads ahead (up to a few chunks) while the others aggregate the chunks
+/// which have already been read, so that reading (and maybe decompressing) overlaps with
+/// processing. Otherwise, or with `config.single_threaded`, it reads and aggregates one chunk at a
+/// time. Compressed input (see `Compression`) is an error.
+pub fn process_reader<R: Read + Send>(
+    reader: R,
+    config: Config,
+    chunk_size: usize,
+) -> Result<ResultsMap, Error> {
+    process_reader_with_max_line(reader, config, chunk_size, MAX_LINE_BYTES)
+}
+
+/// `process_reader`, with lines longer than `max_line_bytes` being an error. A line that's
+/// split between reads has to be kept around until its end turns up, so without a limit, input
+/// without any newlines would be buffered until memory runs out. Long lines that happen to fit
+/// within one read of `chunk_size` bytes are still allowed.
+pub fn process_reader_with_max_line<R: Read + Send>(
+    reader: R,
+    config: Config,
+    chunk_size: usize,
+    max_line_bytes: usize,
+) -> Result<ResultsMap, Error> {
+    let config = config.normalize();
+    // peek at the start for compressed input, which would otherwise be aggregated as garbage
+    let mut reader = BufReader::new(reader);
+    let start =
+        std::io::BufRead::fill_buf(&mut reader).map_err(|err| Error::Io(err.to_string()))?;
+    if let Some(compression) = Compression::detect(start) {
+        return Err(Error::Compressed(compression));
+    }
+    let chunks = ChunkReader::new(reader, chunk_size, config.record_sep, max_line_bytes);
+    let process = |chunk: std::io::Result<Vec<u8>>| {
+        let chunk = chunk.map_err(|err| Error::Io(err.to_string()))?;
+        process_data(&chunk, config, None)
+    };
+
+    #[cfg(feature = "rayon")]
+    if !config.single_threaded {
+        use rayon::prelude::*;
+        let (tx, rx) = std::sync::mpsc::sync_channel(rayon::current_num_threads() + 1);
+        return std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for chunk in chunks {
+                    let failed = chunk.is_err();
+                    // a send error means processing already failed, so no point reading more
+                    if tx.send(chunk).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            rx.into_iter().par_bridge().map(process).try_reduce(
+                || ResultsMap::new(config),
+                |a, b| merge_checked(a, b, config),
+            )
+        });
+    }
+
+    chunks
+        .map(process)
+        .try_fold(ResultsMap::new(config), |a, b| merge_checked(a, b?, config))
+}
+
+/// Collect a histogram of all the temperatures for just one city in `data`. This is a separate
+/// pass over the input, so that the main aggregation doesn't need to keep an 8K histogram for
+/// every city just to get one of them. Invalid lines are skipped.
+pub fn city_histogram(data: &[u8], city: &BStr, config: Config) -> Histogram {
+    let process_chunk = |chunk: &[u8]| {
+        let mut hist = Histogram::new();
+        for line in chunk.split(|b| *b == config.record_sep) {
+            if let Some(row) = Row::parse_with(line.as_bstr(), &config) {
+                if row.city == city {
+                    hist.record_weighted(row.temp, row.weight);
+                }
+            }
+        }
+        hist
+    };
+    let merge = |mut a: Histogram, b: Histogram| {
+        a.merge(&b);
+        a
+    };
+
+    // always chunked, so that there aren't thousands of histograms
+    let chunks = split_chunks(data, chunk_count(data.len(), config), config.record_sep);
+
+    #[cfg(feature = "rayon")]
+    if !config.single_threaded {
+        use rayon::prelude::*;
+        return chunks
+            .into_par_iter()
+            .map(process_chunk)
+            .reduce(Histogram::new, merge);
+    }
+
+    chunks
+        .into_iter()
+        .map(process_chunk)
+        .fold(Histogram::new(), merge)
+}
+
+/// Strict and/or safe version of `process_data`, which bails out on the first invalid line or
+/// overflow (or at least the first one rayon gets to, when there are several).
+fn process_checked<'a, V>(
+    data: &'a [u8],
+    config: Config,
+    visit: &V,
+) -> Result<ResultsMap<&'a BStr>, Error>
+where
+    V: Fn(Row) + Sync,
+{
+    let ingest = |mut results: ResultsMap<_>, line: &'a [u8]| {
+        let row = if config.strict {
+            Row::parse_strict(line.as_bstr(), &config)?
+        } else {
+            Row::parse_with(line.as_bstr(), &config)
+        };
+        if let Some(row) = row {
+            if config.safe {
+                results.try_ingest(row)?;
+            } else {
+                results.ingest(row);
+            }
+            visit(row);
+        }
+        Ok(results)
+    };
+
+    #[cfg(feature = "rayon")]
+    if !config.single_threaded {
+        use rayon::prelude::*;
+        return data
+            .par_split(|b| *b == config.record_sep)
+            .try_fold(|| ResultsMap::for_ingest(config), ingest)
+            .try_reduce(
+                || ResultsMap::new(config),
+                |a, b| merge_checked(a, b, config),
+            );
+    }
+
+    data.split(|b| *b == config.record_sep)
+        .try_fold(ResultsMap::for_ingest(config), ingest)
+}
+
+/// Collect all of the measurement results in `data`, using `parse` to split up each line.
+///
+/// This is the meat of the work, the vast majority of program runtime is spent in this function.
+/// It's not inlined for better visibility in perf tools, even though it's only called once from
+/// main().
+#[cfg(feature = "rayon")]
+#[inline(never)]
+fn process_data_with<'a, P>(
+    data: &'a [u8],
+    config: Config,
+    progress: Option<&Progress>,
+    parse: P,
+) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>> + Copy + Sync,
+{
+    use rayon::prelude::*;
+
+    if config.single_threaded {
+        return process_data_serial(data, config, progress, parse);
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.strategy == Strategy::Numa {
+        return numa::process_numa(data, config, progress, parse);
+    }
+
+    if config.strategy == Strategy::Chunked || progress.is_some() {
+        return split_chunks(data, chunk_count(data.len(), config), config.record_sep)
+            .into_par_iter()
+            .map(|chunk| process_chunk(chunk, config, progress, parse))
+            // not sum(), so that empty input still keeps the config
+            .reduce(|| ResultsMap::new(config), std::ops::Add::add);
+    }
+
+    if let Some(min_len) = config.min_chunk_bytes {
+        return rayon::iter::split(data, |piece| {
+            split_in_half(piece, min_len.get() as usize, config.record_sep)
+        })
+        .map(|piece| process_serial(piece, config, parse))
+        .sum();
+    }
+
+    // split on lines in parallel
+    data.par_split(|b| *b == config.record_sep)
+        // Rayon will make a bunch of ResultsMaps (the exact amount isn't specified beyond "as
+        // needed" but I've seen it surpass 25,000, see process_data_unmerged to count them) and
+        // reuse them whenever it calls this closure in a worker thread. fold() returns a
+        // ParallelIterator<Item = ResultsMap>. They're boxed so that handing one on from line to
+        // line only moves a pointer, since a ResultsMap is more than 128 bytes.
+        .fold(
+            || Box::new(ResultsMap::for_ingest(config)),
+            |mut results, line| {
+                // SPICY HOT! Called for every line.
+                if let Some(row) = parse(line.as_bstr()) {
+                    results.ingest(row);
+                }
+                // pass on results accumulator for next task
+                results
+            },
+        )
+        .map(|results| *results)
+        // Then immediately (and still in parallel) reduce those ResultsMaps into a single one.
+        // Somehow this (which uses the std::iter::Sum impl above) is faster than using
+        // ParallelIterator::reduce, even though it's basically the same code.
+        .sum()
+}
+
+/// Every map that the lines strategy's `fold` makes, before they get merged together, for seeing
+/// how rayon splits up the work between them. Summing them up gives the same results as
+/// `process_data`.
+///
+/// This always splits the input line by line like the lines strategy without `min_chunk_bytes`,
+/// whatever `config.strategy` and `config.single_threaded` say (and even with histograms, which
+/// normally switch to chunks), and there's no strict or safe mode. The city names get copied
+/// into every map, so it's a lot slower than the real thing.
+#[cfg(feature = "debug-internals")]
+pub fn process_data_unmerged(data: &[u8], config: Config) -> Vec<ResultsMap> {
+    use rayon::prelude::*;
+
+    data.par_split(|b| *b == config.record_sep)
+        .fold(
+            || ResultsMap::for_ingest(config),
+            |mut results, line| {
+                if let Some(row) = Row::parse_with(line.as_bstr(), &config) {
+                    results.ingest(row);
+                }
+                results
+            },
+        )
+        .map(ResultsMap::into_owned)
+        .collect()
+}
+
+/// Without rayon, everything is single-threaded
+#[cfg(not(feature = "rayon"))]
+#[inline(never)]
+fn process_data_with<'a, P>(
+    data: &'a [u8],
+    config: Config,
+    progress: Option<&Progress>,
+    parse: P,
+) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>> + Copy,
+{
+    process_data_serial(data, config, progress, parse)
+}
+
+/// Single-threaded version of the above, used without rayon or with `config.single_threaded`
+#[inline(always)]
+fn process_data_serial<'a, P>(
+    data: &'a [u8],
+    config: Config,
+    progress: Option<&Progress>,
+    parse: P,
+) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>> + Copy,
+{
+    if config.strategy != Strategy::Lines || progress.is_some() {
+        return split_chunks(data, chunk_count(data.len(), config), config.record_sep)
+            .into_iter()
+            .map(|chunk| process_chunk(chunk, config, progress, parse))
+            .fold(ResultsMap::new(config), std::ops::Add::add);
+    }
+
+    process_serial(data, config, parse)
+}
+
+/// Aggregate everything in `data` on the current thread.
+#[cfg_attr(not(feature = "rayon"), inline(always))]
+fn process_serial<'a, P>(data: &'a [u8], config: Config, parse: P) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>>,
+{
+    if config.intern {
+        return split_records(data, config.record_sep)
+            .fold(InternedResults::new(config), |mut results, line| {
+                if let Some(row) = parse(line.as_bstr()) {
+                    results.ingest(row);
+                }
+                results
+            })
+            .into_results();
+    }
+
+    split_records(data, config.record_sep).fold(
+        ResultsMap::for_ingest(config),
+        |mut results, line| {
+            // SPICY HOT! Called for every line.
+            if let Some(row) = parse(line.as_bstr()) {
+                results.ingest(row);
+            }
+            // pass on results accumulator for next task
+            results
+        },
+    )
+}
+
+/// Split `data` into records ending in `sep`, exactly like `data.split(|&b| b == sep)` (including
+/// the empty or unterminated piece after the last `sep`), for the serial hot loop.
+#[cfg(not(feature = "memchr"))]
+#[inline(always)]
+fn split_records(data: &[u8], sep: u8) -> impl Iterator<Item = &[u8]> {
+    data.split(move |&b| b == sep)
+}
+
+/// Split `data` into records ending in `sep`, exactly like `data.split(|&b| b == sep)` (including
+/// the empty or unterminated piece after the last `sep`), for the serial hot loop. This version
+/// uses memchr's SIMD search for the separators rather than checking a byte at a time.
+///
+/// Built without rayon, on 20M rows that was about 5% faster with ~400 cities (0.87s vs 0.91s
+/// median of 15), and 8% faster with 100k cities, where the time goes to the hash map instead.
+#[cfg(feature = "memchr")]
+#[inline(always)]
+fn split_records(data: &[u8], sep: u8) -> impl Iterator<Item = &[u8]> {
+    let mut ends = memchr::memchr_iter(sep, data);
+    let mut start = Some(0);
+    std::iter::from_fn(move || {
+        let begin = start?;
+        match ends.next() {
+            Some(end) => {
+                start = Some(end + 1);
+                Some(&data[begin..end])
+            }
+            None => {
+                start = None;
+                Some(&data[begin..])
+            }
+        }
+    })
+}
+
+/// Aggregate one chunk for the chunked strategy, then update the progress counter (and live
+/// snapshot, if any).
+fn process_chunk<'a, P>(
+    chunk: &'a [u8],
+    config: Config,
+    progress: Option<&Progress>,
+    parse: P,
+) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>>,
+{
+    let results = process_serial(chunk, config, parse);
+    if let Some(progress) = progress {
+        progress.add_chunk(chunk.len(), &results);
+    }
+    results
+}
+
+/// Largest chunk for the chunked strategy, so that progress updates aren't too far apart
+const MAX_CHUNK_SIZE: usize = 64 << 20;
+
+/// How many chunks to use for the chunked strategy. A few per thread so that the work evens out,
+/// and no more than 64MiB each so that progress updates aren't too far apart. Single-threaded,
+/// that doesn't depend on the size of rayon's pool, so the chunks (and anything that depends on
+/// the order they're merged in) are the same whatever `RAYON_NUM_THREADS` is.
+fn chunk_count(len: usize, config: Config) -> usize {
+    #[cfg(feature = "rayon")]
+    let threads = if config.single_threaded {
+        1
+    } else {
+        rayon::current_num_threads()
+    };
+    #[cfg(not(feature = "rayon"))]
+    let threads = {
+        let _ = config;
+        1
+    };
+    (threads * 4).max(len / MAX_CHUNK_SIZE + 1)
+}
+
+/// Split `data` into about `count` chunks of roughly equal size. Each chunk ends just after a
+/// `sep` (except maybe the last one) so that no record is split between two chunks.
+fn split_chunks(data: &[u8], count: usize, sep: u8) -> Vec<&[u8]> {
+    let target_size = data.len() / count.max(1) + 1;
+    let mut chunks = Vec::with_capacity(count);
+    let mut rest = data;
+    while !rest.is_empty() {
+        let end = match rest.iter().skip(target_size).position(|b| *b == sep) {
+            Some(pos) => target_size + pos + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// Splitter for `rayon::iter::split`: cut `data` at the first `sep` after its midpoint, unless
+/// that would leave either half smaller than `min_len` bytes. Rayon keeps splitting pieces as long
+/// as it has idle threads to give them to.
+#[cfg(feature = "rayon")]
+fn split_in_half(data: &[u8], min_len: usize, sep: u8) -> (&[u8], Option<&[u8]>) {
+    let mid = data.len() / 2;
+    if mid < min_len.max(1) {
+        return (data, None);
+    }
+    match data[mid..].find_byte(sep) {
+        Some(pos) if data.len() - (mid + pos + 1) >= min_len => {
+            let (first, second) = data.split_at(mid + pos + 1);
+            (first, Some(second))
+        }
+        _ => (data, None),
+    }
+}
+
+/// Skip a UTF-8 byte order mark at the start of `data`, if there is one. Some Windows tools
+/// like to add them, and otherwise it would end up as part of the first city's name.
+pub fn strip_bom(data: &[u8]) -> &[u8] {
+    data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data)
+}
+
+/// A compressed format, recognized by the magic bytes at the start of the input rather than by
+/// file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Which compressed format `data` starts with, if any. None of the magic numbers are valid
+    /// UTF-8, so they can't be confused with the start of a real measurements file.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [0xfd, 0x37, 0x7a, ..] => Some(Self::Xz),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        })
+    }
+}
+
+/// Split off a leading `#` metadata line, returning its contents (without the `#` or newline) and
+/// the rest of the data. Returns None and all of `data` if it doesn't start with `#`.
+pub fn split_header(data: &[u8]) -> (Option<&[u8]>, &[u8]) {
+    let Some(rest) = data.strip_prefix(b"#") else {
+        return (None, data);
+    };
+    match rest.find_byte(b'\n') {
+        Some(pos) => (
+            Some(rest[..pos].trim_end_with(|c| c == '\r')),
+            &rest[pos + 1..],
+        ),
+        None => (Some(rest), &[]),
+    }
+}
+
+/// Get the first `n` lines of `data` (including the newline at the end of the last one), or all of
+/// it if there aren't that many lines.
+///
+/// This is a serial scan for newlines, but it uses memchr and is still fast compared to the
+/// actual parsing and aggregation, which runs in parallel over the truncated data as usual.
+pub fn first_lines(data: &[u8], n: usize) -> &[u8] {
+    first_records(data, n, b'\n')
+}
+
+/// `first_lines`, for records that end with `sep` rather than a newline
+pub fn first_records(data: &[u8], n: usize, sep: u8) -> &[u8] {
+    let mut end = 0;
+    for _ in 0..n {
+        match data[end..].find_byte(sep) {
+            Some(pos) => end += pos + 1,
+            None => return data,
+        }
+    }
+    &data[..end]
+}
+
+/// Shard `index` (counting from 0) of `count`, for splitting one input between several
+/// processes or machines that each aggregate their own part. That's about `1/count` of `data`,
+/// starting at the first record that starts at or after `index * len / count` and running up to
+/// where the next shard starts, so every record is in exactly one shard and none are split.
+/// Records are separated by `sep`. Shards can be empty if there are fewer records than shards,
+/// or some are very long.
+///
+/// The shards' results can be merged back together to get the same as the whole input, e.g. with
+/// the count and total in `write_binary`'s output.
+///
+/// ```
+/// let data = b"Paris;12.3\nOslo;-3.0\nRome;17.0\n";
+/// let shards: Vec<&[u8]> = (0..2).map(|i| onebrc::shard(data, i, 2, b'\n')).collect();
+/// assert_eq!(shards, [&b"Paris;12.3\nOslo;-3.0\n"[..], b"Rome;17.0\n"]);
+/// ```
+pub fn shard(data: &[u8], index: usize, count: usize, sep: u8) -> &[u8] {
+    let start = |index: usize| {
+        if index >= count {
+            return data.len();
+        }
+        // u128 so that this can't overflow
+        let pos = (data.len() as u128 * index as u128 / count as u128) as usize;
+        record_start(data, pos, sep)
+    };
+    &data[start(index)..start(index + 1)]
+}
+
+/// The records that start within `range` of `data`, for aggregating a fixed amount of a big input
+/// without making a separate file. Like `shard`, records are never split: one that starts inside
+/// the range is kept whole, even if it runs past the end, and one that starts before it is left
+/// out, so consecutive ranges cover every record exactly once. The range is clamped to `data`.
+///
+/// ```
+/// let data = b"Paris;12.3\nOslo;-3.0\nRome;17.0\n";
+/// assert_eq!(onebrc::byte_range(data, 0..12, b'\n'), b"Paris;12.3\nOslo;-3.0\n");
+/// assert_eq!(onebrc::byte_range(data, 12..usize::MAX, b'\n'), b"Rome;17.0\n");
+/// ```
+pub fn byte_range(data: &[u8], range: Range<usize>, sep: u8) -> &[u8] {
+    let start = record_start(data, range.start, sep);
+    let end = record_start(data, range.end, sep).max(start);
+    &data[start..end]
+}
+
+/// where the first record that starts at or after `pos` starts, or the end of `data`
+fn record_start(data: &[u8], pos: usize, sep: u8) -> usize {
+    if pos == 0 {
+        return 0;
+    }
+    if pos >= data.len() {
+        return data.len();
+    }
+    // a record starting right at pos counts, so look from the byte before it
+    match data[pos - 1..].find_byte(sep) {
+        Some(offset) => pos + offset,
+        None => data.len(),
+    }
+}
+
+/// Above this many cities, `summarize` finalizes and sorts in parallel. Finalizing is cheap
+/// (about 0.1µs per city) so below this, rayon's overhead costs more than it saves, and the
+/// serial sort only starts taking a couple of milliseconds at around 20k cities.
+#[cfg(feature = "rayon")]
+const PARALLEL_SUMMARIZE_THRESHOLD: usize = 20_000;
+/// Same, but when there are histograms. Finding the mode scans every bin, so finalizing takes
+/// about 4µs per city and is worth splitting up much sooner.
+#[cfg(feature = "rayon")]
+const PARALLEL_SUMMARIZE_HISTOGRAM_THRESHOLD: usize = 1_000;
+
+/// Finalize statstics: determine the mean temperatures and sort by city name. For typical
+/// inputs it's faster to do this serially, since rayon's parallel iteration over maps is to first
+/// collect them into an intermediate Vec, and the computation in stats.finalize is cheap. Only
+/// very high cardinality results are done in parallel, see `PARALLEL_SUMMARIZE_THRESHOLD`.
+///
+/// This is `finalize_unsorted` followed by `sort_summary`, which can be called separately to time
+/// them separately.
+pub fn summarize(results: ResultsMap) -> Vec<(BString, FinalStats)> {
+    let config = results.config;
+    let mut summary = finalize_unsorted(results);
+    sort_summary(&mut summary, config);
+    summary
+}
+
+/// Whether `summarize` should work in parallel on this many cities.
+#[cfg(feature = "rayon")]
+fn summarize_in_parallel(cities: usize, config: Config) -> bool {
+    let threshold = if config.histogram {
+        PARALLEL_SUMMARIZE_HISTOGRAM_THRESHOLD
+    } else {
+        PARALLEL_SUMMARIZE_THRESHOLD
+    };
+    cities >= threshold && !config.single_threaded
+}
+
+/// The first half of `summarize`: finalize every city's stats, in no particular order.
+pub fn finalize_unsorted(results: ResultsMap) -> Vec<(BString, FinalStats)> {
+    #[cfg(feature = "tracing")]
+    let _timer = trace::Timer::new(tracing::info_span!("finalize", cities = results.map.len()));
+    let config = results.config;
+    #[cfg(feature = "rayon")]
+    if summarize_in_parallel(results.map.len(), config) {
+        use rayon::prelude::*;
+        let stats: Vec<(BString, Stats)> = results.into_iter().collect();
+        return stats
+            .into_par_iter()
+            .map(|(city, stats)| (city, stats.finalize(config)))
+            .collect();
+    }
+
+    results
+        .into_iter()
+        .map(|(city, stats)| (city, stats.finalize(config)))
+        .collect()
+}
+
+/// The second half of `summarize`: sort finalized stats by city name. `config` should be the one
+/// the results were aggregated with, it decides whether to sort in parallel.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub fn sort_summary(summary: &mut [(BString, FinalStats)], config: Config) {
+    #[cfg(feature = "tracing")]
+    let _timer = trace::Timer::new(tracing::info_span!("sort", cities = summary.len()));
+    #[cfg(feature = "rayon")]
+    if summarize_in_parallel(summary.len(), config) {
+        use rayon::prelude::*;
+        summary.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        return;
+    }
+    summary.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+}
+
+/// `summarize`, but in the order that each city first appears in the input rather than sorted
+/// by name.
+///
+/// Every map numbers its cities as it first sees them, and merging two maps numbers the second
+/// one's new cities after all of the first one's, so this is exact whenever results are merged
+/// in input order. That's always the case single-threaded, and rayon's reductions keep the
+/// order of their inputs too, with the exception of `process_reader`, which hands chunks out to
+/// threads as they come and merges them in whatever order they finish. Cities added with
+/// `ResultsMap::ingest_raw` after merging maps together don't get numbered after the merged ones.
+pub fn summarize_as_seen(results: ResultsMap) -> Vec<(BString, FinalStats)> {
+    #[cfg(feature = "tracing")]
+    let _timer = trace::Timer::new(tracing::info_span!("finalize", cities = results.map.len()));
+    let config = results.config;
+    let mut stats: Vec<(BString, Stats)> = results.into_iter().collect();
+    stats.sort_unstable_by_key(|(_, stats)| stats.first_seen);
+    stats
+        .into_iter()
+        .map(|(city, stats)| (city, stats.finalize(config)))
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_float_accum() {
+    // Paris and Oslo both have an exact mean of a half tenth (3.05 and -5.55), which the f64
+    // totals miss by a hair and round the other way.
+    let data = include_bytes!("../testdata/float-accum.txt");
+
+    // what a reference implementation summing doubles gets
+    let mut reference = std::collections::BTreeMap::<&str, (f64, u32)>::new();
+    for line in std::str::from_utf8(data).unwrap().lines() {
+        let (city, temp) = line.split_once(';').unwrap();
+        let entry = reference.entry(city).or_default();
+        entry.0 += temp.parse::<f64>().unwrap();
+        entry.1 += 1;
+    }
+    let means = |config| {
+        summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| (city.to_string(), stats.mean.to_string()))
+            .collect::<Vec<_>>()
+    };
+    let expected: Vec<_> = reference
+        .into_iter()
+        .map(|(city, (total, count))| {
+            let mean = (total / f64::from(count) * 10.0).round() / 10.0;
+            (city.to_string(), format!("{mean:.1}"))
+        })
+        .collect();
+
+    for strategy in [Strategy::Lines, Strategy::Chunked] {
+        let config = Config {
+            strategy,
+            float_accum: true,
+            ..Config::default()
+        };
+        assert_eq!(means(config), expected);
+    }
+    assert_eq!(
+        means(Config::default()),
+        [("Oslo", "-5.6"), ("Paris", "3.1"), ("Rome", "17.0")].map(|(c, m)| (c.into(), m.into()))
+    );
+    assert_eq!(
+        expected[..2],
+        [
+            ("Oslo".into(), "-5.5".into()),
+            ("Paris".into(), "3.0".into())
+        ]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_endpoints() {
+    let data = include_bytes!("../testdata/endpoints.txt");
+    let t = Temperature::from_tenths;
+    let expected = [
+        ("Oslo", Some(t(-30)), Some(t(-81))),
+        ("Paris", Some(t(123)), Some(t(99))),
+        ("Rome", Some(t(170)), Some(t(170))),
+    ];
+    for strategy in [Strategy::Lines, Strategy::Chunked] {
+        let config = Config {
+            single_threaded: true,
+            endpoints: true,
+            strategy,
+            ..Config::default()
+        };
+        let results = summarize(process_data(data, config, None).unwrap());
+        let endpoints: Vec<_> = results
+            .iter()
+            .map(|(city, stats)| (city.to_str().unwrap(), stats.first(), stats.last()))
+            .collect();
+        assert_eq!(endpoints, expected, "{strategy:?}");
+    }
+
+    // not kept track of unless they're wanted
+    let results = summarize(process_data(data, Config::default(), None).unwrap());
+    assert_eq!(results[0].1.first(), None);
+    assert_eq!(results[0].1.last(), None);
+
+    // merging keeps the first from the earlier map and the last from the later one
+    let rows = |data: &'static [u8]| {
+        let mut results = ResultsMap::new(Config {
+            endpoints: true,
+            ..Config::default()
+        });
+        for line in data.lines() {
+            if let Some(row) = Row::parse(line.as_bstr()) {
+                results.ingest_raw(row.city.as_bytes(), row.temp);
+            }
+        }
+        results
+    };
+    let (a, b) = data.split_at(data.find(b"Rome").unwrap());
+    let merged = summarize(rows(a) + rows(b));
+    assert_eq!(merged[1].1.first(), Some(t(123)));
+    assert_eq!(merged[1].1.last(), Some(t(99)));
+    assert_eq!(FinalStats::new(t(0), t(0), t(0), 1).first(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_empty_city() {
+    let data = include_bytes!("../testdata/empty-city.txt");
+    let run = |config| {
+        summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| format!("{city}={stats}"))
+            .collect::<Vec<_>>()
+    };
+    let kept = [
+        "=-1.0/5.7/12.3",
+        "Oslo=-3.0/-3.0/-3.0",
+        "Paris=12.3/13.2/14.1",
+    ];
+    assert_eq!(run(Config::default()), kept);
+    assert_eq!(
+        run(Config {
+            strategy: Strategy::Chunked,
+            ..Config::default()
+        }),
+        kept
+    );
+    let skip = Config {
+        skip_empty_city: true,
+        ..Config::default()
+    };
+    assert_eq!(run(skip), &kept[1..]);
+
+    for config in [Config::default(), skip] {
+        let err = process_data(
+            data,
+            Config {
+                strict: true,
+                ..config
+            },
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            Error::Parse(ParseError {
+                line: ";12.3".into(),
+                reason: "empty city",
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_group_by() {
+    let data = include_bytes!("../testdata/group-by.txt");
+    let run = |group_by, config| {
+        summarize(process_data(data, Config { group_by, ..config }, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| format!("{city}={stats}"))
+            .collect::<Vec<_>>()
+    };
+    let prefix = GroupBy::Prefix(NonZeroU8::new(2).unwrap());
+    let before = GroupBy::Before(b'/');
+    for config in [
+        Config::default(),
+        Config {
+            strategy: Strategy::Chunked,
+            ..Config::default()
+        },
+        Config {
+            strict: true,
+            ..Config::default()
+        },
+        Config {
+            intern: true,
+            ..Config::default()
+        },
+    ] {
+        // the ü in Zürich straddles the second byte, and is kept whole
+        assert_eq!(
+            run(prefix, config),
+            [
+                "As=-4.2/7.9/20.0",
+                "Eu=8.1/11.5/14.1",
+                "Os=-3.0/-3.0/-3.0",
+                "Za=-1.0/-1.0/-1.0",
+                "Zü=3.0/3.0/3.0",
+            ],
+            "{config:?}"
+        );
+        assert_eq!(
+            run(before, config),
+            [
+                "Asia=-4.2/7.9/20.0",
+                "Europe=8.1/11.5/14.1",
+                "Oslo=-3.0/-3.0/-3.0",
+                "Zagreb=-1.0/-1.0/-1.0",
+                "Zürich=3.0/3.0/3.0",
+            ],
+            "{config:?}"
+        );
+    }
+
+    let mut results = ResultsMap::new(Config {
+        group_by: before,
+        ..Config::default()
+    });
+    results.ingest_raw(b"Europe/Paris", Temperature::from_tenths(123));
+    results.ingest_raw(b"Europe/Berlin", Temperature::from_tenths(81));
+    let summary = summarize(results);
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].0, "Europe");
+    assert_eq!(summary[0].1.count(), 2);
+
+    assert_eq!("city".parse(), Ok(GroupBy::City));
+    assert_eq!("prefix:2".parse(), Ok(prefix));
+    assert_eq!("before:/".parse(), Ok(before));
+    for invalid in [
+        "prefix:0",
+        "prefix:256",
+        "prefix:",
+        "before:",
+        "before:ab",
+        "city:1",
+        "x",
+    ] {
+        assert!(invalid.parse::<GroupBy>().is_err(), "{invalid}");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_reference_rounding() {
+    // every mean is exactly halfway between two tenths
+    let data = b"A;0.0\nA;-0.1\nB;1.2\nB;1.3\nC;-1.2\nC;-1.3\nD;-3.4\nD;-3.5\n\
+                 E;-0.1\nE;-0.1\nE;0.0\nE;0.0\nF;-99.9\nF;-99.8\nG;99.8\nG;99.9\n";
+    let means = |config| {
+        summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(_, stats)| stats.mean.to_string())
+            .collect::<Vec<_>>()
+    };
+    // what the official 1BRC's Math.round(mean * 10.0) / 10.0 gets
+    let reference = ["0.0", "1.3", "-1.2", "-3.4", "0.0", "-99.8", "99.9"];
+    let config = Config {
+        reference_rounding: true,
+        ..Config::default()
+    };
+    assert_eq!(means(config), reference);
+    // and summing up f64s, like the reference does, comes out the same here
+    let float = Config {
+        float_accum: true,
+        ..config
+    };
+    assert_eq!(means(float), reference);
+    assert_eq!(
+        means(Config::default()),
+        ["-0.1", "1.3", "-1.3", "-3.5", "-0.1", "-99.9", "99.9"]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_negative_zero_mean() {
+    let data =
+        b"Zero;-0.1\nZero;0.1\nTiny;-0.1\nTiny;0.0\nTiny;0.0\nTiny;0.0\nTiny;0.0\nTiny;0.0\n";
+    let results = summarize(process_data(data, Config::default(), None).unwrap());
+    let stats = |city: &str| results.iter().find(|(c, _)| c == city).unwrap().1;
+
+    assert_eq!(stats("Zero").to_string(), "-0.1/0.0/0.1");
+    // -0.1 / 6 rounds to zero, with no sign left over
+    assert_eq!(stats("Tiny").to_string(), "-0.1/0.0/0.0");
+    assert_eq!(
+        stats("Tiny").precise_mean(Unit::Celsius, 1).to_string(),
+        "0.0"
+    );
+    assert_eq!(
+        stats("Tiny").precise_mean(Unit::Celsius, 2).to_string(),
+        "-0.02"
+    );
+    assert_eq!(
+        stats("Zero").precise_mean(Unit::Celsius, 3).to_string(),
+        "0.000"
+    );
+    // -17.8C is -0.04F
+    assert_eq!(
+        Unit::Fahrenheit
+            .convert(Temperature::parse("-17.8"))
+            .to_string(),
+        "0.0"
+    );
+}
+
+/// Every city's raw (total, count, min, max), sorted by city, for comparing results exactly
+#[cfg(test)]
+fn raw_stats(results: &ResultsMap) -> Vec<(BString, Total, Count, Temperature, Temperature)> {
+    let mut stats: Vec<_> = results
+        .map
+        .iter()
+        .map(|(city, s)| (city.clone(), s.total, s.count, s.min, s.max))
+        .collect();
+    stats.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    stats
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    /// Merging is associative and commutative: splitting rows up any which way and merging the
+    /// pieces back together in any grouping and order gives exactly the same stats as one pass,
+    /// which the nondeterministic rayon reductions depend on.
+    #[test]
+    fn test_merge_associative(
+        rows in proptest::collection::vec((0..8u8, -999..=999i32), 0..200),
+        cuts in proptest::collection::vec(0..200usize, 0..10),
+        order in proptest::collection::vec(proptest::num::usize::ANY, 0..10),
+    ) {
+        let city = |i: u8| format!("city{i}").into_bytes();
+        let ingest = |rows: &[(u8, i32)]| {
+            let mut results = ResultsMap::default();
+            for (i, temp) in rows {
+                results.ingest_raw(&city(*i), Temperature::from_tenths(*temp));
+            }
+            results
+        };
+        let expected = raw_stats(&ingest(&rows));
+
+        // split at random points, which includes empty pieces when cuts repeat
+        let mut cuts: Vec<usize> = cuts.into_iter().map(|c| c.min(rows.len())).collect();
+        cuts.sort_unstable();
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for cut in cuts.into_iter().chain([rows.len()]) {
+            pieces.push(ingest(&rows[start..cut]));
+            start = cut;
+        }
+
+        // merge adjacent pairs, or swapped pairs, in a random order until there's one left
+        let mut order = order.into_iter();
+        while pieces.len() > 1 {
+            let pick = order.next().unwrap_or(0);
+            let i = pick % (pieces.len() - 1);
+            let b = pieces.remove(i + 1);
+            let a = pieces.remove(i);
+            let merged = if pick % 2 == 0 { a + b } else { b + a };
+            pieces.insert(i, merged);
+        }
+        proptest::prop_assert_eq!(raw_stats(&pieces[0]), expected);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_shard() {
+    for data in [
+        &include_bytes!("../testdata/measurements-10000-unique-keys.txt")[..],
+        include_bytes!("../testdata/measurements-20.txt"),
+        b"a;1.0\nb;2.0",
+        b"a;1.0\n",
+        b"",
+    ] {
+        let expected = raw_stats(&process_data(data, Config::default(), None).unwrap());
+        for count in [1, 2, 3, 7, 50] {
+            let shards: Vec<&[u8]> = (0..count).map(|i| shard(data, i, count, b'\n')).collect();
+            // every record is in exactly one shard
+            assert_eq!(shards.concat(), data);
+            // and none are split, or the shards would have extra cities
+            let merged: ResultsMap = shards
+                .iter()
+                .map(|s| process_data(s, Config::default(), None).unwrap())
+                .sum();
+            assert_eq!(raw_stats(&merged), expected, "{count}");
+        }
+    }
+
+    // a record starting exactly at the boundary belongs to the shard after it
+    let data = b"a;1.0\nb;2.0\n";
+    assert_eq!(shard(data, 0, 2, b'\n'), b"a;1.0\n");
+    assert_eq!(shard(data, 1, 2, b'\n'), b"b;2.0\n");
+}
+
+#[cfg(test)]
+#[test]
+fn test_byte_range() {
+    let data = include_bytes!("../testdata/measurements-sample.txt");
+    let rows = |data: &[u8]| -> u64 {
+        summarize(process_data(data, Config::default(), None).unwrap())
+            .iter()
+            .map(|(_, stats)| stats.count())
+            .sum()
+    };
+    let full = raw_stats(&process_data(data, Config::default(), None).unwrap());
+    for range in [0..data.len(), 0..usize::MAX, 0..data.len() + 1] {
+        let slice = byte_range(data, range.clone(), b'\n');
+        assert_eq!(slice, data, "{range:?}");
+        let results = process_data(slice, Config::default(), None).unwrap();
+        assert_eq!(raw_stats(&results), full, "{range:?}");
+    }
+
+    // The first two lines are "Honolulu;19.6\n" and "Vostok;-45.3\n", 14 and 13 bytes, so they
+    // start at 0 and 14, and the third at 27. Only the lines starting in the range count, but
+    // they count whole.
+    assert_eq!(byte_range(data, 0..14, b'\n'), b"Honolulu;19.6\n");
+    assert_eq!(rows(byte_range(data, 0..15, b'\n')), 2);
+    assert_eq!(rows(byte_range(data, 1..14, b'\n')), 0);
+    assert_eq!(byte_range(data, 1..15, b'\n'), b"Vostok;-45.3\n");
+    assert_eq!(rows(byte_range(data, 14..28, b'\n')), 2);
+
+    // consecutive ranges add up to the whole thing
+    let total = rows(data);
+    let sum: u64 = (0..data.len())
+        .step_by(100)
+        .map(|start| rows(byte_range(data, start..start + 100, b'\n')))
+        .sum();
+    assert_eq!(sum, total);
+
+    assert_eq!(byte_range(data, data.len()..usize::MAX, b'\n'), b"");
+    assert_eq!(byte_range(b"", 0..10, b'\n'), b"");
+}
+
+#[cfg(test)]
+#[test]
+fn test_first_lines() {
+    let data = include_bytes!("../testdata/measurements-20.txt");
+    let total_count = |data| {
+        summarize(process_data(data, Config::default(), None).unwrap())
+            .iter()
+            .map(|(_, stats)| stats.count())
+            .sum::<u64>()
+    };
+
+    assert_eq!(total_count(data), 20);
+    for n in [0, 1, 5, 19, 20] {
+        let limited = first_lines(data, n);
+        assert_eq!(total_count(limited), n as u64);
+        assert_eq!(limited.lines().count(), n);
+        assert!(data.starts_with(limited));
+    }
+    assert_eq!(first_lines(data, 25), data);
+    // a last line with no trailing newline counts too
+    assert_eq!(first_lines(b"a;1.0\nb;2.0", 2), b"a;1.0\nb;2.0");
+}
+
+#[cfg(test)]
+#[test]
+fn test_progress() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let progress = Progress::new(data.len());
+    let with_progress = process_data(data, Config::default(), Some(&progress)).unwrap();
+    assert_eq!(progress.done(), data.len());
+
+    // chunking for progress doesn't change the results
+    let without = process_data(data, Config::default(), None).unwrap();
+    assert_eq!(
+        format!("{:?}", summarize(with_progress)),
+        format!("{:?}", summarize(without))
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_strict() {
+    let strict = Config {
+        strict: true,
+        ..Config::default()
+    };
+    let summary = |data: &[u8], config| {
+        let results = process_data(data, config, None)?;
+        Ok::<_, Error>(format!("{:?}", summarize(results)))
+    };
+
+    // valid input is the same either way, including a trailing newline
+    let data = include_bytes!("../testdata/measurements-20.txt");
+    assert_eq!(summary(data, strict), summary(data, Config::default()));
+
+    // no decimal point
+    let err = process_data(b"Paris;12.0\nParis;12\n", strict, None).unwrap_err();
+    assert_eq!(
+        err,
+        Error::Parse(ParseError {
+            line: "Paris;12".into(),
+            reason: "missing decimal point"
+        })
+    );
+
+    // decimal commas need to be enabled, and then are the only thing allowed
+    let comma = Config {
+        decimal: Decimal::Comma,
+        ..strict
+    };
+    let data = b"Paris;12,3\nParis;-1,0\n";
+    assert!(process_data(data, strict, None).is_err());
+    assert_eq!(
+        summary(data, comma),
+        summary(b"Paris;12.3\nParis;-1.0\n", strict)
+    );
+    let err = process_data(b"Paris;12,3\nParis;12.3\n", comma, None).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse(ParseError {
+            reason: "invalid character",
+            ..
+        })
+    ));
+
+    // no delimiter
+    let err = process_data(b"Paris;12.0\nParis 12.0\n", strict, None).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse(ParseError {
+            reason: "missing ';'",
+            ..
+        })
+    ));
+
+    // whole degrees, where a decimal point isn't allowed
+    let int_degrees = Config {
+        int_degrees: true,
+        ..strict
+    };
+    assert_eq!(
+        summary(b"Paris;12\nParis;-3\n", int_degrees),
+        summary(b"Paris;12.0\nParis;-3.0\n", strict)
+    );
+    let err = process_data(b"Paris;12\nParis;12.0\n", int_degrees, None).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Parse(ParseError {
+            reason: "invalid character",
+            ..
+        })
+    ));
+
+    // Empty or extra fields. Normally they just get read as 12.3, strict mode rejects them
+    // (unless the city could have had a ';' in it).
+    for line in ["Paris;;12.3", "Paris;12.3;extra"] {
+        let data = format!("Oslo;-3.0\n{line}\n");
+        assert_eq!(
+            summary(data.as_bytes(), Config::default()),
+            summary(b"Oslo;-3.0\nParis;12.3\n", Config::default())
+        );
+        let err = process_data(data.as_bytes(), strict, None).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Parse(ParseError {
+                line: line.into(),
+                reason: "wrong number of fields"
+            })
+        );
+    }
+    let split_last = Config {
+        split_last: true,
+        ..strict
+    };
+    let summary = summarize(process_data(b"Paris;;12.3\n", split_last, None).unwrap());
+    assert_eq!(summary[0].0, "Paris;");
+    let reversed = Config {
+        order: FieldOrder::TempCity,
+        ..strict
+    };
+    assert!(process_data(b"12.3;;Paris\n", reversed, None).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_safe() {
+    let safe = Config {
+        safe: true,
+        ..Config::default()
+    };
+    let row = |city, temp| Row {
+        city: BStr::new(city),
+        temp: Temperature::parse(temp),
+        weight: 1,
+    };
+    // a map with one city that's nearly full, as if it had already seen a ton of rows
+    let near_limit = |total, count| {
+        let mut results: ResultsMap<&BStr> = ResultsMap::new(safe);
+        results.ingest(row("Paris", "0.0"));
+        results.ingest(row("Oslo", "0.0"));
+        let stats = results.map.get_mut(BStr::new("Paris")).unwrap();
+        stats.total = total;
+        stats.count = count;
+        results
+    };
+    let overflow = Err(Error::Overflow {
+        city: "Paris".into(),
+    });
+
+    // total
+    let mut results = near_limit(Total::MAX - 1000, 10);
+    assert_eq!(results.try_ingest(row("Paris", "99.9")), Ok(()));
+    assert_eq!(results.try_ingest(row("Paris", "0.2")), overflow);
+    let mut results = near_limit(Total::MIN + 998, 10);
+    assert_eq!(results.try_ingest(row("Paris", "-99.9")), overflow);
+    // other cities are unaffected
+    assert_eq!(results.try_ingest(row("Oslo", "-99.9")), Ok(()));
+
+    // count
+    let mut results = near_limit(0, Count::MAX - 1);
+    assert_eq!(results.try_ingest(row("Paris", "1.0")), Ok(()));
+    assert_eq!(results.try_ingest(row("Paris", "1.0")), overflow);
+
+    // merging
+    let merged = near_limit(0, Count::MAX / 2).try_merge(near_limit(0, Count::MAX / 2));
+    assert_eq!(
+        merged.unwrap().map[BStr::new("Paris")].count,
+        Count::MAX - 1
+    );
+    let merged = near_limit(0, Count::MAX / 2 + 1).try_merge(near_limit(0, Count::MAX / 2 + 1));
+    assert_eq!(merged.map(|_| ()), overflow);
+    let merged = near_limit(Total::MAX / 2 + 1, 1).try_merge(near_limit(Total::MAX / 2 + 1, 1));
+    assert_eq!(merged.map(|_| ()), overflow);
+
+    // without safe mode, merging just wraps
+    let mut wrapped = near_limit(Total::MAX, 1);
+    wrapped.merge(near_limit(1, 1));
+    assert_eq!(wrapped.map[BStr::new("Paris")].total, Total::MIN);
+
+    // normal input is the same either way
+    let data = include_bytes!("../testdata/measurements-20.txt");
+    assert_eq!(
+        format!("{:?}", summarize(process_data(data, safe, None).unwrap())),
+        format!(
+            "{:?}",
+            summarize(process_data(data, Config::default(), None).unwrap())
+        )
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_high_cardinality() {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    const CITIES: usize = 50_000;
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut data = Vec::new();
+    // city -> (min, max, total, count) in tenths
+    let mut expected = BTreeMap::<String, (i32, i32, i32, u32)>::new();
+    // every city at least once, then a bunch more at random
+    let extra: Vec<usize> = (0..CITIES * 3).map(|_| rng.gen_range(0..CITIES)).collect();
+    for i in (0..CITIES).chain(extra) {
+        let city = format!("city{i}");
+        let tenths = rng.gen_range(-999..=999);
+        data.extend_from_slice(format!("{city};{}\n", Temperature::from_tenths(tenths)).as_bytes());
+        let e = expected.entry(city).or_insert((tenths, tenths, 0, 0));
+        *e = (e.0.min(tenths), e.1.max(tenths), e.2 + tenths, e.3 + 1);
+    }
+
+    let expected: Vec<String> = expected
+        .into_iter()
+        .map(|(city, (min, max, total, count))| {
+            let mean = Temperature::from_tenths(total) / count;
+            let (min, max) = (Temperature::from_tenths(min), Temperature::from_tenths(max));
+            format!("{city}={min}/{mean}/{max}")
+        })
+        .collect();
+    for strategy in [Strategy::Lines, Strategy::Chunked] {
+        let config = Config {
+            strategy,
+            ..Config::default()
+        };
+        let actual: Vec<String> = summarize(process_data(&data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| format!("{city}={stats}"))
+            .collect();
+        assert_eq!(actual.len(), CITIES);
+        assert_eq!(actual, expected);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parallel_summarize() {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    let mut rng = SmallRng::seed_from_u64(2);
+    // Well above the thresholds, with cities hashed into a random order. Fewer cities with
+    // histograms because they're 8K each.
+    for (cities, histogram) in [(100_000, false), (5_000, true)] {
+        let mut data = Vec::new();
+        for i in 0..cities * 2 {
+            let tenths = rng.gen_range(-999..=999);
+            let line = format!("city{};{}\n", i % cities, Temperature::from_tenths(tenths));
+            data.extend_from_slice(line.as_bytes());
+        }
+
+        let config = Config {
+            histogram,
+            ..Config::default()
+        };
+        let serial = Config {
+            single_threaded: true,
+            ..config
+        };
+        let parallel = summarize(process_data(&data, config, None).unwrap());
+        assert_eq!(parallel.len(), cities);
+        assert!(parallel.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            format!("{parallel:?}"),
+            format!(
+                "{:?}",
+                summarize(process_data(&data, serial, None).unwrap())
+            )
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_process_reader() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let expected = format!(
+        "{:?}",
+        summarize(process_data(data, Config::default(), None).unwrap())
+    );
+    let serial = Config {
+        single_threaded: true,
+        ..Config::default()
+    };
+    // tiny chunks to make sure lines get stitched back together properly
+    for chunk_size in [1, 7, 64, 1000, READER_CHUNK_SIZE] {
+        for config in [Config::default(), serial] {
+            let results = process_reader(&data[..], config, chunk_size).unwrap();
+            assert_eq!(format!("{:?}", summarize(results)), expected);
+        }
+    }
+
+    // errors from processing and reading both come through
+    let strict = Config {
+        strict: true,
+        ..Config::default()
+    };
+    let err = process_reader(&b"Paris;12.3\nOslo;1\n"[..], strict, 4).unwrap_err();
+    assert!(matches!(err, Error::Parse(_)));
+
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+    }
+    for config in [Config::default(), serial] {
+        let err = process_reader(Broken, config, 16).unwrap_err();
+        assert_eq!(err, Error::Io("broken".into()));
+    }
+
+    // a stream without any newlines stops at the line limit rather than running out of memory
+    let endless = || std::io::repeat(b'x');
+    for config in [Config::default(), serial] {
+        let err = process_reader_with_max_line(endless(), config, 4096, 100_000).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to read input: line longer than 100000 bytes"
+        );
+    }
+    let err = process_reader(endless().take(3 * MAX_LINE_BYTES as u64), serial, 4096).unwrap_err();
+    assert_eq!(
+        err,
+        Error::Io(format!("line longer than {MAX_LINE_BYTES} bytes"))
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_compressed() {
+    // the fixtures have a .txt extension anyway, it's only the contents that matter
+    for (data, compression) in [
+        (
+            &include_bytes!("../testdata/compressed-gzip.txt")[..],
+            Compression::Gzip,
+        ),
+        (
+            include_bytes!("../testdata/compressed-zstd.txt"),
+            Compression::Zstd,
+        ),
+        (
+            include_bytes!("../testdata/compressed-xz.txt"),
+            Compression::Xz,
+        ),
+    ] {
+        assert_eq!(Compression::detect(data), Some(compression));
+        let err = process_reader(data, Config::default(), 16).unwrap_err();
+        assert_eq!(err, Error::Compressed(compression));
+        assert_eq!(
+            err.to_string(),
+            format!("input is {compression} compressed, which isn't supported")
+        );
+    }
+    for data in [
+        &include_bytes!("../testdata/measurements-3.txt")[..],
+        include_bytes!("../testdata/bom.txt"),
+        b"",
+        b"\x1f",
+        b"(\xb5/",
+    ] {
+        assert_eq!(Compression::detect(data), None);
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+#[test]
+fn test_deterministic() {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    // enough rows per city for f64 totals to depend on the order they're added up in
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut data = Vec::new();
+    for _ in 0..100_000 {
+        let city = rng.gen_range(0..100);
+        let tenths = rng.gen_range(-999..=999);
+        let line = format!("city{city};{}\n", Temperature::from_tenths(tenths));
+        data.extend_from_slice(line.as_bytes());
+    }
+
+    let mut configs = Vec::new();
+    for strategy in [Strategy::Lines, Strategy::Chunked] {
+        configs.push(Config {
+            strategy,
+            ..Config::default()
+        });
+        configs.push(Config {
+            strategy,
+            float_accum: true,
+            ..Config::default()
+        });
+    }
+    configs.push(Config {
+        histogram: true,
+        ..Config::default()
+    });
+    configs.push(Config {
+        min_chunk_bytes: NonZeroU32::new(4096),
+        ..Config::default()
+    });
+    #[cfg(feature = "tdigest")]
+    configs.push(Config {
+        quantile: Quantile::new(0.99),
+        ..Config::default()
+    });
+
+    for config in configs {
+        let serial = Config {
+            single_threaded: true,
+            ..config
+        };
+        let expected = format!(
+            "{:?}",
+            summarize(process_data(&data, serial, None).unwrap())
+        );
+        for threads in [1, 3, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            for _ in 0..5 {
+                let output = pool.install(|| {
+                    format!(
+                        "{:?}",
+                        summarize(process_data(&data, config, None).unwrap())
+                    )
+                });
+                assert!(output == expected, "{threads} threads, {config:?}");
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "debug-internals"))]
+#[test]
+fn test_process_data_unmerged() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let maps = process_data_unmerged(data, Config::default());
+    assert!(!maps.is_empty());
+    assert!(maps.iter().all(|map| map.len() <= 10_000));
+    let merged: ResultsMap = maps.into_iter().sum();
+    assert_eq!(
+        raw_stats(&merged),
+        raw_stats(&process_data(data, Config::default(), None).unwrap())
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_max_cities() {
+    let data = include_bytes!("../testdata/max-cities.txt");
+    let config = Config {
+        max_cities: NonZeroU16::new(2),
+        ..Config::default()
+    };
+    let expected = [
+        ("(other)", "16.0/19.2/25.2"),
+        ("Oslo", "-5.0/-4.0/-3.0"),
+        ("Paris", "12.3/13.2/14.1"),
+    ];
+    for config in [
+        config,
+        Config {
+            strategy: Strategy::Chunked,
+            ..config
+        },
+        Config {
+            safe: true,
+            ..config
+        },
+        Config {
+            intern: true,
+            ..config
+        },
+    ] {
+        let summary: Vec<_> = summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| (city.to_string(), stats.to_string()))
+            .collect();
+        assert_eq!(
+            summary,
+            expected.map(|(c, s)| (c.into(), s.into())),
+            "{config:?}"
+        );
+    }
+
+    // merging two full maps puts the cities that don't fit into the same bucket
+    let ingest = |lines: &[&str]| {
+        let mut results = ResultsMap::new(config);
+        for line in lines {
+            results.ingest(Row::parse(BStr::new(line)).unwrap());
+        }
+        results.into_owned()
+    };
+    let a = ingest(&["Paris;1.0", "Oslo;2.0", "Rome;3.0"]);
+    let b = ingest(&["Lima;4.0", "Paris;5.0", "Cairo;6.0", "Cairo;7.0"]);
+    assert_eq!((a.len(), b.len()), (3, 3));
+    let merged = a + b;
+    assert_eq!(merged.len(), 3);
+    let count = |city: &str| merged.map[BStr::new(city)].count;
+    assert_eq!(
+        (count("Paris"), count("Oslo"), count(OTHER_CITY)),
+        (2, 1, 4)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_time_window() {
+    assert_eq!(TimeWindow::new(Some(5), Some(5)), None);
+    assert_eq!(TimeWindow::new(None, Some(0)), None);
+    assert!(TimeWindow::new(Some(u32::MAX - 1), None).is_some());
+
+    let data = include_bytes!("../testdata/timestamps.txt");
+    let run = |config| {
+        summarize(process_data(data, config, None).unwrap())
+            .into_iter()
+            .map(|(city, stats)| format!("{city}={stats}"))
+            .collect::<Vec<_>>()
+    };
+    let window = |since, until| Config {
+        time_window: TimeWindow::new(since, until),
+        ..Config::default()
+    };
+    // the first three rows, in the middle of the input's timestamps
+    let config = window(Some(1_700_000_000), Some(1_700_007_200));
+    for config in [
+        config,
+        Config {
+            strict: true,
+            ..config
+        },
+        Config {
+            strategy: Strategy::Chunked,
+            ..config
+        },
+    ] {
+        assert_eq!(run(config), ["Oslo=-3.0/-3.0/-3.0", "Paris=12.3/13.2/14.1"]);
+    }
+    assert_eq!(
+        run(window(None, None)),
+        [
+            "Oslo=-5.0/-4.0/-3.0",
+            "Paris=12.3/15.5/20.0",
+            "Rome=17.0/23.5/30.0"
+        ]
+    );
+    assert_eq!(
+        run(window(Some(1_700_010_000), None)),
+        ["Paris=20.0/20.0/20.0"]
+    );
+
+    // a timestamp that isn't a number is skipped, or an error in strict mode
+    let strict = Config {
+        strict: true,
+        ..config
+    };
+    assert!(Row::parse_with(BStr::new("17e8;Paris;1.0"), &config).is_none());
+    let err = Row::parse_strict(BStr::new("17e8;Paris;1.0"), &strict).unwrap_err();
+    assert_eq!(err.reason, "invalid timestamp");
+    assert_eq!(
+        Row::parse_timestamp(b"99999999999999999999999"),
+        Some(u64::MAX)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_expected_cities() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let run = |config| format!("{:?}", summarize(process_data(data, config, None).unwrap()));
+    let expected = run(Config::default());
+    for expected_cities in [1, 413, 10_000, 100_000] {
+        for strategy in [Strategy::Lines, Strategy::Chunked] {
+            for single_threaded in [false, true] {
+                let config = Config {
+                    expected_cities,
+                    strategy,
+                    single_threaded,
+                    ..Config::default()
+                };
+                assert_eq!(run(config), expected, "{config:?}");
+            }
+        }
+    }
+
+    // Count how many times each map has to grow (rehashing everything in it) while adding all
+    // the cities. With enough room up front, it never does.
+    let mut maps = [ResultsMap::with_capacity(10_000), ResultsMap::default()];
+    let mut growths = [0, 0];
+    for line in data.lines() {
+        let row = Row::parse(line.as_bstr()).unwrap();
+        for (results, growths) in maps.iter_mut().zip(&mut growths) {
+            let capacity = results.map.capacity();
+            results.ingest_raw(row.city, row.temp);
+            if results.map.capacity() != capacity {
+                *growths += 1;
+            }
+        }
+    }
+    assert_eq!(growths[0], 0);
+    assert!(growths[1] >= 10, "{growths:?}");
+    let [sized, default] = maps;
+    assert_eq!(summarize(sized), summarize(default));
+}
+
+#[cfg(test)]
+#[test]
+fn test_min_chunk_bytes() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let run = |min_chunk_bytes| {
+        let config = Config {
+            min_chunk_bytes,
+            ..Config::default()
+        };
+        format!("{:?}", summarize(process_data(data, config, None).unwrap()))
+    };
+    let expected = run(None);
+    for min_len in [1, 10, 1000, 100_000, data.len() as u32, u32::MAX] {
+        assert_eq!(run(NonZeroU32::new(min_len)), expected, "{min_len}");
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+#[test]
+fn test_split_in_half() {
+    let data = b"aa;1.0\nbbbb;2.0\ncc;3.0\n";
+    assert_eq!(
+        split_in_half(data, 0, b'\n'),
+        (&b"aa;1.0\nbbbb;2.0\n"[..], Some(&b"cc;3.0\n"[..]))
+    );
+    assert_eq!(
+        split_in_half(data, 7, b'\n'),
+        (&b"aa;1.0\nbbbb;2.0\n"[..], Some(&b"cc;3.0\n"[..]))
+    );
+    // the second half would be too small
+    assert_eq!(split_in_half(data, 8, b'\n'), (&data[..], None));
+    assert_eq!(split_in_half(b"", 0, b'\n'), (&b""[..], None));
+    assert_eq!(
+        split_in_half(b"no newline at all", 0, b'\n'),
+        (&b"no newline at all"[..], None)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_sample() {
+    assert!(Sample::new(0.0, 0).is_none());
+    assert!(Sample::new(1.1, 0).is_none());
+    assert!(Sample::new(f64::NAN, 0).is_none());
+
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let lines = data.lines().count() as f64;
+    let count = |config: Config| {
+        let results = summarize(process_data(data, config, None).unwrap());
+        results.iter().map(|(_, stats)| stats.count()).sum::<u64>() as f64
+    };
+    let sampled = |fraction, seed| Config {
+        sample: Sample::new(fraction, seed),
+        ..Config::default()
+    };
+
+    assert_eq!(count(sampled(1.0, 0)), lines);
+    for fraction in [0.01, 0.1, 0.5] {
+        let n = count(sampled(fraction, 7));
+        assert!(
+            (n / lines - fraction).abs() < 0.02,
+            "{fraction}: {n} of {lines}"
+        );
+        // picked by contents, so it's the same every time and however the work is split
+        let serial = Config {
+            single_threaded: true,
+            strategy: Strategy::Chunked,
+            ..sampled(fraction, 7)
+        };
+        assert_eq!(count(serial), n);
+    }
+    // different seeds pick different lines
+    let line = b"Paris;12.3";
+    assert!((0..64).any(|seed| Sample::new(0.5, seed).unwrap().includes(line)));
+    assert!((0..64).any(|seed| !Sample::new(0.5, seed).unwrap().includes(line)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_city_histogram() {
+    let data = include_bytes!("../testdata/mode.txt");
+    let bins = |city, config| {
+        city_histogram(data, BStr::new(city), config)
+            .iter()
+            .map(|(temp, count)| (temp.to_string(), count))
+            .collect::<Vec<_>>()
+    };
+    let expected = [("9.9", 1), ("12.3", 3), ("15.0", 1), ("20.0", 1)]
+        .map(|(temp, count)| (temp.to_string(), count));
+    assert_eq!(bins("Paris", Config::default()), expected);
+    let serial = Config {
+        single_threaded: true,
+        ..Config::default()
+    };
+    assert_eq!(bins("Paris", serial), expected);
+    assert_eq!(
+        bins("Oslo", serial),
+        [("-3.0".into(), 2), ("4.5".into(), 1)]
+    );
+    assert_eq!(bins("Berlin", Config::default()), []);
+    assert_eq!(bins("Pari", Config::default()), []);
+}
+
+#[cfg(test)]
+#[test]
+fn test_global_histogram() {
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    let rows = data.lines().count() as u64;
+    for single_threaded in [false, true] {
+        for (strategy, intern) in [
+            (Strategy::Lines, false),
+            (Strategy::Chunked, false),
+            (Strategy::Chunked, true),
+        ] {
+            let config = Config {
+                global_histogram: true,
+                single_threaded,
+                strategy,
+                intern,
+                ..Config::default()
+            };
+            // it doesn't need per-city histograms, or chunks to fit them in
+            assert_eq!(config.normalize().strategy, strategy);
+            let mut results = process_data(data, config, None).unwrap();
+            let global = results.take_global_histogram().unwrap();
+            assert_eq!(global.total(), rows, "{strategy:?} intern={intern}");
+            assert!(summarize(results)
+                .iter()
+                .all(|(_, stats)| stats.mode.is_none()));
+        }
+    }
+
+    // both at once keeps the mode
+    let config = Config {
+        global_histogram: true,
+        histogram: true,
+        ..Config::default()
+    };
+    let mut results = process_data(data, config, None).unwrap();
+    assert_eq!(results.take_global_histogram().unwrap().total(), rows);
+    assert!(summarize(results)
+        .iter()
+        .all(|(_, stats)| stats.mode.is_some()));
+
+    // empty input still gets an (empty) histogram, and none unless asked for
+    let config = Config {
+        global_histogram: true,
+        ..Config::default()
+    };
+    let mut results = process_data(b"", config, None).unwrap();
+    assert_eq!(results.take_global_histogram().unwrap().total(), 0);
+    let mut results = process_data(data, Config::default(), None).unwrap();
+    assert!(results.take_global_histogram().is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_self_check() {
+    let data = include_bytes!("../testdata/measurements-20.txt");
+    for (_, stats) in summarize(process_data(data, Config::default(), None).unwrap()) {
+        assert_eq!(stats.self_check(), Ok(()));
+    }
+
+    let t = Temperature::from_tenths;
+    let good = FinalStats {
+        mean: t(10),
+        min: t(-5),
+        max: t(20),
+        mode: None,
+        quantile: None,
+        first: None,
+        last: None,
+        total: 20,
+        count: 2,
+    };
+    assert_eq!(good.self_check(), Ok(()));
+    // what a wrapped total would look like
+    let overflowed = FinalStats {
+        mean: t(-30),
+        total: -60,
+        ..good
+    };
+    assert_eq!(overflowed.self_check(), Err("mean is below min"));
+    let high = FinalStats {
+        mean: t(21),
+        ..good
+    };
+    assert_eq!(high.self_check(), Err("mean is above max"));
+    let empty = FinalStats { count: 0, ..good };
+    assert_eq!(empty.self_check(), Err("no measurements"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_weighted() {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    // the same rows weighted, and repeated that many times
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut weighted = Vec::new();
+    let mut expanded = Vec::new();
+    for _ in 0..2000 {
+        let city = format!("city{}", rng.gen_range(0..50));
+        let temp = Temperature::from_tenths(rng.gen_range(-999..=999));
+        let weight = rng.gen_range(1..=20);
+        weighted.extend_from_slice(format!("{city};{temp};{weight}\n").as_bytes());
+        for _ in 0..weight {
+            expanded.extend_from_slice(format!("{city};{temp}\n").as_bytes());
+        }
+    }
+
+    let run = |data: &[u8], config| summarize(process_data(data, config, None).unwrap());
+    for histogram in [false, true] {
+        let expected = run(
+            &expanded,
+            Config {
+                histogram,
+                ..Config::default()
+            },
+        );
+        for (strategy, strict, safe) in [
+            (Strategy::Lines, false, false),
+            (Strategy::Chunked, false, false),
+            (Strategy::Lines, true, true),
+        ] {
+            let config = Config {
+                weighted: true,
+                histogram,
+                strategy,
+                strict,
+                safe,
+                ..Config::default()
+            };
+            assert_eq!(run(&weighted, config), expected);
+        }
+    }
+
+    // the weight is always last, whatever order the other fields are in
+    let config = Config {
+        weighted: true,
+        order: FieldOrder::TempCity,
+        ..Config::default()
+    };
+    let summary = run(b"12.0;Paris;3\n15.0;Paris;1\n", config);
+    assert_eq!(summary[0].1.count(), 4);
+    assert_eq!(summary[0].1.to_string(), "12.0/12.8/15.0");
+
+    // missing or invalid weights get skipped, or are errors in strict mode
+    let config = Config {
+        weighted: true,
+        ..Config::default()
+    };
+    for line in [
+        "Paris;12.3",
+        "Paris;12.3;0",
+        "Paris;12.3;-1",
+        "Paris;12.3;1.5",
+        "Paris;12.3;",
+    ] {
+        assert!(Row::parse_with(line.into(), &config).is_none(), "{line}");
+        let strict = Config {
+            strict: true,
+            ..config
+        };
+        assert!(Row::parse_strict(line.into(), &strict).is_err(), "{line}");
+    }
+    let row = Row::parse_with("Paris;12.3;4294967295".into(), &config).unwrap();
+    assert_eq!(row.weight, u32::MAX);
+    assert!(Row::parse_with("Paris;12.3;4294967296".into(), &config).is_none());
+
+    // weights near u32::MAX would overflow a u32 count or histogram bin after a couple of rows,
+    // which is an error rather than a wrong mean
+    let data = b"Oslo;1.0;4000000000\nOslo;3.0;4000000000\nOslo;3.0;4000000000\n";
+    let overflow = Err(Error::Overflow {
+        city: "Oslo".into(),
+    });
+    for histogram in [false, true] {
+        for strategy in [Strategy::Lines, Strategy::Chunked] {
+            let config = Config {
+                weighted: true,
+                histogram,
+                strategy,
+                ..Config::default()
+            };
+            let result = process_data(data, config, None).map(summarize);
+            if cfg!(feature = "wide-total") && !histogram {
+                assert_eq!(result.unwrap()[0].1.to_string(), "1.0/2.3/3.0");
+            } else {
+                assert_eq!(result, overflow, "histogram={histogram} {strategy:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_visit() {
+    use std::sync::Mutex;
+
+    let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+    for config in [
+        Config::default(),
+        Config {
+            strategy: Strategy::Chunked,
+            histogram: true,
+            ..Config::default()
+        },
+        Config {
+            single_threaded: true,
+            ..Config::default()
+        },
+        Config {
+            strict: true,
+            safe: true,
+            ..Config::default()
+        },
+        // only what's left after filtering
+        Config {
+            min_temp: Temperature::from_tenths(0),
+            sample: Sample::new(0.5, 1),
+            ..Config::default()
+        },
+    ] {
+        let rows = Mutex::new(Vec::new());
+        let results = process_data_visit(data, config, None, |row| {
+            // the city name only lives as long as each call
+            rows.lock()
+                .unwrap()
+                .push((row.city().to_owned(), row.temp()));
+        })
+        .unwrap();
+        let rows = rows.into_inner().unwrap();
+        let visited = ResultsMap::from_rows(rows.iter().map(|(city, temp)| (&city[..], *temp)));
+        assert_eq!(raw_stats(&visited), raw_stats(&results), "{config:?}");
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_whitespace() {
+    let config = Config {
+        whitespace: true,
+        ..Config::default()
+    };
+    let expected = [
+        ("Oslo", "-12.7/-3.7/4.5"),
+        ("Ouagadougou", "29.0/32.1/35.2"),
+        ("Paris", "9.9/12.1/14.1"),
+        ("Reykjavík", "-0.8/-0.2/0.4"),
+    ];
+    for data in [
+        &include_bytes!("../testdata/whitespace-spaces.txt")[..],
+        include_bytes!("../testdata/whitespace-tabs.txt"),
+    ] {
+        for config in [
+            config,
+            Config {
+                strict: true,
+                ..config
+            },
+            Config {
+                strategy: Strategy::Chunked,
+                ..config
+            },
+        ] {
+            let summary: Vec<_> = summarize(process_data(data, config, None).unwrap())
+                .into_iter()
+                .map(|(city, stats)| (city.to_string(), stats.to_string()))
+                .collect();
+            assert_eq!(
+                summary,
+                expected.map(|(c, s)| (c.into(), s.into())),
+                "{config:?}"
+            );
+        }
+    }
+
+    let row = |line: &str, config: &Config| {
+        Row::parse_with(BStr::new(line), config).map(|row| (row.city.to_string(), row.temp))
+    };
+    let parsed = |city: &str, tenths| Some((city.to_string(), Temperature::from_tenths(tenths)));
+    assert_eq!(row(" \tParis \t 12.3 \r", &config), parsed("Paris", 123));
+    assert_eq!(row("Paris12.3", &config), None);
+    let split_last = Config {
+        split_last: true,
+        ..config
+    };
+    assert_eq!(row("Da Nang  33.7", &split_last), parsed("Da Nang", 337));
+    let weighted = Config {
+        weighted: true,
+        ..config
+    };
+    assert_eq!(row("Paris 12.3\t 4", &weighted), parsed("Paris", 123));
+    let strict = Config {
+        strict: true,
+        ..config
+    };
+    // the name ends at the first space, leaving an extra field
+    let err = Row::parse_strict(BStr::new("Da Nang 33.7"), &strict).unwrap_err();
+    assert_eq!(err.reason, "wrong number of fields");
+}
+
+#[cfg(test)]
+#[test]
+fn test_fields() {
+    let fields = |line: &str, quoted| {
+        let config = Config {
+            delimiter: b',',
+            quoted,
+            ..Config::default()
+        };
+        config
+            .fields(line.as_bytes())
+            .map(|f| f.to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(fields("a,\"b,c\",d", false), ["a", "\"b", "c\"", "d"]);
+    assert_eq!(fields("a,\"b,c\",d", true), ["a", "b,c", "d"]);
+    assert_eq!(fields("\"say \"\"hi\"\"\",", true), ["say \"\"hi\"\"", ""]);
+    // quotes only count at the start of a field, and anything after the closing one is dropped
+    assert_eq!(fields("a\"b,\"c\"d,e", true), ["a\"b", "c", "e"]);
+    // no closing quote
+    assert_eq!(fields("a,\"b,c", true), ["a", "b,c"]);
+    assert_eq!(fields("", true), [""]);
+
+    let config = Config {
+        delimiter: b',',
+        quoted: true,
+        ..Config::default()
+    };
+    assert!(config.csv_columns(b"city,city", "city", "city").is_err());
+    let wide = format!("{}city,temperature", "x,".repeat(254));
+    assert!(config
+        .csv_columns(wide.as_bytes(), "city", "temperature")
+        .is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_split_records() {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    let check = |data: &[u8], sep| {
+        let expected: Vec<&[u8]> = data.split(|&b| b == sep).collect();
+        assert_eq!(
+            split_records(data, sep).collect::<Vec<_>>(),
+            expected,
+            "{:?}",
+            data.as_bstr()
+        );
+    };
+    for data in [
+        &b""[..],
+        b"\n",
+        b"\n\n",
+        b"a",
+        b"a\n",
+        b"\na",
+        b"ab\ncd",
+        b"ab\ncd\n",
+    ] {
+        check(data, b'\n');
+    }
+
+    // Random buffers of all sorts of lengths, mostly a few separators to a line and some long
+    // runs without any, so that the SIMD search goes through its whole-vector loop too.
+    let mut rng = SmallRng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let len = rng.gen_range(0..300);
+        let sep_odds = rng.gen_range(0.0..0.3);
+        let data: Vec<u8> = (0..len)
+            .map(|_| {
+                if rng.gen_bool(sep_odds) {
+                    b'\n'
+                } else {
+                    rng.gen_range(b'a'..=b'z')
+                }
+            })
+            .collect();
+        check(&data, b'\n');
+        check(&data, b'a');
+    }
+}
+
+#[cfg(all(test, feature = "wide-total"))]
+#[test]
+fn test_wide_total() {
+    // a couple of partial results that have each seen quadrillions of rows, so that their
+    // combined total overflows an i64
+    let partial = || {
+        let mut results = ResultsMap::from_rows([(&b"Paris"[..], Temperature::from_tenths(0))]);
+        let stats = results.map.get_mut(BStr::new("Paris")).unwrap();
+        stats.count = 5_000_000_000_000_000;
+        stats.total = 99 * stats.count as Total * 10;
+        results
+    };
+    let mut results = partial() + partial();
+    results.ingest_raw(b"Paris", Temperature::from_tenths(990));
+    assert!(results.map[BStr::new("Paris")].total > i64::MAX as Total);
+
+    let summary = summarize(results);
+    let stats = &summary[0].1;
+    assert_eq!(stats.count(), 10_000_000_000_000_001);
+    assert_eq!(stats.mean(), Temperature::from_tenths(990));
+    assert_eq!(stats.precise_mean(Unit::Celsius, 3).to_string(), "99.000");
+}
@@ -1,231 +1,1508 @@
-use std::fmt;
 use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use bstr::{BStr, BString, ByteSlice};
 use memmap2::Mmap;
+use onebrc::{
+    byte_range, city_histogram, dataset_info, finalize_unsorted, first_records, process_buffers,
+    process_reader_with_max_line, shard, sort_summary, split_header, strip_bom, summarize_as_seen,
+    Compression, FinalStats, Histogram, Progress, Temperature, MAX_LINE_BYTES, READER_CHUNK_SIZE,
+};
 
-mod temperature;
-use temperature::Temperature;
+mod diff;
+mod memory;
+mod options;
+mod output;
+mod profile;
+mod watch_dir;
+use options::Options;
+use output::{
+    write_atomic, write_exact_means, write_global_histogram, write_histogram, write_results,
+};
+use profile::Profile;
 
-type HashMap<K, V> = ahash::AHashMap<K, V>;
+/// Find all the city names that aren't valid UTF-8. This is only checked once per unique city
+/// after aggregation, so the hot path doesn't pay for it.
+fn invalid_utf8_cities(summary: &[(BString, FinalStats)]) -> Vec<&BStr> {
+    summary
+        .iter()
+        .map(|(city, _)| city.as_bstr())
+        .filter(|city| city.to_str().is_err())
+        .collect()
+}
+
+/// A single reading and the city it's from
+type CityReading<'a> = (&'a BStr, Temperature);
 
-#[derive(Debug, Clone, Copy)]
-struct Row<'a> {
-    city: &'a BStr,
-    temp: Temperature,
+/// The hottest and coldest single readings of all, each with its city, as `(hottest, coldest)`.
+/// These come from every city's max and min after aggregation, so the hot path doesn't track
+/// anything extra. Ties go to whichever city comes first in the summary, which is the first
+/// alphabetically unless it's in `--order-as-seen` order.
+fn extremes(summary: &[(BString, FinalStats)]) -> Option<(CityReading<'_>, CityReading<'_>)> {
+    // min_by_key keeps the first of equal elements, max_by_key would keep the last
+    let (hot_city, hot) = summary
+        .iter()
+        .min_by_key(|(_, stats)| std::cmp::Reverse(stats.max()))?;
+    let (cold_city, cold) = summary.iter().min_by_key(|(_, stats)| stats.min())?;
+    Some((
+        (hot_city.as_bstr(), hot.max()),
+        (cold_city.as_bstr(), cold.min()),
+    ))
 }
 
-impl<'a> Row<'a> {
-    /// Parse a single row. SPICY HOT!
-    fn parse(s: &'a BStr) -> Option<Self> {
-        // split at the location of the ';'. This means the first character of what we send to
-        // Temperature::parse is ';' but that's fine, it'll be ignored there (and saves us
-        // extra bounds checks manually slicing that away here).
-        // And since lines are short (only a few dozen bytes) it's faster to use a basic naive
-        // linear byte-by-byte search that s.iter().position() compiles down to rather than
-        // something like memchr.
-        let (city, temp_s) = s.split_at(s.iter().position(|b| *b == b';')?);
-        let temp = Temperature::parse(temp_s);
-        Some(Self {
-            city: BStr::new(city),
-            temp,
-        })
+/// Drop any cities that shouldn't be reported. This happens after aggregation so that it never
+/// affects the stats of the cities that remain.
+///
+/// With `--only` and/or `--prefix`, a city is kept if it matches any of them.
+fn filter_results(summary: &mut Vec<(BString, FinalStats)>, opts: &Options) {
+    if !opts.only.is_empty() || !opts.prefix.is_empty() {
+        summary.retain(|(city, _)| {
+            opts.only.contains(city) || opts.prefix.iter().any(|p| city.starts_with(p))
+        });
+    }
+    if !opts.exclude.is_empty() {
+        summary.retain(|(city, _)| !opts.exclude.contains(city));
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Stats {
-    total: Temperature,
-    count: u32,
-    min: Temperature,
-    max: Temperature,
+/// Run `f` `count` times (at least once) and log how long each run took, followed by the median,
+/// for warm-cache benchmarking. Stops at the first error. Returns the last result, and how long
+/// each run took in order.
+fn repeat_timed<T, E>(
+    count: usize,
+    log: &mut impl Write,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<(T, Vec<Duration>), E> {
+    let mut times = Vec::with_capacity(count);
+    let mut result = None;
+    for i in 1..=count.max(1) {
+        let start = Instant::now();
+        result = Some(f()?);
+        let elapsed = start.elapsed();
+        // errors writing to stderr aren't worth caring about
+        let _ = writeln!(log, "iteration {i}: {:.3}s", elapsed.as_secs_f64());
+        times.push(elapsed);
+    }
+    let mut sorted = times.clone();
+    sorted.sort_unstable();
+    let median = match sorted.len() {
+        n if n % 2 == 0 => (sorted[n / 2 - 1] + sorted[n / 2]) / 2,
+        n => sorted[n / 2],
+    };
+    let _ = writeln!(log, "median: {:.3}s", median.as_secs_f64());
+    Ok((result.expect("ran at least once"), times))
 }
 
-#[derive(Debug, Clone, Copy)]
-struct FinalStats {
-    mean: Temperature,
-    min: Temperature,
-    max: Temperature,
+/// How many threads do the aggregating, for `--bench-log`
+fn thread_count(opts: &Options) -> usize {
+    #[cfg(feature = "rayon")]
+    if !opts.config().single_threaded {
+        return rayon::current_num_threads();
+    }
+    let _ = opts;
+    1
 }
 
-impl fmt::Display for FinalStats {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}/{}/{}", self.min, self.mean, self.max)
+/// Append a row to the `--bench-log` CSV file for each timed run, after a header if the file is
+/// new. The version is whatever `ONEBRC_GIT_DESCRIBE` was set to at build time (like
+/// `ONEBRC_GIT_DESCRIBE=$(git describe --always --dirty) cargo build --release`), or empty.
+fn write_bench_log(
+    path: &Path,
+    times: &[Duration],
+    input_bytes: usize,
+    opts: &Options,
+) -> io::Result<()> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let new = file.metadata()?.len() == 0;
+    let mut out = io::BufWriter::new(file);
+    if new {
+        writeln!(
+            out,
+            "timestamp,version,input_bytes,threads,strategy,elapsed_ms"
+        )?;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let version = option_env!("ONEBRC_GIT_DESCRIBE").unwrap_or("");
+    let threads = thread_count(opts);
+    for elapsed in times {
+        writeln!(
+            out,
+            "{timestamp},{version},{input_bytes},{threads},{},{:.3}",
+            opts.strategy,
+            elapsed.as_secs_f64() * 1000.0
+        )?;
+    }
+    out.flush()
+}
+
+/// mmap a whole file, accessible as a big &[u8]. No UTF-8 check
+///
+/// Returns None for an empty file, which has nothing to process anyway. Zero-length mappings
+/// aren't allowed by POSIX or Windows, so this doesn't rely on how memmap2 works around that.
+fn map_file(path: &Path) -> Result<Option<Mmap>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("failed to open '{}': {err}", path.display()))?;
+    let len = file
+        .metadata()
+        .map_err(|err| format!("failed to stat '{}': {err}", path.display()))?
+        .len();
+    if len == 0 {
+        return Ok(None);
+    }
+    let mmap = unsafe { Mmap::map(&file) }
+        .map_err(|err| format!("failed to mmap '{}': {err}", path.display()))?;
+    // whatever the file extension says
+    if let Some(compression) = Compression::detect(&mmap) {
+        return Err(format!(
+            "'{}' is {compression} compressed, which isn't supported",
+            path.display()
+        ));
     }
+    Ok(Some(mmap))
 }
 
-impl Stats {
-    fn new(temp: Temperature) -> Self {
-        Self {
-            total: temp,
-            count: 1,
-            min: temp,
-            max: temp,
+/// Open and mmap the input file, or all of the files matching `--glob`. Empty files are left
+/// out, so this can return an empty Vec.
+fn open_inputs(opts: &Options) -> Result<Vec<Mmap>, String> {
+    let Some(pattern) = &opts.glob else {
+        return Ok(map_file(&opts.path)?.into_iter().collect());
+    };
+
+    #[cfg(feature = "glob")]
+    {
+        let paths = glob::glob(pattern)
+            .map_err(|err| format!("invalid glob pattern '{pattern}': {err}"))?;
+        let mut mmaps = Vec::new();
+        let mut matched = false;
+        for path in paths {
+            let path = path.map_err(|err| err.to_string())?;
+            if path.is_dir() {
+                if !opts.quiet {
+                    eprintln!("warning: skipping directory '{}'", path.display());
+                }
+                continue;
+            }
+            matched = true;
+            mmaps.extend(map_file(&path)?);
         }
+        if !matched {
+            return Err(format!("no files match '{pattern}'"));
+        }
+        Ok(mmaps)
     }
 
-    fn finalize(self) -> FinalStats {
-        FinalStats {
-            mean: self.total / self.count,
-            min: self.min,
-            max: self.max,
+    #[cfg(not(feature = "glob"))]
+    Err(format!(
+        "can't expand '{pattern}', built without glob support"
+    ))
+}
+
+/// With `--header`, skip the `#` header line at the start of `data` (if there is one) and apply
+/// its settings to `opts`. With `--csv-header`, the first line names the columns instead.
+fn read_header<'a>(data: &'a [u8], opts: &mut Options) -> Result<&'a [u8], String> {
+    if opts.csv_header {
+        let (header, rest) = match data.find_byte(opts.config().record_sep) {
+            Some(pos) => (&data[..pos], &data[pos + 1..]),
+            None => (data, &[][..]),
+        };
+        opts.apply_csv_header(header)?;
+        return Ok(rest);
+    }
+    if !opts.header {
+        return Ok(data);
+    }
+    let (header, rest) = split_header(data);
+    if let Some(header) = header {
+        opts.apply_header(header)?;
+    }
+    Ok(rest)
+}
+
+/// Write the results to `--output` if it was given, otherwise stdout
+fn write_output(summary: &[(BString, FinalStats)], opts: &Options) -> io::Result<()> {
+    match &opts.output {
+        Some(path) => write_atomic(path, |out| write_results(out, summary, opts)),
+        None => {
+            // stdout is line buffered, which would mean a write for every city with
+            // `--format lines`
+            let mut stdout = io::BufWriter::new(io::stdout().lock());
+            write_results(&mut stdout, summary, opts).and_then(|()| stdout.flush())
         }
     }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "diff") {
+        std::process::exit(diff::run_diff(&args[1..], &mut io::stdout().lock()));
+    }
+
+    let mut opts =
+        Options::parse_with_env(args, |name| std::env::var(name).ok()).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            std::process::exit(2);
+        });
+    #[cfg(feature = "rayon")]
+    if let Some(threads) = opts.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .build_global()
+            .expect("nothing else sets up the global thread pool");
+    }
+    if let Some(dir) = &opts.watch_dir {
+        let Err(err) = watch_dir::run(&opts, dir);
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+    let start = Instant::now();
+    let mut profile = Profile::new(opts.profile);
+    // stdin gets read in chunks by process_reader instead
+    let mmaps = if opts.is_stdin() {
+        Vec::new()
+    } else {
+        open_inputs(&opts).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        })
+    };
+    let mut buffers: Vec<&[u8]> = Vec::with_capacity(mmaps.len());
+    for mmap in &mmaps {
+        let data = read_header(strip_bom(mmap), &mut opts).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        });
+        let record_sep = opts.config().record_sep;
+        buffers.push(match (opts.limit, opts.shard) {
+            (Some(limit), _) => first_records(data, limit, record_sep),
+            (None, Some((index, count))) => shard(data, index, count, record_sep),
+            (None, None) if opts.has_byte_range() => {
+                byte_range(data, opts.byte_range(), record_sep)
+            }
+            (None, None) => data,
+        });
+    }
+    let opts = opts;
+    profile.phase("open");
+
+    if opts.info {
+        let info = dataset_info(&buffers, opts.config());
+        println!("size: {} bytes", info.size);
+        println!("rows: ~{}", info.rows);
+        println!("cities: ~{}", info.cities);
+        return;
+    }
+
+    // progress display is only for humans watching, and needs to know the total size up front
+    let repeat = opts.repeat.unwrap_or(1);
+    let total_len: usize = buffers.iter().map(|data| data.len()).sum();
+    let show_progress = (opts.progress || opts.watch)
+        && !opts.quiet
+        && !opts.is_stdin()
+        && io::stderr().is_terminal();
+    let progress = show_progress.then(|| {
+        if opts.watch {
+            Progress::with_snapshot(total_len * repeat)
+        } else {
+            Progress::new(total_len * repeat)
+        }
+    });
 
-    fn update_row(&mut self, temp: Temperature) {
-        self.total += temp;
-        self.count += 1;
-        if temp < self.min {
-            self.min = temp;
+    // do all the main work
+    let merged_results = std::thread::scope(|scope| {
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel();
+        if let Some(progress) = &progress {
+            if opts.watch {
+                scope.spawn(move || progress.watch_until(stop_rx));
+            } else {
+                scope.spawn(move || progress.render_until(stop_rx));
+            }
         }
-        if temp > self.max {
-            self.max = temp;
+        let process = || {
+            if opts.is_stdin() {
+                let max_line = opts
+                    .max_line_bytes
+                    .map_or(MAX_LINE_BYTES, NonZeroUsize::get);
+                process_reader_with_max_line(
+                    io::stdin(),
+                    opts.config(),
+                    READER_CHUNK_SIZE,
+                    max_line,
+                )
+            } else {
+                process_buffers(&buffers, opts.config(), progress.as_ref())
+            }
+        };
+        let results = match opts.repeat {
+            Some(count) if !opts.quiet => repeat_timed(count, &mut io::stderr(), process),
+            Some(count) => repeat_timed(count, &mut io::sink(), process),
+            None if opts.bench_log.is_some() => repeat_timed(1, &mut io::sink(), process),
+            None => process().map(|results| (results, Vec::new())),
+        };
+        drop(stop_tx);
+        results
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    });
+    // merging happens within the same rayon reductions as the aggregating, so it's counted here too
+    profile.phase("aggregate");
+    let (mut merged_results, times) = merged_results;
+
+    // only after all the timed runs are done
+    if let Some(path) = &opts.bench_log {
+        if let Err(err) = write_bench_log(path, &times, total_len, &opts) {
+            eprintln!("error: failed to write '{}': {err}", path.display());
+            std::process::exit(1);
         }
     }
 
-    fn update_stats(&mut self, other: Stats) {
-        self.total += other.total;
-        self.count += other.count;
-        if other.min < self.min {
-            self.min = other.min;
+    if let Some(fraction) = opts.sample {
+        if !opts.quiet {
+            eprintln!(
+                "note: results are from a sample of about {}% of lines, min and max may be \
+                 less extreme than the real ones",
+                fraction * 100.0
+            );
         }
-        if other.max > self.max {
-            self.max = other.max;
+    }
+
+    if let Some(hist) = merged_results.take_global_histogram() {
+        let written = match &opts.global_histogram_file {
+            Some(path) => {
+                File::create(path).and_then(|mut file| write_global_histogram(&mut file, &hist))
+            }
+            None => write_global_histogram(&mut io::stderr().lock(), &hist),
+        };
+        if let Err(err) = written {
+            eprintln!("error: failed to write global histogram: {err}");
+            std::process::exit(1);
         }
     }
-}
 
-#[derive(Debug, Default)]
-struct ResultsMap {
-    map: HashMap<BString, Stats>,
-}
+    // summarize, split up so that finalizing and sorting are timed separately. With
+    // --order-as-seen the sort happens before finalizing, and is counted with it.
+    profile.skip();
+    let mut summary_results = if opts.order_as_seen {
+        let summary = summarize_as_seen(merged_results);
+        profile.phase("finalize");
+        summary
+    } else {
+        let mut summary = finalize_unsorted(merged_results);
+        profile.phase("finalize");
+        sort_summary(&mut summary, opts.config());
+        profile.phase("sort");
+        summary
+    };
+    filter_results(&mut summary_results, &opts);
 
-impl ResultsMap {
-    /// add a single row to these results
-    fn ingest(&mut self, row: Row) {
-        if let Some(stats) = self.map.get_mut(row.city) {
-            stats.update_row(row.temp);
-        } else {
-            self.map.insert(row.city.into(), Stats::new(row.temp));
+    if opts.self_check {
+        for (city, stats) in &summary_results {
+            if let Err(reason) = stats.self_check() {
+                eprintln!("error: self-check failed for city '{city}': {reason}");
+                std::process::exit(1);
+            }
         }
     }
 
-    /// combine with all of `other`'s results
-    fn merge(&mut self, other: ResultsMap) {
-        // special case if we're merging into an empty map, we can just assume the other map
-        // in-place
-        if self.map.is_empty() {
-            *self = other;
-            return;
+    if opts.validate_utf8 {
+        let invalid = invalid_utf8_cities(&summary_results);
+        if !invalid.is_empty() {
+            for city in invalid {
+                // Debug formatting shows the invalid bytes as escapes
+                eprintln!("error: invalid UTF-8 in city name {city:?}");
+            }
+            std::process::exit(1);
         }
+    }
 
-        for (city, stats) in other {
-            if let Some(my_stats) = self.map.get_mut(&city) {
-                my_stats.update_stats(stats);
-            } else {
-                self.map.insert(city, stats);
-            }
+    profile.skip();
+    if let Err(err) = write_output(&summary_results, &opts) {
+        match &opts.output {
+            Some(path) => eprintln!("error: failed to write '{}': {err}", path.display()),
+            None => eprintln!("error: failed to write output: {err}"),
         }
+        std::process::exit(1);
+    }
+    profile.phase("output");
+    // errors writing to stderr aren't worth caring about
+    let _ = profile.write(&mut io::stderr().lock());
+
+    if opts.debug_means {
+        write_exact_means(&mut io::stderr().lock(), &summary_results)
+            .expect("failed to write exact means");
     }
-}
 
-impl std::ops::Add for ResultsMap {
-    type Output = Self;
+    if let Some(city) = &opts.histogram_city {
+        let mut hist = Histogram::new();
+        for data in &buffers {
+            hist.merge(&city_histogram(data, city.as_bstr(), opts.config()));
+        }
+        write_histogram(&mut io::stderr().lock(), city, &hist).expect("failed to write histogram");
+    }
 
-    fn add(mut self, rhs: Self) -> Self::Output {
-        self.merge(rhs);
-        self
+    if opts.verbose && !opts.quiet {
+        eprintln!("cities: {}", summary_results.len());
+        if let Some(((hot_city, hot), (cold_city, cold))) = extremes(&summary_results) {
+            let convert = |temp| opts.unit.convert_rounded(temp, opts.rounding());
+            eprintln!("hottest: {} ({hot_city})", convert(hot));
+            eprintln!("coldest: {} ({cold_city})", convert(cold));
+        }
+        eprintln!("elapsed: {:.3}s", start.elapsed().as_secs_f64());
+        match memory::peak_rss() {
+            Some(bytes) => eprintln!("peak memory: {:.1} MiB", bytes as f64 / 1048576.0),
+            None => eprintln!("peak memory: unavailable"),
+        }
     }
 }
 
-impl std::iter::Sum for ResultsMap {
-    fn sum<I: Iterator<Item = Self>>(mut iter: I) -> Self {
-        let first = match iter.next() {
-            Some(x) => x,
-            None => return Self::default(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use onebrc::{process_data, summarize, Config, Temperature};
+
+    /// run the whole pipeline on some input data and return the output as a string
+    fn run(data: &[u8], args: &[&str]) -> String {
+        let args = args
+            .iter()
+            .map(|s| s.to_string())
+            .chain(["measurements.txt".into()]);
+        let mut opts = Options::parse(args).unwrap();
+        let data = read_header(strip_bom(data), &mut opts).unwrap();
+        let results = process_data(data, opts.config(), None).unwrap();
+        let mut summary = if opts.order_as_seen {
+            summarize_as_seen(results)
+        } else {
+            summarize(results)
         };
-        iter.fold(first, std::ops::Add::add)
+        filter_results(&mut summary, &opts);
+        let mut out = Vec::new();
+        write_results(&mut out, &summary, &opts).unwrap();
+        String::from_utf8(out).unwrap()
     }
-}
 
-impl IntoIterator for ResultsMap {
-    type Item = (BString, Stats);
-    type IntoIter = <HashMap<BString, Stats> as IntoIterator>::IntoIter;
+    #[test]
+    fn test_no_final_newline() {
+        let data = include_bytes!("../testdata/mode.txt");
+        let expected = "{Oslo=-3.0/-0.5/4.5, Paris=9.9/13.6/20.0}";
+        assert_eq!(run(data, &[]), format!("{expected}\n"));
+        assert_eq!(run(data, &["--no-final-newline"]), expected);
+        assert_eq!(
+            run(data, &["--no-final-newline", "--format", "compact"]),
+            "{Oslo=-3/-0.5/4.5, Paris=9.9/13.6/20}"
+        );
+        assert_eq!(run(b"", &["--no-final-newline"]), "{}");
+        let args = ["--no-final-newline", "--format", "csv", "-"].map(String::from);
+        assert!(Options::parse(args).is_err());
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.map.into_iter()
+    #[test]
+    fn test_mode() {
+        let data = include_bytes!("../testdata/mode.txt");
+        assert_eq!(
+            run(data, &["--with-mode"]),
+            "{Oslo=-3.0/-0.5/4.5/-3.0, Paris=9.9/13.6/20.0/12.3}\n"
+        );
+        // and no mode without the flag
+        assert_eq!(
+            run(data, &[]),
+            "{Oslo=-3.0/-0.5/4.5, Paris=9.9/13.6/20.0}\n"
+        );
     }
-}
 
-/// Given a buffer containing input file contents (possibly mmap'd), collect all of the
-/// measurement results together.
-///
-/// This is the meat of the work, the vast majority of program runtime is spent in this function.
-/// It's not inlined for better visibility in perf tools, even though it's only called once from
-/// main().
-#[cfg(feature = "rayon")]
-#[inline(never)]
-fn process_data(data: &[u8]) -> ResultsMap {
-    use rayon::prelude::*;
-
-    // split on lines in parallel
-    data.par_split(|b| *b == b'\n')
-        // Rayon will make a bunch of ResultsMaps (the exact amount isn't specified beyond "as
-        // needed" but I've seen it surpass 25,000) and reuse them whenever it calls this closure
-        // in a worker thread. fold() returns a ParallelIterator<Item = ResultsMap>.
-        .fold(ResultsMap::default, |mut results, line| {
-            // SPICY HOT! Called for every line.
-            if let Some(row) = Row::parse(line.as_bstr()) {
-                results.ingest(row);
+    #[test]
+    fn test_repeat() {
+        let data = include_bytes!("../testdata/measurements-20.txt");
+        let expected = run(data, &[]);
+        let mut log = Vec::new();
+        let mut outputs = Vec::new();
+        let results = repeat_timed(3, &mut log, || {
+            let out = run(data, &[]);
+            outputs.push(out.clone());
+            Ok::<_, ()>(out)
+        });
+        let (results, times) = results.unwrap();
+        assert_eq!(results, expected);
+        assert_eq!(outputs, [expected.as_str(); 3]);
+        assert_eq!(times.len(), 3);
+
+        let log = String::from_utf8(log).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4, "{log}");
+        for (i, line) in lines[..3].iter().enumerate() {
+            assert!(
+                line.starts_with(&format!("iteration {}: ", i + 1)),
+                "{line}"
+            );
+        }
+        assert!(lines[3].starts_with("median: "));
+
+        // errors stop it early
+        let mut count = 0;
+        let results = repeat_timed(3, &mut io::sink(), || {
+            count += 1;
+            Err::<(), _>("oops")
+        });
+        assert_eq!((results.map(|(r, _)| r), count), (Err("oops"), 1));
+    }
+
+    #[test]
+    fn test_repeat_with_progress() {
+        // The progress display and `--repeat`'s timings both write to stderr from different
+        // threads, like in main, which deadlocked when the display held onto stderr's lock for
+        // the whole run. Run it on another thread so that a deadlock fails rather than hangs.
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let data = include_bytes!("../testdata/measurements-20.txt");
+            for watch in [false, true] {
+                let progress = if watch {
+                    Progress::with_snapshot(data.len() * 2)
+                } else {
+                    Progress::new(data.len() * 2)
+                };
+                let results = std::thread::scope(|scope| {
+                    let (stop_tx, stop_rx) = std::sync::mpsc::channel();
+                    let progress = &progress;
+                    if watch {
+                        scope.spawn(move || progress.watch_until(stop_rx));
+                    } else {
+                        scope.spawn(move || progress.render_until(stop_rx));
+                    }
+                    let results = repeat_timed(2, &mut io::stderr(), || {
+                        // long enough for the progress to be redrawn in between
+                        std::thread::sleep(Duration::from_millis(300));
+                        process_buffers(&[data], Config::default(), Some(progress))
+                    });
+                    drop(stop_tx);
+                    results
+                });
+                assert_eq!(results.unwrap().1.len(), 2);
+                assert_eq!(progress.done(), data.len() * 2);
             }
-            // pass on results accumulator for next task
-            results
-        })
-        // Then immediately (and still in parallel) reduce those ResultsMaps into a single one.
-        // Somehow this (which uses the std::iter::Sum impl above) is faster than using
-        // ParallelIterator::reduce, even though it's basically the same code.
-        .sum()
-}
+            done_tx.send(()).unwrap();
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(30))
+            .expect("deadlocked writing to stderr");
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn test_glob() {
+        let parts = [
+            &include_bytes!("../testdata/measurements-20.txt")[..],
+            include_bytes!("../testdata/measurements-10.txt"),
+            include_bytes!("../testdata/mode.txt"),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        for (i, part) in parts.iter().enumerate() {
+            std::fs::write(dir.path().join(format!("part-{i}.txt")), part).unwrap();
+        }
+        // doesn't match, and directories get skipped
+        std::fs::write(dir.path().join("other.txt"), "Paris;99.9\n").unwrap();
+        std::fs::create_dir(dir.path().join("part-dir.txt")).unwrap();
+
+        let glob = |pattern: &str| {
+            let pattern = dir.path().join(pattern).to_string_lossy().into_owned();
+            let args = ["-q", "--glob", &pattern].map(String::from);
+            let opts = Options::parse(args).unwrap();
+            let mmaps = open_inputs(&opts)?;
+            let buffers: Vec<&[u8]> = mmaps.iter().map(|mmap| &mmap[..]).collect();
+            let summary = summarize(process_buffers(&buffers, opts.config(), None).unwrap());
+            let mut out = Vec::new();
+            write_results(&mut out, &summary, &opts).unwrap();
+            Ok::<_, String>(String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(glob("part-*.txt").unwrap(), run(&parts.concat(), &[]));
+        assert_eq!(
+            glob("nothing-*.txt"),
+            Err(format!(
+                "no files match '{}'",
+                dir.path().join("nothing-*.txt").display()
+            ))
+        );
+        assert!(glob("part-[.txt").is_err());
+    }
+
+    #[test]
+    fn test_bom() {
+        let data = include_bytes!("../testdata/bom.txt");
+        assert_eq!(
+            run(data, &[]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        // only at the very start
+        let data = b"Paris;12.3\n\xEF\xBB\xBFParis;14.1\n";
+        assert_eq!(strip_bom(data), data);
+    }
+
+    #[test]
+    fn test_write_histogram() {
+        let data = include_bytes!("../testdata/mode.txt");
+        let hist = city_histogram(data, BStr::new("Paris"), Default::default());
+        let mut out = Vec::new();
+        write_histogram(&mut out, b"Paris", &hist).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "histogram for Paris:\n   9.9 1\n  12.3 3\n  15.0 1\n  20.0 1\n"
+        );
+    }
+
+    #[test]
+    fn test_write_global_histogram() {
+        let data = include_bytes!("../testdata/mode.txt");
+        let config = Options::parse(["--global-histogram", "-"].map(String::from))
+            .unwrap()
+            .config();
+        let mut results = process_data(data, config, None).unwrap();
+        let hist = results.take_global_histogram().unwrap();
+        let mut out = Vec::new();
+        write_global_histogram(&mut out, &hist).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "histogram for all cities:\n  -3.0 2\n   4.5 1\n   9.9 1\n  12.3 3\n  \
+             15.0 1\n  20.0 1\n"
+        );
+    }
+
+    #[test]
+    fn test_field_order() {
+        let normal = include_bytes!("../testdata/measurements-20.txt");
+        let reversed = include_bytes!("../testdata/reversed-20.txt");
+        assert_eq!(run(reversed, &["--order", "temp-city"]), run(normal, &[]));
+        assert_eq!(run(normal, &["--order", "city-temp"]), run(normal, &[]));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let data = b"Paris;12.3\nUNKNOWN;0.0\nOslo;-3.0\n";
+        assert_eq!(
+            run(data, &["--exclude", "UNKNOWN"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/12.3/12.3}\n"
+        );
+        assert_eq!(
+            run(data, &["--exclude", "Oslo"]),
+            "{Paris=12.3/12.3/12.3, UNKNOWN=0.0/0.0/0.0}\n"
+        );
+        assert_eq!(
+            run(data, &["--exclude", "Paris", "--exclude", "UNKNOWN"]),
+            "{Oslo=-3.0/-3.0/-3.0}\n"
+        );
+        // exact matches only
+        assert_eq!(run(data, &["--exclude", "Pari"]), run(data, &[]));
+    }
+
+    #[test]
+    fn test_only_prefix() {
+        let data = b"Paris;12.3\nParma;20.0\nOslo;-3.0\nOsaka;15.5\n";
+        assert_eq!(
+            run(data, &["--prefix", "Par"]),
+            "{Paris=12.3/12.3/12.3, Parma=20.0/20.0/20.0}\n"
+        );
+        assert_eq!(
+            run(data, &["--only", "Oslo", "--only", "Paris"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/12.3/12.3}\n"
+        );
+        // either one is enough
+        assert_eq!(
+            run(data, &["--only", "Oslo", "--prefix", "Parm"]),
+            "{Oslo=-3.0/-3.0/-3.0, Parma=20.0/20.0/20.0}\n"
+        );
+        assert_eq!(run(data, &["--prefix", "Berlin"]), "{}\n");
+        assert_eq!(run(data, &["--only", "Pari"]), "{}\n");
+        // and excluding still works on top
+        assert_eq!(
+            run(data, &["--prefix", "Os", "--exclude", "Osaka"]),
+            "{Oslo=-3.0/-3.0/-3.0}\n"
+        );
+    }
 
-/// Single-threaded version of the above
-#[cfg(not(feature = "rayon"))]
-#[inline(never)]
-fn process_data(data: &[u8]) -> ResultsMap {
-    data.split(|&b| b == b'\n')
-        .fold(ResultsMap::default(), |mut results, line| {
-            // SPICY HOT! Called for every line.
-            if let Some(row) = Row::parse(line.as_bstr()) {
-                results.ingest(row);
+    #[test]
+    fn test_single_threaded() {
+        let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+        for args in [
+            &[][..],
+            &["--strategy", "chunked"],
+            &["--with-mode"],
+            &["--strict"],
+        ] {
+            let single: Vec<_> = args.iter().copied().chain(["--single-threaded"]).collect();
+            assert_eq!(run(data, &single), run(data, args), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn test_temp_field() {
+        let data = b"Paris;12.3\nParis;14.1;st01\nParis;10.0;st02;x\nOslo;-3.0;st03;x\n";
+        assert_eq!(
+            run(data, &["--temp-field", "2"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=10.0/12.1/14.1}\n"
+        );
+        assert_eq!(
+            run(data, &["--temp-field", "2", "--strict"]),
+            run(data, &["--temp-field", "2"])
+        );
+        // any other field, and lines without that many fields are skipped
+        let data = b"Paris;st01;12.3\nParis;st01;14.1;x\nParis;x\nOslo;st03;-3.0;x;y\n";
+        assert_eq!(
+            run(data, &["--temp-field", "3"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+
+        for field in ["0", "1", "256", "x"] {
+            let args = ["--temp-field", field, "-"].map(String::from);
+            assert!(Options::parse(args).is_err(), "{field}");
+        }
+        let args = ["--temp-field", "2", "--split-last", "-"].map(String::from);
+        assert!(Options::parse(args).is_err());
+    }
+
+    #[test]
+    fn test_csv_header() {
+        // the columns can be in any order, and quoted names and values can have commas in them
+        let data = b"id,\"reading, C\",station,city\n\
+                     1,12.3,st01,\"Paris, FR\"\n\
+                     2,-3.0,st02,Oslo\n\
+                     3,\"14.1\",st01,\"Paris, FR\"\n";
+        let expected = "{Oslo=-3.0/-3.0/-3.0, Paris, FR=12.3/13.2/14.1}\n";
+        let args = ["--csv-header", "--temp-col", "reading, C"];
+        assert_eq!(run(data, &args), expected);
+        assert_eq!(run(data, &[&args[..], &["--strict"]].concat()), expected);
+
+        // by station instead, and with another delimiter
+        let data = b"station;temperature;city\nst01;12.3;Paris\nst02;-3.0;Oslo\n";
+        assert_eq!(
+            run(
+                data,
+                &["--csv-header", "--city-col", "station", "--delimiter", ";"]
+            ),
+            "{st01=12.3/12.3/12.3, st02=-3.0/-3.0/-3.0}\n"
+        );
+
+        let mut opts = Options::parse(["--csv-header", "x.csv"].map(String::from)).unwrap();
+        assert!(opts.apply_csv_header(b"city,temp").is_err());
+        assert!(opts
+            .apply_csv_header(b"temperature,city,temperature")
+            .is_ok());
+        // every file needs the same columns
+        assert!(opts.apply_csv_header(b"temperature,city").is_ok());
+        assert!(opts.apply_csv_header(b"city,temperature").is_err());
+
+        for args in [
+            &["--city-col", "city", "-"][..],
+            &["--csv-header", "--header", "x.csv"],
+            &["--csv-header", "--temp-field", "2", "x.csv"],
+            &["--csv-header", "-"],
+        ] {
+            let parsed = Options::parse(args.iter().map(|s| s.to_string()));
+            assert!(parsed.is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn test_split_last() {
+        let data = b"Paris;12.3\n\"Foo;Bar\";1.0\nParis;14.1\n";
+        assert_eq!(
+            run(data, &["--split-last"]),
+            "{\"Foo;Bar\"=1.0/1.0/1.0, Paris=12.3/13.2/14.1}\n"
+        );
+        // by default the temperature gets mangled instead
+        assert_eq!(
+            run(data, &[]),
+            "{\"Foo=1.0/1.0/1.0, Paris=12.3/13.2/14.1}\n"
+        );
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let data = b"Paris   12.3\nOslo\t\t-3.0\n  Paris 14.1  \n";
+        assert_eq!(
+            run(data, &["--whitespace"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        let args = ["--whitespace", "--temp-field", "2", "-"].map(String::from);
+        assert!(Options::parse(args).is_err());
+    }
+
+    #[test]
+    fn test_max_cities() {
+        let data = include_bytes!("../testdata/max-cities.txt");
+        assert_eq!(
+            run(data, &["--max-cities", "2"]),
+            "{(other)=16.0/19.2/25.2, Oslo=-5.0/-4.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        assert_eq!(run(data, &["--max-cities", "5"]).matches('=').count(), 5);
+        for bad in ["0", "65536", "x"] {
+            let args = ["--max-cities", bad, "measurements.txt"].map(String::from);
+            assert!(Options::parse(args).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_with_endpoints() {
+        let data = include_bytes!("../testdata/endpoints.txt");
+        assert_eq!(
+            run(data, &["--with-endpoints"]),
+            "{Oslo=-8.1/-2.2/4.5/-3.0/-8.1, Paris=9.9/14.1/20.0/12.3/9.9, \
+             Rome=17.0/17.0/17.0/17.0/17.0}\n"
+        );
+        assert_eq!(
+            run(
+                data,
+                &["--with-endpoints", "--with-mode", "--format", "csv"]
+            ),
+            "city,min,mean,max,mode,first,last\n\
+             Oslo,-8.1,-2.2,4.5,-8.1,-3.0,-8.1\n\
+             Paris,9.9,14.1,20.0,9.9,12.3,9.9\n\
+             Rome,17.0,17.0,17.0,17.0,17.0,17.0\n"
+        );
+        let opts = Options::parse(["--with-endpoints", "x.txt"].map(String::from)).unwrap();
+        assert!(opts.config().single_threaded);
+    }
+
+    #[test]
+    fn test_empty_city() {
+        let data = include_bytes!("../testdata/empty-city.txt");
+        assert_eq!(
+            run(data, &[]),
+            "{=-1.0/5.7/12.3, Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        assert_eq!(
+            run(data, &["--skip-empty-city"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        let opts = Options::parse(["--strict", "measurements.txt"].map(String::from)).unwrap();
+        let err = process_data(data, opts.config(), None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid line ';12.3': empty city");
+    }
+
+    #[test]
+    fn test_exact_means() {
+        // totals of 425 and -41 tenths
+        let data =
+            b"Paris;12.3\nParis;10.0\nParis;10.1\nParis;10.1\nOslo;-3.0\nOslo;-1.1\nOslo;0.0\n";
+        let summary = summarize(process_data(data, Config::default(), None).unwrap());
+        let mut out = Vec::new();
+        write_exact_means(&mut out, &summary).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "mean for Oslo: {} (rounded to -1.4)\nmean for Paris: 10.625 (rounded to 10.6)\n",
+                -41.0 / 3.0 / 10.0
+            )
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let data = include_bytes!("../testdata/group-by.txt");
+        assert_eq!(
+            run(data, &["--group-by", "prefix:2"]),
+            "{As=-4.2/7.9/20.0, Eu=8.1/11.5/14.1, Os=-3.0/-3.0/-3.0, Za=-1.0/-1.0/-1.0, \
+             Zü=3.0/3.0/3.0}\n"
+        );
+        assert_eq!(
+            run(data, &["--group-by", "before:/"]),
+            "{Asia=-4.2/7.9/20.0, Europe=8.1/11.5/14.1, Oslo=-3.0/-3.0/-3.0, \
+             Zagreb=-1.0/-1.0/-1.0, Zürich=3.0/3.0/3.0}\n"
+        );
+        assert!(Options::parse(["--group-by", "prefix:0", "x.txt"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_time_window() {
+        let data = include_bytes!("../testdata/timestamps.txt");
+        assert_eq!(
+            run(data, &["--since", "1700000000", "--until", "1700007200"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n"
+        );
+        assert_eq!(
+            run(data, &["--until", "1700000000"]),
+            "{Rome=30.0/30.0/30.0}\n"
+        );
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert!(parse(&["--since", "5", "--until", "5", "-"]).is_err());
+        assert!(parse(&["--since", "-1", "-"]).is_err());
+        assert!(parse(&["--until", "soon", "-"]).is_err());
+    }
+
+    #[test]
+    fn test_integer_temperatures() {
+        let data = b"Paris;12\nOslo;12.0\n";
+        // by default 12 is 12 tenths
+        assert_eq!(run(data, &[]), "{Oslo=12.0/12.0/12.0, Paris=1.2/1.2/1.2}\n");
+        assert_eq!(
+            run(data, &["--assume-integer-tenths-off"]),
+            "{Oslo=12.0/12.0/12.0, Paris=12.0/12.0/12.0}\n"
+        );
+        // decimal commas too
+        let data = b"Paris;12\nOslo;12,5\n";
+        assert_eq!(
+            run(data, &["--assume-integer-tenths-off", "--decimal", "comma"]),
+            "{Oslo=12.5/12.5/12.5, Paris=12.0/12.0/12.0}\n"
+        );
+        // and strict mode is tested in lib.rs, just make sure the options are exclusive
+        let args = ["--strict", "--assume-integer-tenths-off", "-"].map(String::from);
+        assert!(Options::parse(args).is_err());
+
+        // whole degrees only
+        let data = b"Paris;12\nOslo;-3\nParis;15\n";
+        assert_eq!(run(data, &[]), "{Oslo=-0.3/-0.3/-0.3, Paris=1.2/1.4/1.5}\n");
+        assert_eq!(
+            run(data, &["--int-degrees"]),
+            "{Oslo=-3.0/-3.0/-3.0, Paris=12.0/13.5/15.0}\n"
+        );
+        assert_eq!(
+            run(data, &["--int-degrees", "--strict"]),
+            run(data, &["--int-degrees"])
+        );
+        let args = ["--int-degrees", "--assume-integer-tenths-off", "-"].map(String::from);
+        assert!(Options::parse(args).is_err());
+    }
+
+    #[test]
+    fn test_validate_utf8() {
+        let data = include_bytes!("../testdata/invalid-utf8.txt");
+        let summary = summarize(process_data(data, Default::default(), None).unwrap());
+        assert_eq!(
+            invalid_utf8_cities(&summary),
+            [BStr::new(b"Par\xffis"), BStr::new(b"\xc3(")]
+        );
+
+        let data = include_bytes!("../testdata/measurements-complex-utf8.txt");
+        let summary = summarize(process_data(data, Default::default(), None).unwrap());
+        assert!(invalid_utf8_cities(&summary).is_empty());
+    }
+
+    #[test]
+    fn test_extremes() {
+        let data = include_bytes!("../testdata/measurements-20.txt");
+        let summary = summarize(process_data(data, Default::default(), None).unwrap());
+        let ((hot_city, hot), (cold_city, cold)) = extremes(&summary).unwrap();
+        assert_eq!(
+            (hot_city, hot.to_string()),
+            ("Parakou1️⃣🐝🏎️".into(), "36.3".into())
+        );
+        assert_eq!(
+            (cold_city, cold.to_string()),
+            ("Nashville1️⃣🐝🏎️".into(), "-4.9".into())
+        );
+
+        // ties go to the first city alphabetically
+        let data = b"Oslo;30.0\nLima;30.0\nRome;-5.0\nBern;-5.0\nAbuja;10.0\n";
+        let summary = summarize(process_data(data, Default::default(), None).unwrap());
+        let ((hot_city, _), (cold_city, _)) = extremes(&summary).unwrap();
+        assert_eq!((hot_city, cold_city), ("Lima".into(), "Bern".into()));
+
+        assert_eq!(extremes(&[]), None);
+    }
+
+    #[test]
+    fn test_csv() {
+        let data = b"Paris, France;12.3\nThe \"Big\" Apple;5.0\nOslo;-3.0\nParis, France;12.4\n";
+        let out = run(data, &["--format", "csv"]);
+        assert_eq!(
+            out,
+            "city,min,mean,max\n\
+             Oslo,-3.0,-3.0,-3.0\n\
+             \"Paris, France\",12.3,12.4,12.4\n\
+             \"The \"\"Big\"\" Apple\",5.0,5.0,5.0\n"
+        );
+
+        // and make sure a real CSV parser agrees
+        let mut reader = csv::Reader::from_reader(out.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec!["city", "min", "mean", "max"]
+        );
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|r| r.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], ["Paris, France", "12.3", "12.4", "12.4"]);
+        assert_eq!(rows[2][0], "The \"Big\" Apple");
+
+        // mode gets its own column
+        let out = run(data, &["--format", "csv", "--with-mode"]);
+        assert!(out.starts_with("city,min,mean,max,mode\nOslo,-3.0,-3.0,-3.0,-3.0\n"));
+    }
+
+    #[test]
+    fn test_out_decimal() {
+        let data = b"Paris, France;12.3\nOslo;-3.0\nParis, France;12.4\n";
+        let comma = ["--out-decimal", "comma"];
+        assert_eq!(
+            run(data, &comma),
+            "{Oslo=-3,0/-3,0/-3,0, Paris, France=12,3/12,4/12,4}\n"
+        );
+        assert_eq!(
+            run(data, &[&comma[..], &["--format", "lines"]].concat()),
+            "Oslo=-3,0/-3,0/-3,0\nParis, France=12,3/12,4/12,4\n"
+        );
+        assert_eq!(
+            run(data, &[&comma[..], &["--mean-precision", "2"]].concat()),
+            "{Oslo=-3,0/-3,00/-3,0, Paris, France=12,3/12,35/12,4}\n"
+        );
+
+        // CSV quotes them, so the columns don't change
+        let out = run(data, &[&comma[..], &["--format", "csv"]].concat());
+        let mut reader = csv::Reader::from_reader(out.as_bytes());
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|r| r.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(rows[0], ["Oslo", "-3,0", "-3,0", "-3,0"]);
+        assert_eq!(rows[1], ["Paris, France", "12,3", "12,4", "12,4"]);
+
+        // the binary format has no decimal separator to change
+        let summary = summarize(process_data(data, Config::default(), None).unwrap());
+        let binary = |args: &[&str]| {
+            let args = args.iter().chain(&["--format", "binary", "x.txt"]);
+            let opts = Options::parse(args.map(|s| s.to_string())).unwrap();
+            let mut out = Vec::new();
+            write_results(&mut out, &summary, &opts).unwrap();
+            out
+        };
+        assert_eq!(binary(&comma), binary(&[]));
+        assert!(Options::parse(["--out-decimal", "dot", "x.txt"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let data = b"Paris;12.0\nParis;12.6\nOslo;-3.0\nOslo;-0.0\nRome;-0.3\n";
+        assert_eq!(
+            run(data, &["--format", "compact"]),
+            "{Oslo=-3/-1.5/0, Paris=12/12.3/12.6, Rome=-0.3/-0.3/-0.3}\n"
+        );
+        // the default stays as the challenge wants it
+        assert_eq!(
+            run(data, &[]),
+            "{Oslo=-3.0/-1.5/0.0, Paris=12.0/12.3/12.6, Rome=-0.3/-0.3/-0.3}\n"
+        );
+        assert_eq!(
+            run(data, &["--format", "compact", "--mean-precision", "2"]),
+            "{Oslo=-3/-1.50/0, Paris=12/12.30/12.6, Rome=-0.3/-0.30/-0.3}\n"
+        );
+    }
+
+    #[test]
+    fn test_quantile() {
+        let args = ["--quantile", "0.5", "-"].map(String::from);
+        #[cfg(not(feature = "tdigest"))]
+        assert!(Options::parse(args).is_err());
+        #[cfg(feature = "tdigest")]
+        {
+            assert!(Options::parse(args).is_ok());
+            let data = include_bytes!("../testdata/mode.txt");
+            assert_eq!(
+                run(data, &["--quantile", "0.5"]),
+                "{Oslo=-3.0/-0.5/4.5/-3.0, Paris=9.9/13.6/20.0/12.3}\n"
+            );
+            // the top quantile is the max, and it comes after the mode
+            assert_eq!(
+                run(data, &["--quantile", "1", "--with-mode", "--format", "csv"]),
+                "city,min,mean,max,mode,quantile\nOslo,-3.0,-0.5,4.5,-3.0,4.5\n\
+                 Paris,9.9,13.6,20.0,12.3,20.0\n"
+            );
+            for q in ["0", "1.5", "p99"] {
+                let args = ["--quantile", q, "-"].map(String::from);
+                assert!(Options::parse(args).is_err(), "{q}");
             }
-            // pass on results accumulator for next task
-            results
-        })
-}
+        }
+    }
 
-fn main() {
-    let measurements_path = std::env::args().nth(1).expect("missing filename argument");
-    let file = File::open(measurements_path).expect("failed to open input file");
+    #[test]
+    fn test_order_as_seen() {
+        for data in [
+            &include_bytes!("../testdata/measurements-20.txt")[..],
+            include_bytes!("../testdata/measurements-10000-unique-keys.txt"),
+        ] {
+            let mut expected: Vec<&[u8]> = Vec::new();
+            for line in data.lines() {
+                let city = line.split_str(";").next().unwrap();
+                if !expected.contains(&city) {
+                    expected.push(city);
+                }
+            }
+            for args in [
+                &["--single-threaded"][..],
+                &["--single-threaded", "--strategy", "chunked", "--with-mode"],
+                &[],
+            ] {
+                let args = [args, &["--order-as-seen", "--format", "lines"]].concat();
+                let out = run(data, &args);
+                let cities: Vec<&[u8]> = out
+                    .lines()
+                    .map(|line| line.rsplit_once('=').unwrap().0.as_bytes())
+                    .collect();
+                assert!(cities == expected, "{args:?}");
+            }
+        }
+    }
 
-    // mmap the whole thing, accessible as a bug &[u8]. No UTF-8 check
-    let data = unsafe { Mmap::map(&file).expect("failed to mmap input file") };
+    #[test]
+    fn test_lines_format() {
+        let data = include_bytes!("../testdata/measurements-20.txt");
+        let lines = run(data, &["--format", "lines"]);
+        // the same cities and values as the default format, just one per line
+        let brace = run(data, &[]);
+        let brace = brace
+            .trim_end()
+            .strip_prefix('{')
+            .unwrap()
+            .strip_suffix('}');
+        assert_eq!(lines, format!("{}\n", brace.unwrap().replace(", ", "\n")));
+        let cities: Vec<&str> = lines
+            .lines()
+            .map(|l| l.split('=').next().unwrap())
+            .collect();
+        assert!(cities.is_sorted());
 
-    // do all the main work
-    let merged_results = process_data(&data);
-
-    // Finalize statstics: determine the mean temperatures and sort by city name. It's faster to do
-    // this serially, since rayon's parallel iteration over maps is to first collect them into an
-    // intermediate Vec, and the computation in stats.finalize is cheap (like 3 f64 ops).
-    let mut summary_results: Vec<(BString, FinalStats)> = merged_results
-        .into_iter()
-        .map(|(city, stats)| (city, stats.finalize()))
-        .collect();
-    summary_results.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-
-    // Print results
-    print!("{{");
-    for (i, (city, stats)) in summary_results.into_iter().enumerate() {
-        let comma = if i == 0 { "" } else { ", " };
-        print!("{comma}{city}={stats}");
-    }
-    println!("}}");
+        let data = include_bytes!("../testdata/mode.txt");
+        assert_eq!(
+            run(data, &["--format", "lines", "--with-mode"]),
+            "Oslo=-3.0/-0.5/4.5/-3.0\nParis=9.9/13.6/20.0/12.3\n"
+        );
+        assert_eq!(run(b"", &["--format", "lines"]), "");
+    }
+
+    #[test]
+    fn test_binary_format() {
+        let data = include_bytes!("../testdata/mode.txt");
+        let args = ["--format", "binary", "--with-mode", "measurements.txt"];
+        let opts = Options::parse(args.map(String::from)).unwrap();
+        let summary = summarize(process_data(data, opts.config(), None).unwrap());
+        let mut out = Vec::new();
+        write_results(&mut out, &summary, &opts).unwrap();
+        assert!(out.starts_with(b"1BRB\x04\0\0\0Oslo"));
+        // the first and last temperatures aren't encoded, so compare everything else
+        let fields = |summary: &[(BString, FinalStats)]| {
+            summary
+                .iter()
+                .map(|(city, stats)| format!("{city}={stats}/{}", stats.count()))
+                .collect::<Vec<_>>()
+        };
+        let decoded = onebrc::read_binary(&out).unwrap();
+        assert_eq!(fields(&decoded), fields(&summary));
+    }
+
+    #[test]
+    fn test_unit() {
+        let data = b"Paris;0.0\nOslo;-40.0\nOslo;-17.9\n";
+        assert_eq!(
+            run(data, &["--unit", "kelvin"]),
+            "{Oslo=233.2/244.2/255.3, Paris=273.2/273.2/273.2}\n"
+        );
+        // Oslo's mean is -28.95C, exactly -20.11F, rather than -29.0C converted
+        assert_eq!(
+            run(data, &["--unit", "fahrenheit"]),
+            "{Oslo=-40.0/-20.1/-0.2, Paris=32.0/32.0/32.0}\n"
+        );
+    }
+
+    #[test]
+    fn test_unit_rounding() {
+        // The mean is -18.25C, exactly -0.85F. Fahrenheit can only tie for means, since a single
+        // reading in tenths of a degree Celsius converts to a whole number of fifths.
+        let data = b"Minsk;-18.0\nMinsk;-18.5\n";
+        assert_eq!(run(data, &["--unit", "f"]), "{Minsk=-1.3/-0.9/-0.4}\n");
+        assert_eq!(
+            run(data, &["--unit", "f", "--reference-rounding"]),
+            "{Minsk=-1.3/-0.8/-0.4}\n"
+        );
+        // every tenth of a degree Celsius is a tie in Kelvin: -0.25K, -0.15K, and -0.05K
+        let data = b"Vostok;-273.2\nVostok;-273.4\n";
+        assert_eq!(run(data, &["--unit", "k"]), "{Vostok=-0.3/-0.2/-0.1}\n");
+        assert_eq!(
+            run(data, &["--unit", "k", "--reference-rounding"]),
+            "{Vostok=-0.2/-0.1/0.0}\n"
+        );
+    }
+
+    #[test]
+    fn test_mean_precision() {
+        for name in [
+            "measurements-20.txt",
+            "measurements-complex-utf8.txt",
+            "mode.txt",
+        ] {
+            let data = std::fs::read(format!("testdata/{name}")).unwrap();
+            assert_eq!(run(&data, &["--mean-precision", "1"]), run(&data, &[]));
+        }
+
+        // Paris: (12.3 + 15.0 + 12.4) / 3 = 13.2333...
+        // Oslo: (-3.0 + -3.1 + 4.5) / 3 = -0.5333...
+        // Rome: (0.1 + 0.2) / 2 = 0.15
+        let data = b"Paris;12.3\nParis;15.0\nParis;12.4\nOslo;-3.0\nOslo;-3.1\nOslo;4.5\nRome;0.1\nRome;0.2";
+        assert_eq!(
+            run(data, &["--mean-precision", "4"]),
+            "{Oslo=-3.1/-0.5333/4.5, Paris=12.3/13.2333/15.0, Rome=0.1/0.1500/0.2}\n"
+        );
+        assert_eq!(
+            run(data, &["--mean-precision", "2", "--unit", "f"]),
+            "{Oslo=26.4/31.04/40.1, Paris=54.1/55.82/59.0, Rome=32.2/32.27/32.4}\n"
+        );
+        assert!(Options::parse(["--mean-precision", "7", "-"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let data = include_bytes!("../testdata/measurements-10000-unique-keys.txt");
+        let full = run(data, &[]);
+        assert_eq!(run(data, &["--sample", "1.0"]), full);
+        assert_eq!(run(data, &["--sample", "1", "--sample-seed", "5"]), full);
+
+        // the same seed always picks the same lines
+        let sampled = run(data, &["--sample", "0.1", "--sample-seed", "42"]);
+        assert_ne!(sampled, full);
+        assert_eq!(
+            run(data, &["--sample-seed", "42", "--sample", "0.1"]),
+            sampled
+        );
+        assert_ne!(
+            run(data, &["--sample", "0.1", "--sample-seed", "43"]),
+            sampled
+        );
+
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        for bad in ["0", "1.5", "-0.1", "half"] {
+            assert!(parse(&["--sample", bad, "-"]).is_err(), "{bad}");
+        }
+        assert!(parse(&["--sample-seed", "x", "-"]).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let data = b"#delim=\\t unit=F prec=2\nParis\t12.3\nOslo;x\t-3.0\nParis\t14.1\n";
+        // the header isn't a city, and the ';' is just part of a city name now
+        assert_eq!(
+            run(data, &["--header"]),
+            "{Oslo;x=26.6/26.60/26.6, Paris=54.1/55.76/57.4}\n"
+        );
+        let data = b"#delim=tab\r\nParis\t12.3\n";
+        assert_eq!(run(data, &["--header"]), "{Paris=12.3/12.3/12.3}\n");
+        // no header is fine too
+        assert_eq!(
+            run(b"Paris;12.3\n", &["--header"]),
+            "{Paris=12.3/12.3/12.3}\n"
+        );
+        // and without --header, it's just another line
+        assert_eq!(
+            run(b"#delim=;\nParis;12.3\n", &[]),
+            "{#delim==0.0/0.0/0.0, Paris=12.3/12.3/12.3}\n"
+        );
+
+        let mut opts = Options::default();
+        assert!(opts.apply_header(b"delim=ab").is_err());
+        assert!(opts.apply_header(b"unit=X").is_err());
+        assert!(opts.apply_header(b"prec=9").is_err());
+        assert!(opts.apply_header(b"color=blue").is_err());
+        assert!(opts.apply_header(b"delim").is_err());
+        assert!(opts.apply_header(b"delim=|  unit=k").is_ok());
+        assert_eq!(opts.config().delimiter, b'|');
+        assert_eq!(opts.unit, onebrc::Unit::Kelvin);
+    }
+
+    #[test]
+    fn test_line_suffix_bytes() {
+        let crlf = b"Paris;12.3\r\nOslo;-3.0\r\nParis;14.1\r\n\r\n";
+        let expected = "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n";
+        assert_eq!(run(crlf, &["--line-suffix-bytes", "1"]), expected);
+        assert_eq!(
+            run(crlf, &["--line-suffix-bytes", "1", "--strict"]),
+            expected
+        );
+
+        // the suffix can look like anything, even digits, and short lines are skipped
+        let data = b"Paris;12.3#7\nOslo;-3.0|0\nX\n\nParis;14.1ab\n";
+        assert_eq!(run(data, &["--line-suffix-bytes", "2"]), expected);
+        assert_eq!(
+            run(
+                data,
+                &["--line-suffix-bytes", "2", "--assume-integer-tenths-off"]
+            ),
+            expected
+        );
+        assert_eq!(
+            run(data, &["--line-suffix-bytes", "3"]),
+            "{Oslo=-0.3/-0.3/-0.3, Paris=1.2/1.3/1.4}\n"
+        );
+    }
+
+    #[test]
+    fn test_temp_range() {
+        let data = include_bytes!("../testdata/mode.txt");
+        // Oslo never gets that hot, so it's left out entirely
+        let expected = "{Paris=20.0/20.0/20.0}\n";
+        assert_eq!(run(data, &["--min-temp", "20.0"]), expected);
+        assert_eq!(run(data, &["--min-temp", "20"]), expected);
+        assert_eq!(run(data, &["--min-temp", "20.0", "--strict"]), expected);
+        assert_eq!(
+            run(data, &["--min-temp", "4.5", "--max-temp", "12.3"]),
+            "{Oslo=4.5/4.5/4.5, Paris=9.9/11.7/12.3}\n"
+        );
+        assert_eq!(
+            run(data, &["--max-temp", "-3.0", "--with-mode"]),
+            "{Oslo=-3.0/-3.0/-3.0/-3.0}\n"
+        );
+
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert_eq!(
+            parse(&["--min-temp", "-1.5", "m.txt"]).unwrap().min_temp,
+            Some(Temperature::from_tenths(-15))
+        );
+        assert!(parse(&["--min-temp", "hot", "m.txt"]).is_err());
+        assert!(parse(&["--max-temp", "1.23", "m.txt"]).is_err());
+        assert!(parse(&["--min-temp", "10", "--max-temp", "9.9", "m.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_record_sep() {
+        let data = include_bytes!("../testdata/nul-records.txt");
+        let expected = "{New\nYork=5.0/6.0/7.0, Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n";
+        for extra in [
+            &[][..],
+            &["--strict"],
+            &["--strategy", "chunked"],
+            &["--min-chunk-bytes", "1"],
+            &["--single-threaded"],
+            &["--intern"],
+        ] {
+            let args = [&["--record-sep", "0"][..], extra].concat();
+            assert_eq!(run(data, &args), expected, "{extra:?}");
+        }
+
+        // reading a stream in small pieces only cuts after a separator
+        let config = Options::parse(["--record-sep", "0", "-"].map(String::from))
+            .unwrap()
+            .config();
+        let expected = format!("{:?}", summarize(process_data(data, config, None).unwrap()));
+        let streamed = summarize(onebrc::process_reader(&data[..], config, 8).unwrap());
+        assert_eq!(format!("{streamed:?}"), expected);
+
+        // the limit counts records, not lines
+        assert_eq!(
+            first_records(data, 2, config.record_sep),
+            b"Paris;12.3\0New\nYork;5.0\0"
+        );
+
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert_eq!(
+            parse(&["--record-sep", "|", "m.txt"]).unwrap().record_sep,
+            Some(b'|')
+        );
+        for nul in ["0", "nul", "\\0"] {
+            assert_eq!(
+                parse(&["--record-sep", nul, "m.txt"]).unwrap().record_sep,
+                Some(b'\0')
+            );
+        }
+        assert!(parse(&["--record-sep", "ab", "m.txt"]).is_err());
+        assert!(parse(&["--record-sep", ";", "m.txt"]).is_err());
+        let mut opts = parse(&["--record-sep", "|", "m.txt"]).unwrap();
+        assert!(opts.apply_header(b"delim=|").is_err());
+    }
+
+    #[test]
+    fn test_byte_range_options() {
+        let parse = |args: &[&str]| {
+            Options::parse(args.iter().map(|s| s.to_string()).chain(["x.txt".into()]))
+        };
+        let opts = parse(&["--offset", "10", "--bytes", "20"]).unwrap();
+        assert!(opts.has_byte_range());
+        assert_eq!(opts.byte_range(), 10..30);
+        assert_eq!(
+            parse(&["--offset", "10"]).unwrap().byte_range(),
+            10..usize::MAX
+        );
+        assert_eq!(parse(&["--bytes", "20"]).unwrap().byte_range(), 0..20);
+        assert!(!parse(&[]).unwrap().has_byte_range());
+        for bad in [
+            &["--bytes", "-1"][..],
+            &["--offset", "x"],
+            &["--bytes", "20", "--limit", "5"],
+            &["--offset", "10", "--shard", "0/2"],
+        ] {
+            assert!(parse(bad).is_err(), "{bad:?}");
+        }
+        let stdin = ["--bytes", "20", "-"].map(String::from);
+        assert!(Options::parse(stdin).is_err());
+    }
+
+    #[test]
+    fn test_watch_dir_options() {
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        let opts = parse(&["--watch-dir", "in", "--output", "out.txt"]).unwrap();
+        assert_eq!(opts.watch_dir, Some("in".into()));
+        assert_eq!(opts.output, Some("out.txt".into()));
+        assert!(!opts.is_stdin());
+        for bad in [
+            &["--watch-dir", "in", "x.txt"][..],
+            &["--watch-dir", "in", "--glob", "*.txt"],
+            &["--watch-dir", "in", "--repeat", "2"],
+            &["--watch-dir", "in", "--header"],
+            &["--watch-dir", "in", "--bytes", "10"],
+        ] {
+            assert!(parse(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_output_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        std::fs::write(&path, "old results").unwrap();
+        let args = ["--output", path.to_str().unwrap(), "x.txt"];
+        let opts = Options::parse(args.map(String::from)).unwrap();
+        let summary = summarize(process_data(b"Oslo;-3.0\n", opts.config(), None).unwrap());
+        write_output(&summary, &opts).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{Oslo=-3.0/-3.0/-3.0}\n"
+        );
+        // and the temporary file is gone
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_shard_options() {
+        let shard = |value: &str| {
+            Options::parse(["--shard", value, "measurements.txt"].map(String::from))
+                .map(|opts| opts.shard)
+        };
+        assert_eq!(shard("0/3"), Ok(Some((0, 3))));
+        assert_eq!(shard("2/3"), Ok(Some((2, 3))));
+        for bad in ["3/3", "0/0", "1", "-1/2", "a/b"] {
+            assert!(shard(bad).is_err(), "{bad}");
+        }
+        let args = ["--shard", "0/2", "--limit", "5", "measurements.txt"].map(String::from);
+        assert!(Options::parse(args).is_err());
+    }
+
+    #[test]
+    fn test_stdin_options() {
+        let parse = |args: &[&str]| Options::parse(args.iter().map(|s| s.to_string()));
+        assert!(parse(&["-"]).unwrap().is_stdin());
+        assert!(!parse(&["measurements.txt"]).unwrap().is_stdin());
+        // these all need the whole input up front
+        assert!(parse(&["--limit", "10", "-"]).is_err());
+        assert!(parse(&["--repeat", "2", "-"]).is_err());
+        assert!(parse(&["--bench-log", "log.csv", "-"]).is_err());
+        // and this is only for stdin
+        assert!(parse(&["--max-line-bytes", "100", "-"]).is_ok());
+        assert!(parse(&["--max-line-bytes", "100", "measurements.txt"]).is_err());
+        assert!(parse(&["--max-line-bytes", "0", "-"]).is_err());
+        assert!(parse(&["--histogram", "Paris", "-"]).is_err());
+        assert!(parse(&["--info", "-"]).is_err());
+        assert!(parse(&["--header", "-"]).is_err());
+        assert!(parse(&["--shard", "0/2", "-"]).is_err());
+        assert!(parse(&["--limit", "10", "measurements.txt"]).is_ok());
+    }
 }
@@ -0,0 +1,45 @@
+/// Peak resident set size of this process so far, in bytes. None if it's not available on this
+/// platform.
+#[cfg(unix)]
+pub fn peak_rss() -> Option<u64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes to the struct we give it, and we only read it on success
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let maxrss = u64::try_from(usage.ru_maxrss).ok()?;
+    // Apple reports bytes, everyone else uses KiB
+    if cfg!(target_vendor = "apple") {
+        Some(maxrss)
+    } else {
+        Some(maxrss * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn peak_rss() -> Option<u64> {
+    None
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn test_peak_rss() {
+    use onebrc::{process_data, Config};
+
+    let before = peak_rss().unwrap();
+    assert!(before > 0);
+
+    // a high-cardinality input, with the results kept alive so they count towards the peak
+    let data: Vec<u8> = (0..500_000)
+        .flat_map(|i| format!("city{i};{}.{}\n", i % 100, i % 10).into_bytes())
+        .collect();
+    let results = process_data(&data, Config::default(), None).unwrap();
+    let after = peak_rss().unwrap();
+    assert!(after >= before);
+    // at least the input and the unique city names have to be resident at the same time
+    assert!(after > data.len() as u64 * 2, "{after}");
+    drop(results);
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use bstr::BStr;
+
+use crate::{process_chunk, split_chunks, Config, Progress, ResultsMap, Row, MAX_CHUNK_SIZE};
+
+/// Aggregate `data` for `Strategy::Numa`, with one thread pinned to each CPU this process is
+/// allowed to run on.
+///
+/// The input is split into one contiguous region per thread, in order of the threads' NUMA nodes,
+/// so that all of one node's threads together work through one contiguous part of the input.
+/// Pages that aren't cached yet get read into the memory of the node that touches them first, and
+/// after that each thread is (mostly) reading memory local to its own socket rather than going
+/// across the interconnect. Each thread works through its region a chunk at a time, like the
+/// chunked strategy, so that progress still gets updated as it goes.
+///
+/// This uses its own threads rather than rayon's pool, since rayon can't pin them or say which
+/// thread runs what, so `RAYON_NUM_THREADS` doesn't apply.
+///
+/// So far this has only been benchmarked on a single-node machine, where it's the same speed as
+/// the chunked strategy (0.86s vs 0.85s median on 20M rows). How much it helps on a multi-socket
+/// machine is still to be measured.
+pub(crate) fn process_numa<'a, P>(
+    data: &'a [u8],
+    config: Config,
+    progress: Option<&Progress>,
+    parse: P,
+) -> ResultsMap<&'a BStr>
+where
+    P: for<'l> Fn(&'l BStr) -> Option<Row<'l>> + Copy + Sync,
+{
+    let cpus: Vec<usize> = nodes().into_iter().flatten().collect();
+    let regions = split_chunks(data, cpus.len(), config.record_sep);
+    // P is only Sync, but a reference to it can be sent to the threads
+    let parse = &parse;
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = regions
+            .into_iter()
+            .zip(cpus)
+            .map(|(region, cpu)| {
+                scope.spawn(move || {
+                    pin_to_cpu(cpu);
+                    let count = region.len() / MAX_CHUNK_SIZE + 1;
+                    split_chunks(region, count, config.record_sep)
+                        .into_iter()
+                        .map(|chunk| process_chunk(chunk, config, progress, parse))
+                        .fold(ResultsMap::new(config), std::ops::Add::add)
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| {
+                thread
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .fold(ResultsMap::new(config), std::ops::Add::add)
+    })
+}
+
+/// The CPUs this process is allowed to run on, grouped by NUMA node in node order. If sysfs
+/// doesn't have the topology, they're all treated as one node.
+fn nodes() -> Vec<Vec<usize>> {
+    let allowed = allowed_cpus();
+    let node_dir = Path::new("/sys/devices/system/node");
+    let mut ids: Vec<usize> = std::fs::read_dir(node_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .ok()?
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    ids.sort_unstable();
+
+    let mut nodes: Vec<Vec<usize>> = ids
+        .into_iter()
+        .filter_map(|id| std::fs::read_to_string(node_dir.join(format!("node{id}/cpulist"))).ok())
+        .map(|list| {
+            parse_cpulist(list.trim())
+                .into_iter()
+                .filter(|cpu| allowed.contains(cpu))
+                .collect::<Vec<_>>()
+        })
+        .filter(|cpus| !cpus.is_empty())
+        .collect();
+    if nodes.is_empty() {
+        nodes.push(allowed);
+    }
+    nodes
+}
+
+/// Parse a kernel CPU list like `0-3,8-11,16`. Anything invalid is skipped.
+fn parse_cpulist(list: &str) -> Vec<usize> {
+    list.split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((first, last)) => Some(first.parse().ok()?..=last.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                Some(cpu..=cpu)
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+/// The CPUs in this process's affinity mask, which may be fewer than the machine has (e.g. with
+/// `taskset` or in a container). If that can't be read, assume the first
+/// `available_parallelism` of them.
+fn allowed_cpus() -> Vec<usize> {
+    // SAFETY: cpu_set_t is a plain bitmask, for which all zeroes is valid (and empty), and
+    // sched_getaffinity only writes within the size it's given.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) == 0 {
+            let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect();
+            if !cpus.is_empty() {
+                return cpus;
+            }
+        }
+    }
+    let count = std::thread::available_parallelism().map_or(1, |n| n.get());
+    (0..count).collect()
+}
+
+/// Pin the calling thread to one CPU. That's only an optimization, so if it fails the thread
+/// just carries on unpinned.
+fn pin_to_cpu(cpu: usize) {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return;
+    }
+    // SAFETY: as in allowed_cpus, and sched_setaffinity only reads the set
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_numa() {
+    use crate::{process_data, summarize, Strategy};
+
+    assert_eq!(parse_cpulist("0-3,8-9,12"), [0, 1, 2, 3, 8, 9, 12]);
+    assert_eq!(parse_cpulist("5"), [5]);
+    assert_eq!(parse_cpulist(""), []);
+
+    // every allowed CPU is in exactly one node
+    let mut cpus: Vec<usize> = nodes().into_iter().flatten().collect();
+    cpus.sort_unstable();
+    assert_eq!(cpus, allowed_cpus());
+
+    for name in [
+        "measurements-10000-unique-keys.txt",
+        "measurements-boundaries.txt",
+        "mode.txt",
+    ] {
+        let data = std::fs::read(Path::new("testdata").join(name)).unwrap();
+        let run = |config| {
+            format!(
+                "{:?}",
+                summarize(process_data(&data, config, None).unwrap())
+            )
+        };
+        for histogram in [false, true] {
+            let config = Config {
+                histogram,
+                ..Config::default()
+            };
+            let numa = Config {
+                strategy: Strategy::Numa,
+                ..config
+            };
+            assert_eq!(run(numa), run(config), "{name}");
+        }
+    }
+
+    // fewer lines than threads, on anything with more than one CPU
+    let numa = Config {
+        strategy: Strategy::Numa,
+        ..Config::default()
+    };
+    let summary = summarize(process_data(b"Paris;12.3", numa, None).unwrap());
+    assert_eq!(summary[0].1.to_string(), "12.3/12.3/12.3");
+    assert!(process_data(b"", numa, None).unwrap().map.is_empty());
+}
@@ -0,0 +1,737 @@
+use std::num::{NonZeroU16, NonZeroU32, NonZeroU8, NonZeroUsize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use bstr::{BString, ByteSlice};
+
+#[cfg(feature = "tdigest")]
+use onebrc::Quantile;
+use onebrc::{
+    Config, Decimal, FieldOrder, GroupBy, Rounding, Sample, Strategy, Temperature, TimeWindow, Unit,
+};
+
+use crate::output::Format;
+
+/// Command line options. Parsed by hand, there aren't enough of them to justify pulling in a
+/// whole argument parsing crate.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// measurements file to read
+    pub path: PathBuf,
+    /// also report the most common temperature for each city
+    pub with_mode: bool,
+    /// also report each city's first and last temperatures in input order, which implies
+    /// `single_threaded` so that they're exact
+    pub with_endpoints: bool,
+    /// which order the city and temperature fields are in
+    pub order: FieldOrder,
+    /// read the temperature from this field (counting from 1) and ignore any after it
+    pub temp_field: Option<NonZeroU8>,
+    /// read the city from this field, only set from a `--csv-header`
+    pub city_field: Option<NonZeroU8>,
+    /// the first line is a CSV header naming the columns, see `apply_csv_header`
+    pub csv_header: bool,
+    /// name of the city column with `csv_header`
+    pub city_col: Option<String>,
+    /// name of the temperature column with `csv_header`
+    pub temp_col: Option<String>,
+    /// how to split up work between threads
+    pub strategy: Strategy,
+    /// show a progress indicator on stderr (only if it's a terminal)
+    pub progress: bool,
+    /// show a live view of the hottest and coldest cities so far on stderr (only if it's a
+    /// terminal)
+    pub watch: bool,
+    /// don't print any extra info to stderr
+    pub quiet: bool,
+    /// print some stats about the run to stderr at the end (unless `quiet` is also set)
+    pub verbose: bool,
+    /// temperature unit for output
+    pub unit: Unit,
+    /// treat numbers without a decimal point as whole degrees rather than tenths
+    pub allow_integer: bool,
+    /// every temperature is whole degrees, with no decimal point
+    pub int_degrees: bool,
+    /// validate every line and fail on invalid input
+    pub strict: bool,
+    /// print the mean with this many decimal places, rather than the default one
+    pub mean_precision: Option<u32>,
+    /// fail if any city name isn't valid UTF-8
+    pub validate_utf8: bool,
+    /// only process this many lines from the start of the input
+    pub limit: Option<usize>,
+    /// only process shard K (counting from 0) of N of each input file, as (K, N)
+    pub shard: Option<(usize, usize)>,
+    /// only process the records starting this many bytes or more into each input file, after any
+    /// header
+    pub offset: Option<usize>,
+    /// only process the records starting within this many bytes of `offset`
+    pub bytes: Option<usize>,
+    /// output format
+    pub format: Format,
+    /// leave out the newline after the closing brace
+    pub no_final_newline: bool,
+    /// don't use any worker threads
+    pub single_threaded: bool,
+    /// fail rather than wrap if a city's total or count overflows
+    pub safe: bool,
+    /// decimal separator in temperatures
+    pub decimal: Decimal,
+    /// decimal separator in output temperatures, only for the text formats (the binary format
+    /// doesn't have one)
+    pub out_decimal: Decimal,
+    /// process the input this many times, printing how long each one took
+    pub repeat: Option<usize>,
+    /// append how long each run took to this CSV file
+    pub bench_log: Option<PathBuf>,
+    /// print how long each phase of the run took to stderr
+    pub profile: bool,
+    /// print every city's exact mean, before rounding, to stderr
+    pub debug_means: bool,
+    /// longest line allowed when reading stdin, if not `MAX_LINE_BYTES`
+    pub max_line_bytes: Option<NonZeroUsize>,
+    /// read all the files matching this pattern rather than `path`
+    pub glob: Option<String>,
+    /// rather than `path`, keep processing every new file that shows up in this directory, see
+    /// `watch_dir::DirWatcher`
+    pub watch_dir: Option<PathBuf>,
+    /// write the results to this file rather than stdout, replacing it all at once
+    pub output: Option<PathBuf>,
+    /// sanity check every city's final stats before printing them
+    pub self_check: bool,
+    /// split each line at the last ';' rather than the first
+    pub split_last: bool,
+    /// print the full temperature histogram for this city
+    pub histogram_city: Option<BString>,
+    /// cities to leave out of the output
+    pub exclude: Vec<BString>,
+    /// only output these cities (or ones matching `prefix`)
+    pub only: Vec<BString>,
+    /// only output cities starting with one of these (or ones listed in `only`)
+    pub prefix: Vec<BString>,
+    /// only aggregate about this fraction of lines, for quick approximate results
+    pub sample: Option<f64>,
+    /// seed for picking which lines are sampled
+    pub sample_seed: u64,
+    /// smallest piece of input each parallel task gets, with the lines strategy
+    pub min_chunk_bytes: Option<NonZeroU32>,
+    /// just estimate the input's size, rows, and cities from a sample, without aggregating
+    pub info: bool,
+    /// read settings from a leading `#` line in the input, see `apply_header`
+    pub header: bool,
+    /// field delimiter, if not the default ';'
+    pub delimiter: Option<u8>,
+    /// split fields at runs of whitespace rather than `delimiter`
+    pub whitespace: bool,
+    /// size of rayon's thread pool, if not one per CPU (does nothing without rayon, or for the
+    /// numa strategy, which has its own threads)
+    pub threads: Option<NonZeroUsize>,
+    /// ignore this many bytes at the end of each line
+    pub line_suffix_bytes: u8,
+    /// print a histogram of all temperatures regardless of city, to stderr
+    pub global_histogram: bool,
+    /// write the global histogram to this file instead of stderr
+    pub global_histogram_file: Option<PathBuf>,
+    /// skip rows colder than this, in the input's unit
+    pub min_temp: Option<Temperature>,
+    /// skip rows hotter than this, in the input's unit
+    pub max_temp: Option<Temperature>,
+    /// intern city names into integer ids while aggregating each chunk
+    pub intern: bool,
+    /// record separator, if not the default '\n'
+    pub record_sep: Option<u8>,
+    /// make room for this many cities up front in each map
+    pub expected_cities: u32,
+    /// lump together any cities after this many into "(other)"
+    pub max_cities: Option<NonZeroU16>,
+    /// lines start with a timestamp, and only ones from this many seconds since the epoch on
+    /// are aggregated
+    pub since: Option<u32>,
+    /// lines start with a timestamp, and only ones before this are aggregated
+    pub until: Option<u32>,
+    /// each line ends with a weight field, how many observations it stands for (implies `safe`)
+    pub weighted: bool,
+    /// work out means from an f64 total, like reference implementations that use doubles
+    pub float_accum: bool,
+    /// round means that are exactly halfway up, like Java's Math.round, and the same for any
+    /// temperatures converted to another unit
+    pub reference_rounding: bool,
+    /// skip rows with an empty city name rather than reporting them as a city called ""
+    pub skip_empty_city: bool,
+    /// aggregate by a group derived from the city name, like `prefix:2` or `before:/`
+    pub group_by: GroupBy,
+    /// print cities in the order they first appear in the input, rather than by name
+    pub order_as_seen: bool,
+    /// also report this quantile of each city's temperatures, estimated with a t-digest
+    #[cfg(feature = "tdigest")]
+    pub quantile: Option<Quantile>,
+}
+
+impl Options {
+    /// Parse options from an argument list, not including the program name (argv[0]), ignoring
+    /// the environment so that tests don't depend on where they run
+    #[cfg(test)]
+    pub fn parse<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        Self::parse_with_env(args, |_| None)
+    }
+
+    /// Parse options from an argument list, not including the program name (argv[0]), starting
+    /// from defaults set in environment variables, which `env` looks up by name:
+    ///
+    /// * `ONEBRC_THREADS`: like `--threads`
+    /// * `ONEBRC_FORMAT`: like `--format`
+    /// * `ONEBRC_DELIM`: like `--delimiter`
+    ///
+    /// Anything given on the command line takes precedence over these, and they take precedence
+    /// over the built-in defaults. A `--header` line in the input overrides all of them. An
+    /// empty variable counts as unset.
+    pub fn parse_with_env<I>(args: I, env: impl Fn(&str) -> Option<String>) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut opts = Self::default();
+        let env = |name| env(name).filter(|value| !value.is_empty());
+        if let Some(threads) = env("ONEBRC_THREADS") {
+            opts.threads = Some(
+                threads
+                    .parse()
+                    .map_err(|_| format!("invalid thread count '{threads}' in ONEBRC_THREADS"))?,
+            );
+        }
+        if let Some(format) = env("ONEBRC_FORMAT") {
+            opts.format = format
+                .parse()
+                .map_err(|err| format!("{err} in ONEBRC_FORMAT"))?;
+        }
+        if let Some(delim) = env("ONEBRC_DELIM") {
+            opts.delimiter = Some(
+                delimiter(&delim)
+                    .ok_or_else(|| format!("invalid delimiter '{delim}' in ONEBRC_DELIM"))?,
+            );
+        }
+
+        let mut path = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--with-mode" => opts.with_mode = true,
+                "--with-endpoints" => opts.with_endpoints = true,
+                "--order" => opts.order = value(&mut args, &arg)?.parse()?,
+                "--strategy" => opts.strategy = value(&mut args, &arg)?.parse()?,
+                "--progress" => opts.progress = true,
+                "--watch" => opts.watch = true,
+                "-q" | "--quiet" => opts.quiet = true,
+                "-v" | "--verbose" => opts.verbose = true,
+                "--profile" => opts.profile = true,
+                "--debug-means" => opts.debug_means = true,
+                "--unit" => opts.unit = value(&mut args, &arg)?.parse()?,
+                "--assume-integer-tenths-off" => opts.allow_integer = true,
+                "--int-degrees" => opts.int_degrees = true,
+                "--strict" => opts.strict = true,
+                "--validate-utf8" => opts.validate_utf8 = true,
+                "--exclude" => opts.exclude.push(value(&mut args, &arg)?.into()),
+                "--only" => opts.only.push(value(&mut args, &arg)?.into()),
+                "--prefix" => opts.prefix.push(value(&mut args, &arg)?.into()),
+                "--single-threaded" => opts.single_threaded = true,
+                "--safe" => opts.safe = true,
+                "--self-check" => opts.self_check = true,
+                "--split-last" => opts.split_last = true,
+                "--whitespace" => opts.whitespace = true,
+                "--info" => opts.info = true,
+                "--header" => opts.header = true,
+                "--csv-header" => opts.csv_header = true,
+                "--city-col" => opts.city_col = Some(value(&mut args, &arg)?),
+                "--temp-col" => opts.temp_col = Some(value(&mut args, &arg)?),
+                "--intern" => opts.intern = true,
+                "--weighted" => opts.weighted = true,
+                "--float-accum" => opts.float_accum = true,
+                "--reference-rounding" => opts.reference_rounding = true,
+                "--skip-empty-city" => opts.skip_empty_city = true,
+                "--order-as-seen" => opts.order_as_seen = true,
+                "--no-final-newline" => opts.no_final_newline = true,
+                "--global-histogram" => opts.global_histogram = true,
+                "--global-histogram-file" => {
+                    opts.global_histogram = true;
+                    opts.global_histogram_file = Some(value(&mut args, &arg)?.into());
+                }
+                "--histogram" => opts.histogram_city = Some(value(&mut args, &arg)?.into()),
+                "--glob" => opts.glob = Some(value(&mut args, &arg)?),
+                "--watch-dir" => opts.watch_dir = Some(value(&mut args, &arg)?.into()),
+                "-o" | "--output" => opts.output = Some(value(&mut args, &arg)?.into()),
+                "--bench-log" => opts.bench_log = Some(value(&mut args, &arg)?.into()),
+                "--decimal" => opts.decimal = value(&mut args, &arg)?.parse()?,
+                "--out-decimal" => opts.out_decimal = value(&mut args, &arg)?.parse()?,
+                "--group-by" => opts.group_by = value(&mut args, &arg)?.parse()?,
+                "--format" => opts.format = value(&mut args, &arg)?.parse()?,
+                "--delimiter" => {
+                    let delim = value(&mut args, &arg)?;
+                    opts.delimiter = Some(
+                        delimiter(&delim).ok_or_else(|| format!("invalid delimiter '{delim}'"))?,
+                    );
+                }
+                "--threads" => {
+                    let threads = value(&mut args, &arg)?;
+                    opts.threads = Some(
+                        threads
+                            .parse()
+                            .map_err(|_| format!("invalid thread count '{threads}'"))?,
+                    );
+                }
+                "--shard" => {
+                    let shard = value(&mut args, &arg)?;
+                    opts.shard = Some(parse_shard(&shard).ok_or_else(|| {
+                        format!("invalid shard '{shard}', expected K/N with K from 0 to N-1")
+                    })?);
+                }
+                "--limit" => {
+                    let limit = value(&mut args, &arg)?;
+                    opts.limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| format!("invalid limit '{limit}'"))?,
+                    );
+                }
+                "--offset" => {
+                    let offset = value(&mut args, &arg)?;
+                    opts.offset = Some(
+                        offset
+                            .parse()
+                            .map_err(|_| format!("invalid offset '{offset}'"))?,
+                    );
+                }
+                "--bytes" => {
+                    let bytes = value(&mut args, &arg)?;
+                    opts.bytes = Some(
+                        bytes
+                            .parse()
+                            .map_err(|_| format!("invalid byte count '{bytes}'"))?,
+                    );
+                }
+                "--repeat" => {
+                    let count = value(&mut args, &arg)?;
+                    opts.repeat = Some(
+                        count
+                            .parse()
+                            .ok()
+                            .filter(|n| *n > 0)
+                            .ok_or_else(|| format!("invalid repeat count '{count}'"))?,
+                    );
+                }
+                "--sample" => {
+                    let fraction = value(&mut args, &arg)?;
+                    opts.sample = Some(
+                        fraction
+                            .parse()
+                            .ok()
+                            .filter(|f| Sample::new(*f, 0).is_some())
+                            .ok_or_else(|| format!("invalid sample fraction '{fraction}'"))?,
+                    );
+                }
+                "--line-suffix-bytes" => {
+                    let bytes = value(&mut args, &arg)?;
+                    opts.line_suffix_bytes = bytes
+                        .parse()
+                        .map_err(|_| format!("invalid line suffix length '{bytes}'"))?;
+                }
+                "--expected-cities" => {
+                    let cities = value(&mut args, &arg)?;
+                    opts.expected_cities = cities
+                        .parse()
+                        .map_err(|_| format!("invalid city count '{cities}'"))?;
+                }
+                "--max-cities" => {
+                    let cities = value(&mut args, &arg)?;
+                    opts.max_cities = Some(cities.parse().map_err(|_| {
+                        format!("invalid city limit '{cities}', expected 1 to 65535")
+                    })?);
+                }
+                "--since" => opts.since = Some(parse_timestamp(&value(&mut args, &arg)?)?),
+                "--until" => opts.until = Some(parse_timestamp(&value(&mut args, &arg)?)?),
+                "--max-line-bytes" => {
+                    let bytes = value(&mut args, &arg)?;
+                    opts.max_line_bytes = Some(
+                        bytes
+                            .parse()
+                            .map_err(|_| format!("invalid line length '{bytes}'"))?,
+                    );
+                }
+                "--min-chunk-bytes" => {
+                    let bytes = value(&mut args, &arg)?;
+                    opts.min_chunk_bytes = Some(
+                        bytes
+                            .parse()
+                            .map_err(|_| format!("invalid chunk size '{bytes}'"))?,
+                    );
+                }
+                "--sample-seed" => {
+                    let seed = value(&mut args, &arg)?;
+                    opts.sample_seed = seed
+                        .parse()
+                        .map_err(|_| format!("invalid sample seed '{seed}'"))?;
+                }
+                "--temp-field" => {
+                    let field = value(&mut args, &arg)?;
+                    opts.temp_field = Some(
+                        field
+                            .parse()
+                            .ok()
+                            .filter(|f: &NonZeroU8| f.get() >= 2)
+                            .ok_or_else(|| format!("invalid temperature field '{field}'"))?,
+                    );
+                }
+                "--min-temp" => opts.min_temp = Some(temperature(&value(&mut args, &arg)?)?),
+                "--max-temp" => opts.max_temp = Some(temperature(&value(&mut args, &arg)?)?),
+                "--record-sep" => {
+                    let sep = value(&mut args, &arg)?;
+                    opts.record_sep = Some(match sep.as_str() {
+                        "0" | "nul" | "\\0" => b'\0',
+                        "\\n" => b'\n',
+                        _ if sep.len() == 1 => sep.as_bytes()[0],
+                        _ => return Err(format!("invalid record separator '{sep}'")),
+                    });
+                }
+                #[cfg(feature = "tdigest")]
+                "--quantile" => {
+                    let q = value(&mut args, &arg)?;
+                    opts.quantile = Some(
+                        q.parse()
+                            .ok()
+                            .and_then(Quantile::new)
+                            .ok_or_else(|| format!("invalid quantile '{q}'"))?,
+                    );
+                }
+                #[cfg(not(feature = "tdigest"))]
+                "--quantile" => return Err("--quantile needs the tdigest feature".into()),
+                "--mean-precision" => {
+                    let decimals = value(&mut args, &arg)?
+                        .parse()
+                        .ok()
+                        .filter(|d| (1..=6).contains(d))
+                        .ok_or("mean precision must be between 1 and 6")?;
+                    opts.mean_precision = Some(decimals);
+                }
+                s if s.starts_with("--") => return Err(format!("unknown option '{s}'")),
+                _ => {
+                    if path.replace(arg).is_some() {
+                        return Err("too many filename arguments".into());
+                    }
+                }
+            }
+        }
+
+        if opts.strict && opts.allow_integer {
+            return Err("--strict and --assume-integer-tenths-off can't be used together".into());
+        }
+        if opts.int_degrees && opts.allow_integer {
+            return Err(
+                "--int-degrees and --assume-integer-tenths-off can't be used together".into(),
+            );
+        }
+
+        if opts.glob.is_some() && opts.watch_dir.is_some() {
+            return Err("--glob and --watch-dir can't be used together".into());
+        }
+        match (path, opts.glob.is_some() || opts.watch_dir.is_some()) {
+            (Some(_), true) => {
+                return Err("can't use a filename argument with --glob or --watch-dir".into())
+            }
+            (Some(path), false) => opts.path = path.into(),
+            (None, true) => (),
+            (None, false) => return Err("missing filename argument".into()),
+        }
+
+        if opts.record_sep == Some(opts.field_delimiter()) {
+            return Err("the record separator can't be the same as the field delimiter".into());
+        }
+
+        if (opts.since.is_some() || opts.until.is_some()) && opts.time_window().is_none() {
+            return Err("--since has to be before --until".into());
+        }
+
+        if opts.reference_rounding && opts.mean_precision.is_some() {
+            return Err("--reference-rounding can't be used with --mean-precision".into());
+        }
+
+        if let (Some(min), Some(max)) = (opts.min_temp, opts.max_temp) {
+            if min > max {
+                return Err(format!("--min-temp {min} is higher than --max-temp {max}"));
+            }
+        }
+
+        if opts.temp_field.is_some() && (opts.order == FieldOrder::TempCity || opts.split_last) {
+            return Err("--temp-field can't be used with --order temp-city or --split-last".into());
+        }
+
+        if opts.csv_header
+            && (opts.header
+                || opts.temp_field.is_some()
+                || opts.order == FieldOrder::TempCity
+                || opts.split_last)
+        {
+            return Err("--csv-header can't be used with --header, --temp-field, \
+                        --order temp-city, or --split-last"
+                .into());
+        }
+        if opts.whitespace && (opts.temp_field.is_some() || opts.csv_header) {
+            return Err("--whitespace can't be used with --temp-field or --csv-header".into());
+        }
+        if !opts.csv_header && (opts.city_col.is_some() || opts.temp_col.is_some()) {
+            return Err("--city-col and --temp-col only work with --csv-header".into());
+        }
+
+        if opts.no_final_newline && !matches!(opts.format, Format::Brace | Format::Compact) {
+            return Err("--no-final-newline only works with the brace and compact formats".into());
+        }
+
+        if opts.is_stdin() && (opts.limit.is_some() || opts.repeat.is_some()) {
+            return Err("--limit and --repeat can't be used when reading stdin".into());
+        }
+        if !opts.is_stdin() && opts.max_line_bytes.is_some() {
+            // mmapped files don't need anything buffered
+            return Err("--max-line-bytes only works when reading stdin".into());
+        }
+        if opts.is_stdin() && opts.bench_log.is_some() {
+            return Err("--bench-log can't be used when reading stdin".into());
+        }
+        if opts.is_stdin() && opts.shard.is_some() {
+            return Err("--shard can't be used when reading stdin".into());
+        }
+        if opts.shard.is_some() && opts.limit.is_some() {
+            return Err("--shard and --limit can't be used together".into());
+        }
+        if opts.has_byte_range() {
+            if opts.is_stdin() {
+                return Err("--offset and --bytes can't be used when reading stdin".into());
+            }
+            if opts.shard.is_some() || opts.limit.is_some() {
+                return Err("--offset and --bytes can't be used with --shard or --limit".into());
+            }
+        }
+        if opts.is_stdin() && opts.histogram_city.is_some() {
+            return Err("--histogram can't be used when reading stdin".into());
+        }
+        if opts.is_stdin() && opts.info {
+            return Err("--info can't be used when reading stdin".into());
+        }
+        if opts.is_stdin() && (opts.header || opts.csv_header) {
+            return Err("--header and --csv-header can't be used when reading stdin".into());
+        }
+        // these all only make sense for a single run over inputs that are there from the start
+        if opts.watch_dir.is_some()
+            && (opts.info
+                || opts.repeat.is_some()
+                || opts.bench_log.is_some()
+                || opts.histogram_city.is_some()
+                || opts.global_histogram
+                || opts.header
+                || opts.csv_header
+                || opts.limit.is_some()
+                || opts.shard.is_some()
+                || opts.has_byte_range()
+                || opts.self_check
+                || opts.validate_utf8
+                || opts.profile)
+        {
+            return Err(
+                "--watch-dir can't be used with --info, --repeat, --bench-log, \
+                 --histogram, --global-histogram, --header, --csv-header, --limit, \
+                 --shard, --offset, --bytes, --self-check, --validate-utf8, or --profile"
+                    .into(),
+            );
+        }
+        Ok(opts)
+    }
+
+    /// Apply the settings from a header line like `delim=; unit=C prec=1` (without the leading
+    /// `#`), overriding any given on the command line. Settings are separated by spaces, and
+    /// `delim` is the same as `--delimiter`.
+    pub fn apply_header(&mut self, header: &[u8]) -> Result<(), String> {
+        let header = header
+            .to_str()
+            .map_err(|_| "header line isn't valid UTF-8".to_string())?;
+        for setting in header.split(' ').filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("invalid header setting '{setting}'"))?;
+            match key {
+                "delim" => {
+                    self.delimiter = Some(
+                        delimiter(value)
+                            .ok_or_else(|| format!("invalid header delimiter '{value}'"))?,
+                    )
+                }
+                "unit" => self.unit = value.to_lowercase().parse()?,
+                "prec" => {
+                    let decimals = value
+                        .parse()
+                        .ok()
+                        .filter(|d| (1..=6).contains(d))
+                        .ok_or("header precision must be between 1 and 6")?;
+                    self.mean_precision = Some(decimals);
+                }
+                _ => return Err(format!("unknown header setting '{key}'")),
+            }
+        }
+        if self.delimiter.is_some() && self.delimiter == self.record_sep {
+            return Err("the record separator can't be the same as the field delimiter".into());
+        }
+        Ok(())
+    }
+
+    /// Find the city and temperature columns named in a `--csv-header` line, `city` and
+    /// `temperature` unless `--city-col` and `--temp-col` say otherwise. Every file has to have
+    /// them in the same places, since they all get aggregated with the same settings.
+    pub fn apply_csv_header(&mut self, header: &[u8]) -> Result<(), String> {
+        let city_col = self.city_col.as_deref().unwrap_or("city");
+        let temp_col = self.temp_col.as_deref().unwrap_or("temperature");
+        let (city, temp) = self.config().csv_columns(header, city_col, temp_col)?;
+        if self.city_field.is_some_and(|f| f != city) || self.temp_field.is_some_and(|f| f != temp)
+        {
+            return Err("the CSV header columns aren't in the same order in every file".into());
+        }
+        self.city_field = Some(city);
+        self.temp_field = Some(temp);
+        Ok(())
+    }
+
+    /// the field delimiter: ',' for `--csv-header` unless one was given, otherwise ';'
+    fn field_delimiter(&self) -> u8 {
+        match self.delimiter {
+            Some(delimiter) => delimiter,
+            None if self.csv_header => b',',
+            None => Config::default().delimiter,
+        }
+    }
+
+    /// whether to read from stdin rather than mmapping files, for a filename of "-"
+    pub fn is_stdin(&self) -> bool {
+        self.glob.is_none() && self.path == Path::new("-")
+    }
+
+    /// the aggregation settings from these options
+    pub fn config(&self) -> Config {
+        Config {
+            histogram: self.with_mode,
+            order: self.order,
+            strategy: self.strategy,
+            allow_integer: self.allow_integer,
+            int_degrees: self.int_degrees,
+            temp_field: self.temp_field,
+            city_field: self.city_field,
+            quoted: self.csv_header,
+            strict: self.strict,
+            single_threaded: self.single_threaded || self.with_endpoints,
+            safe: self.safe,
+            decimal: self.decimal,
+            split_last: self.split_last,
+            sample: self
+                .sample
+                .and_then(|fraction| Sample::new(fraction, self.sample_seed)),
+            min_chunk_bytes: self.min_chunk_bytes,
+            delimiter: self.field_delimiter(),
+            whitespace: self.whitespace,
+            line_suffix_bytes: self.line_suffix_bytes,
+            global_histogram: self.global_histogram,
+            min_temp: self.min_temp.unwrap_or(Temperature::MIN),
+            max_temp: self.max_temp.unwrap_or(Temperature::MAX),
+            intern: self.intern,
+            record_sep: self.record_sep.unwrap_or(Config::default().record_sep),
+            expected_cities: self.expected_cities,
+            max_cities: self.max_cities,
+            time_window: self.time_window(),
+            weighted: self.weighted,
+            float_accum: self.float_accum,
+            endpoints: self.with_endpoints,
+            reference_rounding: self.reference_rounding,
+            skip_empty_city: self.skip_empty_city,
+            group_by: self.group_by,
+            #[cfg(feature = "tdigest")]
+            quantile: self.quantile,
+        }
+    }
+
+    /// whether `--offset` or `--bytes` was given
+    pub fn has_byte_range(&self) -> bool {
+        self.offset.is_some() || self.bytes.is_some()
+    }
+
+    /// the byte range of each input file to process, from `--offset` and `--bytes`
+    pub fn byte_range(&self) -> Range<usize> {
+        let start = self.offset.unwrap_or(0);
+        start
+            ..self
+                .bytes
+                .map_or(usize::MAX, |bytes| start.saturating_add(bytes))
+    }
+
+    /// the window from `--since` and `--until`, if either was given
+    fn time_window(&self) -> Option<TimeWindow> {
+        if self.since.is_none() && self.until.is_none() {
+            return None;
+        }
+        TimeWindow::new(self.since, self.until)
+    }
+
+    /// how to round temperatures converted to another unit, the same way as the mean
+    pub fn rounding(&self) -> Rounding {
+        if self.reference_rounding {
+            Rounding::HalfUp
+        } else {
+            Rounding::HalfAwayFromZero
+        }
+    }
+
+    /// whether there's a quantile column in the output
+    pub fn has_quantile(&self) -> bool {
+        #[cfg(feature = "tdigest")]
+        return self.quantile.is_some();
+        #[cfg(not(feature = "tdigest"))]
+        false
+    }
+}
+
+/// get the value for option `name`, which is the next argument
+fn value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("missing value for option '{name}'"))
+}
+
+/// Parse a `--since` or `--until` timestamp, in whole seconds since the Unix epoch
+fn parse_timestamp(s: &str) -> Result<u32, String> {
+    s.parse()
+        .map_err(|_| format!("invalid timestamp '{s}', expected seconds since 1970"))
+}
+
+/// Parse a shard like `2/3`, the third of three
+fn parse_shard(s: &str) -> Option<(usize, usize)> {
+    let (index, count) = s.split_once('/')?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    (index < count).then_some((index, count))
+}
+
+/// Parse a field delimiter, which can be a single character, `tab`, or `\t`
+fn delimiter(s: &str) -> Option<u8> {
+    match s {
+        "tab" | "\\t" => Some(b'\t'),
+        _ if s.len() == 1 => Some(s.as_bytes()[0]),
+        _ => None,
+    }
+}
+
+/// Parse a temperature threshold like `20.0` or `-5`. A whole number is whole degrees, like
+/// `--assume-integer-tenths-off`, since that's what anyone typing one on the command line means.
+fn temperature(s: &str) -> Result<Temperature, String> {
+    Temperature::parse_strict(s)
+        .or_else(|_| {
+            s.parse::<i32>()
+                .ok()
+                .and_then(|degrees| degrees.checked_mul(10))
+                .map(Temperature::from_tenths)
+                .ok_or(())
+        })
+        .map_err(|_| format!("invalid temperature '{s}'"))
+}
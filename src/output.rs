@@ -0,0 +1,270 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use bstr::{BString, ByteSlice};
+use onebrc::{write_binary, Decimal, FinalStats, GlobalHistogram, Histogram, Temperature};
+
+use crate::options::Options;
+
+/// Output format for the final results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// the challenge's `{city=min/mean/max, ...}` format
+    #[default]
+    Brace,
+    /// CSV with a header row
+    Csv,
+    /// like `Brace`, but without a `.0` on whole-degree temperatures
+    Compact,
+    /// each `city=min/mean/max` on its own line, without the braces, for line-oriented tools
+    Lines,
+    /// the binary encoding from `onebrc::write_binary`, always in Celsius
+    Binary,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brace" => Ok(Self::Brace),
+            "csv" => Ok(Self::Csv),
+            "compact" => Ok(Self::Compact),
+            "lines" => Ok(Self::Lines),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!("invalid output format '{s}'")),
+        }
+    }
+}
+
+/// One city's stats as strings, converted and with the precision and decimal separator requested
+/// in the options
+struct Fields {
+    min: String,
+    mean: String,
+    max: String,
+    mode: Option<String>,
+    quantile: Option<String>,
+    /// first and last temperatures, with `--with-endpoints`
+    endpoints: Option<(String, String)>,
+}
+
+impl Fields {
+    fn new(stats: &FinalStats, opts: &Options) -> Self {
+        let converted = stats.to_unit_rounded(opts.unit, opts.rounding());
+        let separator = |s: String| match opts.out_decimal {
+            Decimal::Point => s,
+            Decimal::Comma => s.replace('.', ","),
+        };
+        let show = |temp: Temperature| {
+            separator(if opts.format == Format::Compact {
+                temp.compact().to_string()
+            } else {
+                temp.to_string()
+            })
+        };
+        // an explicit mean precision always gets all its decimal places
+        let mean = match opts.mean_precision {
+            Some(decimals) => separator(stats.precise_mean(opts.unit, decimals).to_string()),
+            None => show(converted.mean()),
+        };
+        Self {
+            min: show(converted.min()),
+            mean,
+            max: show(converted.max()),
+            mode: converted.mode().map(show),
+            quantile: converted.quantile().map(show),
+            endpoints: converted
+                .first()
+                .zip(converted.last())
+                .filter(|_| opts.with_endpoints)
+                .map(|(first, last)| (show(first), show(last))),
+        }
+    }
+}
+
+/// Print results in the format requested in `opts`, converting from Celsius and adjusting
+/// precision as needed. Results should already be sorted.
+pub fn write_results(
+    out: &mut impl Write,
+    summary: &[(BString, FinalStats)],
+    opts: &Options,
+) -> io::Result<()> {
+    match opts.format {
+        Format::Brace | Format::Compact => write_brace(out, summary, opts),
+        Format::Csv => write_csv(out, summary, opts),
+        Format::Lines => write_lines(out, summary, opts),
+        Format::Binary => write_binary(out, summary),
+    }
+}
+
+/// Print results in the challenge's `{city=min/mean/max, ...}` format, with a newline at the end
+/// unless `--no-final-newline` was given
+fn write_brace(
+    out: &mut impl Write,
+    summary: &[(BString, FinalStats)],
+    opts: &Options,
+) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (i, (city, stats)) in summary.iter().enumerate() {
+        let comma = if i == 0 { "" } else { ", " };
+        write!(out, "{comma}")?;
+        write_city(out, city, stats, opts)?;
+    }
+    write!(out, "}}")?;
+    if !opts.no_final_newline {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Print results one `city=min/mean/max` per line, in the same sorted order
+fn write_lines(
+    out: &mut impl Write,
+    summary: &[(BString, FinalStats)],
+    opts: &Options,
+) -> io::Result<()> {
+    for (city, stats) in summary {
+        write_city(out, city, stats, opts)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Print one city's `city=min/mean/max` (plus `/mode`, `/quantile` and `/first/last` if enabled)
+fn write_city(
+    out: &mut impl Write,
+    city: &BString,
+    stats: &FinalStats,
+    opts: &Options,
+) -> io::Result<()> {
+    let f = Fields::new(stats, opts);
+    write!(out, "{city}={}/{}/{}", f.min, f.mean, f.max)?;
+    if let Some(mode) = f.mode {
+        write!(out, "/{mode}")?;
+    }
+    if let Some(quantile) = f.quantile {
+        write!(out, "/{quantile}")?;
+    }
+    if let Some((first, last)) = f.endpoints {
+        write!(out, "/{first}/{last}")?;
+    }
+    Ok(())
+}
+
+/// Print results as CSV, with a `city,min,mean,max` header (plus `mode`, `quantile`, and
+/// `first,last` if enabled)
+fn write_csv(
+    out: &mut impl Write,
+    summary: &[(BString, FinalStats)],
+    opts: &Options,
+) -> io::Result<()> {
+    let mode_header = if opts.with_mode { ",mode" } else { "" };
+    let quantile_header = if opts.has_quantile() { ",quantile" } else { "" };
+    let endpoints_header = if opts.with_endpoints {
+        ",first,last"
+    } else {
+        ""
+    };
+    writeln!(
+        out,
+        "city,min,mean,max{mode_header}{quantile_header}{endpoints_header}"
+    )?;
+    for (city, stats) in summary {
+        let f = Fields::new(stats, opts);
+        let city = csv_quote(city.to_str_lossy());
+        // temperatures need quoting too with `--out-decimal comma`
+        fn q(temp: &str) -> Cow<'_, str> {
+            csv_quote(temp.into())
+        }
+        write!(out, "{city},{},{},{}", q(&f.min), q(&f.mean), q(&f.max))?;
+        if let Some(mode) = &f.mode {
+            write!(out, ",{}", q(mode))?;
+        }
+        if let Some(quantile) = &f.quantile {
+            write!(out, ",{}", q(quantile))?;
+        }
+        if let Some((first, last)) = &f.endpoints {
+            write!(out, ",{},{}", q(first), q(last))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if needed, per RFC 4180
+fn csv_quote(s: Cow<str>) -> Cow<str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s
+    }
+}
+
+/// Write to `path` all at once, by writing a temporary file next to it and renaming that over it,
+/// so that anything reading `path` never sees partial output. The temporary file is hidden, so
+/// that it isn't picked up by `--watch-dir` if `path` is in the watched directory.
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut io::BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let mut out = io::BufWriter::new(File::create(&tmp)?);
+    let written = write(&mut out).and_then(|()| out.flush());
+    drop(out);
+    if let Err(err) = written.and_then(|()| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Print one city's temperature histogram, one `temperature count` line per non-empty bin. The
+/// temperatures are in Celsius, since the histogram bins are tenths of a degree Celsius.
+pub fn write_histogram(out: &mut impl Write, city: &[u8], hist: &Histogram) -> io::Result<()> {
+    writeln!(out, "histogram for {}:", city.as_bstr())?;
+    write_bins(out, hist.iter())
+}
+
+/// Print the histogram of all temperatures, in the same format as `write_histogram`
+pub fn write_global_histogram(out: &mut impl Write, hist: &GlobalHistogram) -> io::Result<()> {
+    writeln!(out, "histogram for all cities:")?;
+    write_bins(out, hist.iter())
+}
+
+/// Print every city's exact mean next to its rounded one, for `--debug-means`. The exact mean
+/// is always in Celsius, like the totals it comes from.
+pub fn write_exact_means(
+    out: &mut impl Write,
+    results: &[(BString, FinalStats)],
+) -> io::Result<()> {
+    for (city, stats) in results {
+        writeln!(
+            out,
+            "mean for {city}: {} (rounded to {})",
+            stats.exact_mean(),
+            stats.mean()
+        )?;
+    }
+    Ok(())
+}
+
+fn write_bins<C: Display>(
+    out: &mut impl Write,
+    bins: impl Iterator<Item = (Temperature, C)>,
+) -> io::Result<()> {
+    for (temp, count) in bins {
+        writeln!(out, "{:>6} {count}", temp.to_string())?;
+    }
+    Ok(())
+}
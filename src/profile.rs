@@ -0,0 +1,73 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Wall clock time spent in each phase of a run, for `--profile`. When it's disabled every
+/// checkpoint is just a branch, so the normal path doesn't even read the clock.
+#[derive(Debug)]
+pub struct Profile {
+    /// when the current phase started, None if profiling is off
+    last: Option<Instant>,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Profile {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            last: enabled.then(Instant::now),
+            phases: Vec::new(),
+        }
+    }
+
+    /// End the current phase, calling it `name`, and start the next one.
+    pub fn phase(&mut self, name: &'static str) {
+        if let Some(last) = &mut self.last {
+            let now = Instant::now();
+            self.phases.push((name, now - *last));
+            *last = now;
+        }
+    }
+
+    /// Start the next phase without counting the time since the last one, for work in between
+    /// that isn't worth reporting.
+    pub fn skip(&mut self) {
+        if let Some(last) = &mut self.last {
+            *last = Instant::now();
+        }
+    }
+
+    /// Write one line per phase, in milliseconds, and their total. Writes nothing if profiling
+    /// is off.
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.last.is_none() {
+            return Ok(());
+        }
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        for &(name, time) in &self.phases {
+            writeln!(out, "profile: {name}: {:.3}ms", ms(time))?;
+        }
+        let total: Duration = self.phases.iter().map(|&(_, time)| time).sum();
+        writeln!(out, "profile: total: {:.3}ms", ms(total))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_profile() {
+    let mut out = Vec::new();
+    let mut profile = Profile::new(false);
+    profile.phase("open");
+    profile.write(&mut out).unwrap();
+    assert!(out.is_empty());
+
+    let mut profile = Profile::new(true);
+    profile.phase("open");
+    profile.skip();
+    profile.phase("output");
+    profile.write(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let names: Vec<&str> = out
+        .lines()
+        .map(|line| line.split(':').nth(1).unwrap().trim())
+        .collect();
+    assert_eq!(names, ["open", "output", "total"]);
+}
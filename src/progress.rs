@@ -0,0 +1,198 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bstr::{BStr, BString};
+
+use crate::{summarize, FinalStats, ResultsMap};
+
+/// How often to redraw the progress line
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+/// How often to redraw the live view of partial results
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How many of the hottest and coldest cities to show in the live view
+const WATCH_CITIES: usize = 3;
+
+/// Shared tally of how much of the input has been processed. Workers bump it after finishing
+/// each chunk, so it's nowhere near the hot path.
+#[derive(Debug)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: usize,
+    /// all the results from finished chunks so far, if a live view was requested
+    snapshot: Option<Mutex<ResultsMap>>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Self {
+            done: AtomicUsize::new(0),
+            total,
+            snapshot: None,
+        }
+    }
+
+    /// Like `new`, but also keep a running copy of the partial results for `watch_until`. That
+    /// costs a copy of each chunk's results, so only use it when someone's watching.
+    pub fn with_snapshot(total: usize) -> Self {
+        Self {
+            snapshot: Some(Mutex::default()),
+            ..Self::new(total)
+        }
+    }
+
+    /// Mark a finished chunk of `bytes` input as processed, and add its results to the snapshot
+    /// if there is one.
+    pub(crate) fn add_chunk(&self, bytes: usize, results: &ResultsMap<&BStr>) {
+        if let Some(snapshot) = &self.snapshot {
+            let copy = ResultsMap {
+                map: results
+                    .map
+                    .iter()
+                    .map(|(city, stats)| (BString::from(*city), stats.clone()))
+                    .collect(),
+                config: results.config,
+                // the snapshot only shows per-city stats
+                global: None,
+            };
+            snapshot.lock().unwrap().merge(copy);
+        }
+        self.add(bytes);
+    }
+
+    /// mark another `bytes` of input as processed
+    pub fn add(&self, bytes: usize) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// how many bytes have been processed so far
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Draw a progress line on stderr every so often until `stop` is signaled or disconnected.
+    pub fn render_until(&self, stop: Receiver<()>) {
+        loop {
+            let finished = !matches!(
+                stop.recv_timeout(UPDATE_INTERVAL),
+                Err(RecvTimeoutError::Timeout)
+            );
+            // only locked while drawing, so that anything else writing to stderr meanwhile (like
+            // `--repeat`'s timings) isn't blocked until the end
+            let mut stderr = std::io::stderr().lock();
+
+            const MIB: f64 = 1048576.0;
+            let done = self.done();
+            let percent = if self.total == 0 {
+                100.0
+            } else {
+                done as f64 * 100.0 / self.total as f64
+            };
+            // errors writing to stderr aren't worth caring about
+            let _ = write!(
+                stderr,
+                "\rprocessed {:.1}/{:.1} MiB ({percent:.0}%)",
+                done as f64 / MIB,
+                self.total as f64 / MIB
+            );
+            let _ = stderr.flush();
+
+            if finished {
+                let _ = writeln!(stderr);
+                return;
+            }
+        }
+    }
+
+    /// Like `render_until`, but also show the hottest and coldest cities so far (by mean) on the
+    /// progress line. Only redrawn once a second, since it has to finalize everything in the
+    /// snapshot each time. Without a snapshot, just the progress is shown.
+    pub fn watch_until(&self, stop: Receiver<()>) {
+        loop {
+            let finished = !matches!(
+                stop.recv_timeout(WATCH_INTERVAL),
+                Err(RecvTimeoutError::Timeout)
+            );
+            // only locked while drawing, so that anything else writing to stderr meanwhile (like
+            // `--repeat`'s timings) isn't blocked until the end
+            let mut stderr = std::io::stderr().lock();
+
+            let percent = if self.total == 0 {
+                100.0
+            } else {
+                self.done() as f64 * 100.0 / self.total as f64
+            };
+            let cities = match &self.snapshot {
+                // copy it first so that workers aren't held up while it's finalized
+                Some(snapshot) => {
+                    let partial = snapshot.lock().unwrap().clone();
+                    render_snapshot(partial, WATCH_CITIES)
+                }
+                None => String::new(),
+            };
+            // clear to the end of the line, in case this one is shorter than the last
+            let _ = write!(stderr, "\r[{percent:3.0}%] {cities}\x1b[K");
+            let _ = stderr.flush();
+
+            if finished {
+                let _ = writeln!(stderr);
+                return;
+            }
+        }
+    }
+}
+
+/// Format the `n` hottest and coldest cities by mean in `results`, e.g.
+/// `hottest: A 30.1, B 29.8 | coldest: C -3.2, D 1.0`.
+fn render_snapshot(results: ResultsMap, n: usize) -> String {
+    let mut summary = summarize(results);
+    // stable sort, so ties stay in city name order
+    summary.sort_by_key(|(_, stats)| stats.mean());
+
+    let list = |cities: &mut dyn Iterator<Item = &(BString, FinalStats)>| {
+        cities
+            .map(|(city, stats)| format!("{city} {}", stats.mean()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "hottest: {} | coldest: {}",
+        list(&mut summary.iter().rev().take(n)),
+        list(&mut summary.iter().take(n))
+    )
+}
+
+#[cfg(test)]
+#[test]
+fn test_render_snapshot() {
+    use crate::Temperature;
+
+    let rows = [
+        ("Paris", 123),
+        ("Oslo", -30),
+        ("Abha", 300),
+        ("Oslo", -10),
+        ("Dakar", 250),
+        ("Nuuk", -80),
+        ("Lima", 250),
+    ];
+    let results = ResultsMap::from_rows(
+        rows.iter()
+            .map(|&(city, tenths)| (city.as_bytes(), Temperature::from_tenths(tenths))),
+    );
+    assert_eq!(
+        render_snapshot(results.clone(), 2),
+        "hottest: Abha 30.0, Lima 25.0 | coldest: Nuuk -8.0, Oslo -2.0"
+    );
+    assert_eq!(
+        render_snapshot(results, 10),
+        "hottest: Abha 30.0, Lima 25.0, Dakar 25.0, Paris 12.3, Oslo -2.0, Nuuk -8.0 | \
+         coldest: Nuuk -8.0, Oslo -2.0, Paris 12.3, Dakar 25.0, Lima 25.0, Abha 30.0"
+    );
+    assert_eq!(
+        render_snapshot(ResultsMap::default(), 2),
+        "hottest:  | coldest: "
+    );
+}
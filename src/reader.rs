@@ -0,0 +1,125 @@
+use std::io::{self, Read};
+
+use bstr::ByteSlice;
+
+/// Splits up a stream into chunks of whole lines, for input that can't be mmapped (like stdin).
+/// Each chunk is about `chunk_size` bytes and ends just after a `sep` (usually a newline), except
+/// maybe the last one. The partial line at the end of each read is carried over to the start of
+/// the next chunk.
+#[derive(Debug)]
+pub struct ChunkReader<R> {
+    reader: R,
+    chunk_size: usize,
+    /// byte at the end of each line
+    sep: u8,
+    /// Longest line allowed, so that input without any separators can't make the carry-over
+    /// grow until memory runs out
+    max_line: usize,
+    carry: Vec<u8>,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R, chunk_size: usize, sep: u8, max_line: usize) -> Self {
+        Self {
+            reader,
+            chunk_size: chunk_size.max(1),
+            sep,
+            max_line,
+            carry: Vec::new(),
+        }
+    }
+
+    /// Read the next chunk, or None at the end of the input. A line longer than `chunk_size`
+    /// makes for a bigger chunk rather than being split, up to `max_line` bytes, and any longer
+    /// is an InvalidData error. Only lines split across reads are checked, since those are the
+    /// ones which get carried over and grow, so long lines that fit in one read are allowed.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = std::mem::take(&mut self.carry);
+        loop {
+            let start = buf.len();
+            buf.resize(start + self.chunk_size, 0);
+            let len = read_full(&mut self.reader, &mut buf[start..])?;
+            buf.truncate(start + len);
+
+            if len == 0 {
+                return Ok((!buf.is_empty()).then_some(buf));
+            }
+            // only the new bytes can have a separator, the carry-over is a partial line
+            let first_sep = buf[start..].find_byte(self.sep);
+            let spans_reads = start > 0 || first_sep.is_none();
+            if spans_reads && first_sep.map_or(buf.len(), |pos| start + pos) > self.max_line {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line longer than {} bytes", self.max_line),
+                ));
+            }
+            if let Some(pos) = buf[start..].rfind_byte(self.sep) {
+                self.carry = buf[start + pos + 1..].to_vec();
+                buf.truncate(start + pos + 1);
+                return Ok(Some(buf));
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+/// Read until `buf` is full or the end of the input, returning how many bytes were read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_reader() {
+    let data = b"Paris;12.3\nOslo;-3.0\nAbha;30.0";
+    for chunk_size in 1..=data.len() + 1 {
+        let chunks: Vec<Vec<u8>> = ChunkReader::new(&data[..], chunk_size, b'\n', usize::MAX)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.concat(), data, "chunk size {chunk_size}");
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(
+            rest.iter().all(|c| c.ends_with(b"\n")),
+            "chunk size {chunk_size}"
+        );
+        assert!(!last.is_empty());
+    }
+
+    assert!(ChunkReader::new(&b""[..], 10, b'\n', usize::MAX)
+        .next()
+        .is_none());
+
+    // lines up to the limit are fine, and any longer is an error rather than growing forever
+    for chunk_size in [1, 3, 9] {
+        let chunks = ChunkReader::new(&data[..], chunk_size, b'\n', 10);
+        assert_eq!(
+            chunks.collect::<io::Result<Vec<_>>>().unwrap().concat(),
+            data
+        );
+        let mut chunks = ChunkReader::new(&data[..], chunk_size, b'\n', 9);
+        let err = chunks.find_map(Result::err).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line longer than 9 bytes");
+    }
+    let chunks = ChunkReader::new(&data[..], 100, b'\n', 9);
+    assert_eq!(
+        chunks.collect::<io::Result<Vec<_>>>().unwrap().concat(),
+        data
+    );
+}
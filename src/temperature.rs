@@ -1,5 +1,7 @@
-use std::fmt;
-use std::ops;
+// Only core is used here (no std or alloc) so that this module can be reused in no_std builds,
+// see the no_std_check crate.
+use core::fmt;
+use core::ops;
 
 /// A single temperature, with tenths of a degree precision
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -15,29 +17,158 @@ impl fmt::Debug for Temperature {
 
 impl fmt::Display for Temperature {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // All the math is on integers, so there's no such thing as negative zero, and zero
+        // always displays as "0.0". unsigned_abs because abs() overflows on i32::MIN.
         let sign = if self.tenths.is_negative() { "-" } else { "" };
-        let whole = self.tenths.abs() / 10;
-        let frac = self.tenths.abs() % 10;
+        let whole = self.tenths.unsigned_abs() / 10;
+        let frac = self.tenths.unsigned_abs() % 10;
         write!(f, "{sign}{whole}.{frac}")
     }
 }
 
+/// Compact display for a [`Temperature`], which leaves off the fractional part when it's zero,
+/// e.g. `12` rather than `12.0`. Made by [`Temperature::compact`].
+#[derive(Debug, Clone, Copy)]
+pub struct CompactDisplay(Temperature);
+
+impl fmt::Display for CompactDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.tenths % 10 == 0 {
+            write!(f, "{}", self.0.tenths / 10)
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
 impl Temperature {
+    /// The coldest possible temperature
+    pub const MIN: Self = Self::from_tenths(i32::MIN);
+    /// The hottest possible temperature
+    pub const MAX: Self = Self::from_tenths(i32::MAX);
+
+    /// Make a Temperature from a raw number of tenths of a degree
+    ///
+    /// ```
+    /// use onebrc::Temperature;
+    ///
+    /// let t = Temperature::from_tenths(123);
+    /// assert_eq!(t.to_string(), "12.3");
+    /// assert_eq!(t.to_tenths(), 123);
+    /// assert_eq!(Temperature::from_tenths(-5).to_string(), "-0.5");
+    /// ```
+    pub const fn from_tenths(tenths: i32) -> Self {
+        Self { tenths }
+    }
+
+    /// Get the raw number of tenths of a degree
+    pub const fn to_tenths(self) -> i32 {
+        self.tenths
+    }
+
+    /// Get the value in degrees as a float, for doing further math
+    ///
+    /// ```
+    /// use onebrc::Temperature;
+    ///
+    /// assert_eq!(Temperature::from_tenths(-14).as_celsius_f64(), -1.4);
+    /// ```
+    pub fn as_celsius_f64(self) -> f64 {
+        self.tenths as f64 / 10.0
+    }
+
+    /// Display without the fractional part when it's zero. The plain `Display` always has one
+    /// decimal place, which the challenge output format requires.
+    ///
+    /// ```
+    /// use onebrc::Temperature;
+    ///
+    /// assert_eq!(Temperature::from_tenths(120).compact().to_string(), "12");
+    /// assert_eq!(Temperature::from_tenths(123).compact().to_string(), "12.3");
+    /// ```
+    pub const fn compact(self) -> CompactDisplay {
+        CompactDisplay(self)
+    }
+
+    /// Add two temperatures, or None if the result would overflow
+    pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.tenths.checked_add(rhs.tenths) {
+            Some(tenths) => Some(Self { tenths }),
+            None => None,
+        }
+    }
+
+    /// The mean of `count` temperatures adding up to `total_tenths`, rounded to the nearest tenth
+    /// (half away from zero, like `f64::round`, but with integer math because that's not
+    /// available in core). Means outside the range of an i32 are clamped to ±`i32::MAX`.
+    ///
+    /// This takes 128-bit totals so that it works with any accumulator width. `count` must not
+    /// be zero.
+    pub fn mean(total_tenths: i128, count: u64) -> Self {
+        let num = total_tenths.unsigned_abs();
+        let den = count as u128;
+        let mut quotient = num / den;
+        if (num % den) >= den - (num % den) {
+            quotient += 1;
+        }
+        let tenths = quotient.min(i32::MAX as u128) as i32;
+        Self {
+            tenths: if total_tenths < 0 { -tenths } else { tenths },
+        }
+    }
+
+    /// `mean`, but rounding halves up towards +∞ rather than away from zero, like Java's
+    /// `Math.round` (add 0.5 and take the floor) which the official 1BRC uses. That only makes a
+    /// difference for negative means exactly halfway between two tenths, like -1.25 which is
+    /// -1.2 rather than -1.3.
+    pub fn mean_half_up(total_tenths: i128, count: u64) -> Self {
+        let count = count as i128;
+        let mut quotient = total_tenths.div_euclid(count);
+        let remainder = total_tenths.rem_euclid(count);
+        if remainder >= count - remainder {
+            quotient += 1;
+        }
+        Self {
+            tenths: quotient.clamp(-i32::MAX as i128, i32::MAX as i128) as i32,
+        }
+    }
+
     /// Parse an ASCII string and assume that it's already valid. SPICY HOT!
     ///
     /// Skip all logical strictness in the name of speed (without losing memory safety). The input
     /// is guaranteed to contain exactly one digit after the decimal point, so we don't even have
     /// to treat the '.' and digit after it separately, it's just another LSD worth of tenths.
+    ///
+    /// That means a number with no decimal point is read as a number of tenths, e.g. `12` comes
+    /// out as 1.2 degrees, not 12.0. It also means that any decimal separator works, `12,3` is
+    /// 12.3 just like `12.3` is. Use `parse_strict` to reject that, or `parse_allow_integer`
+    /// to treat it as whole degrees.
+    ///
+    /// Garbage input with way too many digits saturates at `i32::MAX` tenths (or `-i32::MAX` if
+    /// negative) rather than overflowing. Saturating math costs next to nothing here compared to
+    /// plain `*` and `+`.
     pub fn parse(s: impl AsRef<[u8]>) -> Self {
+        #[cfg(feature = "lut-parse")]
+        return Self::parse_lut(s.as_ref());
+        #[cfg(not(feature = "lut-parse"))]
+        Self::parse_match(s.as_ref())
+    }
+
+    /// `parse` with a `match` on every byte, which is the default
+    #[cfg(any(test, not(feature = "lut-parse")))]
+    #[inline(always)]
+    fn parse_match(s: &[u8]) -> Self {
         let mut tenths = 0i32;
         let mut negative = false;
 
-        for b in s.as_ref().iter() {
+        for b in s.iter() {
             match *b {
                 // negative sign, remember for later
                 b'-' => negative = true,
                 // A digit, shift in a new LSD
-                d @ b'0'..=b'9' => tenths = (tenths * 10) + ((d - b'0') as i32),
+                d @ b'0'..=b'9' => {
+                    tenths = tenths.saturating_mul(10).saturating_add((d - b'0') as i32)
+                }
                 // skip everything else
                 _ => (),
             }
@@ -48,11 +179,95 @@ impl Temperature {
         }
     }
 
-    /// Parse an ASCII string of the form `-?[0-9]+\.[0-9]`.
+    /// `parse` with the `lut-parse` feature: look up every byte's digit value in a table, and
+    /// check for a '-' in a separate pass, so that the loop has no match. Non-digits shift in
+    /// nothing by multiplying by 1 and adding 0.
     ///
-    /// Not used in the actual code but kept around for testing and safe keeping.
-    #[cfg(test)]
+    /// It's slower though: about 20% on 20M rows (1.16s vs 0.97s median). The match is only a
+    /// couple of compares, and its branches are very predictable with numbers this short, while
+    /// this adds a load for every byte, a multiply even for the '.', and a second pass to find
+    /// the sign. So it isn't the default, and is only kept to make it easy to measure again.
+    #[cfg(any(test, feature = "lut-parse"))]
+    #[inline(always)]
+    fn parse_lut(s: &[u8]) -> Self {
+        /// each byte's digit value, or `NOT_DIGIT`
+        const DIGITS: [u8; 256] = {
+            let mut table = [NOT_DIGIT; 256];
+            let mut d = 0;
+            while d < 10 {
+                table[(b'0' + d) as usize] = d;
+                d += 1;
+            }
+            table
+        };
+        const NOT_DIGIT: u8 = u8::MAX;
+
+        let mut tenths = 0i32;
+        for &b in s {
+            let d = DIGITS[b as usize];
+            let (mul, add) = if d == NOT_DIGIT {
+                (1, 0)
+            } else {
+                (10, d as i32)
+            };
+            tenths = tenths.saturating_mul(mul).saturating_add(add);
+        }
+        Self {
+            tenths: if s.contains(&b'-') { -tenths } else { tenths },
+        }
+    }
+
+    /// Like `parse`, except that a number with no decimal point is whole degrees rather than
+    /// tenths, so that `12` is 12.0. The extra bookkeeping makes it a bit slower than `parse`.
+    pub fn parse_allow_integer(s: impl AsRef<[u8]>) -> Self {
+        Self::parse_allow_integer_decimal(s, b'.')
+    }
+
+    /// `parse_allow_integer` with `decimal` as the decimal separator instead of '.'
+    pub fn parse_allow_integer_decimal(s: impl AsRef<[u8]>, decimal: u8) -> Self {
+        let mut tenths = 0i32;
+        let mut negative = false;
+        let mut has_point = false;
+
+        for b in s.as_ref().iter() {
+            match *b {
+                b'-' => negative = true,
+                b if b == decimal => has_point = true,
+                d @ b'0'..=b'9' => {
+                    tenths = tenths.saturating_mul(10).saturating_add((d - b'0') as i32)
+                }
+                _ => (),
+            }
+        }
+
+        if !has_point {
+            tenths = tenths.saturating_mul(10);
+        }
+        Self {
+            tenths: if negative { -tenths } else { tenths },
+        }
+    }
+
+    /// Parse a number of whole degrees with no decimal point, so that `12` is 12.0. Like `parse`
+    /// there's no validation, anything other than digits and '-' is skipped, so `12.5` would be
+    /// 125 degrees.
+    pub fn parse_int_degrees(s: impl AsRef<[u8]>) -> Self {
+        Self {
+            tenths: Self::parse(s).tenths.saturating_mul(10),
+        }
+    }
+
+    /// Parse an ASCII string of the form `-?[0-9]+\.[0-9]`. Values too big to fit in an i32 worth
+    /// of tenths are an error, as is a number with no decimal point.
+    ///
+    /// Used for `--strict` mode, where we care more about validating input than speed.
     pub fn parse_strict(s: impl AsRef<[u8]>) -> Result<Self, &'static str> {
+        Self::parse_strict_decimal(s, b'.')
+    }
+
+    /// `parse_strict` with `decimal` as the decimal separator instead of '.'. Only that separator
+    /// is accepted, so with a ',' separator `12.3` is an invalid character.
+    pub fn parse_strict_decimal(s: impl AsRef<[u8]>, decimal: u8) -> Result<Self, &'static str> {
         #[derive(Clone, Copy, PartialEq)]
         enum State {
             Sign,
@@ -61,6 +276,13 @@ impl Temperature {
             Done,
         }
 
+        fn shift_digit(tenths: i32, d: u8) -> Result<i32, &'static str> {
+            tenths
+                .checked_mul(10)
+                .and_then(|t| t.checked_add((d - b'0') as i32))
+                .ok_or("number too large")
+        }
+
         let s = s.as_ref();
         let mut negative = false;
         let mut state = State::Sign;
@@ -78,12 +300,12 @@ impl Temperature {
                 }
                 (State::Sign, _) => return Err("invalid character"),
 
-                (State::Digit, d @ b'0'..=b'9') => tenths = (tenths * 10) + (d - b'0') as i32,
-                (State::Digit, b'.') => state = State::Frac,
+                (State::Digit, d @ b'0'..=b'9') => tenths = shift_digit(tenths, d)?,
+                (State::Digit, b) if b == decimal => state = State::Frac,
                 (State::Digit, _) => return Err("invalid character"),
 
                 (State::Frac, d @ b'0'..=b'9') => {
-                    tenths = (tenths * 10) + (d - b'0') as i32;
+                    tenths = shift_digit(tenths, d)?;
                     state = State::Done;
                 }
                 (State::Frac, _) => return Err("invalid character"),
@@ -92,6 +314,9 @@ impl Temperature {
             }
         }
 
+        if state == State::Digit && s.last().is_some_and(u8::is_ascii_digit) {
+            return Err("missing decimal point");
+        }
         if state != State::Done {
             return Err("truncated input");
         }
@@ -102,6 +327,34 @@ impl Temperature {
 
         Ok(Self { tenths })
     }
+
+    /// Strict version of `parse_int_degrees`, for an ASCII string of the form `-?[0-9]+`. A
+    /// decimal point is an invalid character.
+    pub fn parse_strict_int_degrees(s: impl AsRef<[u8]>) -> Result<Self, &'static str> {
+        let s = s.as_ref();
+        let (negative, digits) = match s.strip_prefix(b"-") {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() {
+            return Err("truncated input");
+        }
+
+        let mut degrees = 0_i32;
+        for d in digits.iter().copied() {
+            if !d.is_ascii_digit() {
+                return Err("invalid character");
+            }
+            degrees = degrees
+                .checked_mul(10)
+                .and_then(|t| t.checked_add((d - b'0') as i32))
+                .ok_or("number too large")?;
+        }
+        let tenths = degrees.checked_mul(10).ok_or("number too large")?;
+        Ok(Self {
+            tenths: if negative { -tenths } else { tenths },
+        })
+    }
 }
 
 // hand-rolled ops implementations. Just the ones I actually use, not trying to be fully complete
@@ -116,9 +369,7 @@ impl ops::Div<u32> for Temperature {
     type Output = Temperature;
 
     fn div(self, rhs: u32) -> Self::Output {
-        Temperature {
-            tenths: ((self.tenths as f64) / (rhs as f64)).round() as i32,
-        }
+        Temperature::mean(self.tenths as i128, rhs as u64)
     }
 }
 
@@ -140,15 +391,62 @@ fn test_temperature() {
     assert!(Temperature::parse_strict("").is_err());
     assert!(Temperature::parse_strict("12345.6 ").is_err());
     assert!(Temperature::parse_strict("foo0.1").is_err());
-    assert!(Temperature::parse_strict("-123").is_err());
+    assert_eq!(
+        Temperature::parse_strict("-123"),
+        Err("missing decimal point")
+    );
+    assert_eq!(Temperature::parse_strict("-"), Err("truncated input"));
+    assert_eq!(Temperature::parse_strict("12."), Err("truncated input"));
+    assert_eq!(
+        Temperature::parse_strict("1234567890123456789.0"),
+        Err("number too large")
+    );
 
-    // values should round-trip through parse and display, and ensures that the modular arithmetic
-    // in Display is correct.
-    let nums = ["0.0", "1.0", "123.5", "-1.0", "-1.4", "-0.2", "-100.3"];
-    for s in nums {
-        let t = Temperature::parse_strict(s).unwrap();
-        assert_eq!(s, t.to_string().as_str());
+    // Values should round-trip through both parsers and display, which ensures that the modular
+    // arithmetic in Display is correct. These are the boundaries: zero and the smallest nonzero
+    // values either side of it, where the whole part is 0 and only the sign says it's negative,
+    // the edges of the official -99.9 to 99.9 range, where the whole part gains a digit, values
+    // well outside it that the fast parser still takes, and the largest that fit in an i32.
+    let nums = [
+        ("0.0", 0),
+        ("0.1", 1),
+        ("-0.1", -1),
+        ("0.9", 9),
+        ("-0.9", -9),
+        ("-0.2", -2),
+        ("1.0", 10),
+        ("-1.0", -10),
+        ("-1.4", -14),
+        ("9.9", 99),
+        ("-9.9", -99),
+        ("10.0", 100),
+        ("-10.0", -100),
+        ("10.1", 101),
+        ("-10.1", -101),
+        ("99.9", 999),
+        ("-99.9", -999),
+        ("100.0", 1000),
+        ("-100.0", -1000),
+        ("-100.3", -1003),
+        ("123.5", 1235),
+        ("999.9", 9999),
+        ("-999.9", -9999),
+        ("214748364.7", i32::MAX),
+        ("-214748364.7", -i32::MAX),
+    ];
+    for (s, tenths) in nums {
+        let expected = Temperature { tenths };
+        assert_eq!(Temperature::parse_match(s.as_bytes()), expected, "{s}");
+        assert_eq!(Temperature::parse_lut(s.as_bytes()), expected, "{s}");
+        assert_eq!(Temperature::parse_strict(s), Ok(expected), "{s}");
+        assert_eq!(expected.to_string(), s);
     }
+    // negative zero is just zero (see test_negative_zero), and one more tenth than fits
+    assert_eq!(Temperature::parse("-0.0").to_string(), "0.0");
+    assert_eq!(
+        Temperature::parse_strict("214748364.8"),
+        Err("number too large")
+    );
 
     // same tests but for the fast parser
     assert_eq!(Temperature::parse("12.3"), Temperature { tenths: 123 });
@@ -158,11 +456,202 @@ fn test_temperature() {
         Temperature { tenths: 1234567890 }
     );
 
-    // values should round-trip through parse and display, and ensures that the modular arithmetic
-    // in Display is correct.
-    let nums = ["0.0", "1.0", "123.5", "-1.0", "-1.4", "-0.2", "-100.3"];
-    for s in nums {
-        let t = Temperature::parse(s);
-        assert_eq!(s, t.to_string().as_str());
+    // bare integers are read as tenths by parse, but whole degrees by parse_allow_integer
+    assert_eq!(Temperature::parse("12"), Temperature { tenths: 12 });
+    assert_eq!(Temperature::parse("12.0"), Temperature { tenths: 120 });
+    assert_eq!(
+        Temperature::parse_allow_integer("12"),
+        Temperature { tenths: 120 }
+    );
+    assert_eq!(
+        Temperature::parse_allow_integer("-12"),
+        Temperature { tenths: -120 }
+    );
+    assert_eq!(
+        Temperature::parse_allow_integer("12.0"),
+        Temperature { tenths: 120 }
+    );
+    assert_eq!(
+        Temperature::parse_allow_integer("-1.5"),
+        Temperature { tenths: -15 }
+    );
+
+    // always whole degrees with parse_int_degrees
+    assert_eq!(
+        Temperature::parse_int_degrees("12"),
+        Temperature { tenths: 120 }
+    );
+    assert_eq!(
+        Temperature::parse_int_degrees("-7"),
+        Temperature { tenths: -70 }
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees("-12"),
+        Ok(Temperature { tenths: -120 })
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees("0"),
+        Ok(Temperature { tenths: 0 })
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees("12.0"),
+        Err("invalid character")
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees("-"),
+        Err("truncated input")
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees(""),
+        Err("truncated input")
+    );
+    assert_eq!(
+        Temperature::parse_strict_int_degrees("300000000"),
+        Err("number too large")
+    );
+
+    // decimal commas
+    assert_eq!(Temperature::parse("12,3"), Temperature { tenths: 123 });
+    assert_eq!(
+        Temperature::parse_strict_decimal("-12,3", b','),
+        Ok(Temperature { tenths: -123 })
+    );
+    assert_eq!(
+        Temperature::parse_strict_decimal("12.3", b','),
+        Err("invalid character")
+    );
+    assert_eq!(Temperature::parse_strict("12,3"), Err("invalid character"));
+    assert_eq!(
+        Temperature::parse_allow_integer_decimal("12,3", b','),
+        Temperature { tenths: 123 }
+    );
+    assert_eq!(
+        Temperature::parse_allow_integer_decimal("12", b','),
+        Temperature { tenths: 120 }
+    );
+
+    // overflow saturates rather than wrapping
+    assert_eq!(
+        Temperature::parse("1234567890123456789.0"),
+        Temperature { tenths: i32::MAX }
+    );
+    assert_eq!(
+        Temperature::parse("-1234567890123456789.0"),
+        Temperature { tenths: -i32::MAX }
+    );
+
+    // floats
+    assert_eq!(Temperature::from_tenths(-14).as_celsius_f64(), -1.4);
+    assert_eq!(Temperature::from_tenths(-999).as_celsius_f64(), -99.9);
+    assert_eq!(Temperature::from_tenths(0).as_celsius_f64(), 0.0);
+    assert_eq!(Temperature::from_tenths(123).as_celsius_f64(), 12.3);
+
+    // checked math
+    let t = Temperature::from_tenths;
+    assert_eq!(t(-14).checked_add(t(-20)), Some(t(-34)));
+    assert_eq!(t(-14).checked_add(t(20)), Some(t(6)));
+    assert_eq!(t(i32::MAX).checked_add(t(1)), None);
+    assert_eq!(t(i32::MIN).checked_add(t(-1)), None);
+    assert_eq!(t(i32::MIN).checked_add(t(i32::MAX)), Some(t(-1)));
+
+    // means of totals too big for an i32
+    assert_eq!(Temperature::mean(i64::MAX as i128, 1).tenths, i32::MAX);
+    assert_eq!(Temperature::mean(i128::MIN, u64::MAX).tenths, -i32::MAX);
+    assert_eq!(Temperature::mean(30_000_000_000, 100).tenths, 300_000_000);
+    assert_eq!(Temperature::mean(-30_000_000_005, 1000).tenths, -30_000_000);
+    assert_eq!(Temperature::mean(i128::MAX, u64::MAX).tenths, i32::MAX);
+    assert_eq!(
+        Temperature::mean(150_000_000_000_000_000_000, 10_000_000_000_000_000_000).tenths,
+        15
+    );
+
+    assert_eq!(Temperature::mean_half_up(i128::MIN, 1).tenths, -i32::MAX);
+    assert_eq!(Temperature::mean_half_up(i128::MAX, 1).tenths, i32::MAX);
+    assert_eq!(
+        Temperature::mean_half_up(i128::MIN, u64::MAX).tenths,
+        -i32::MAX
+    );
+
+    // integer division rounding should match f64::round
+    for tenths in -1000..=1000 {
+        for count in 1..=20 {
+            let expected = ((tenths as f64) / (count as f64)).round() as i32;
+            assert_eq!(
+                Temperature { tenths } / count,
+                Temperature { tenths: expected },
+                "{tenths} / {count}"
+            );
+            // and Java's Math.round
+            let expected = ((tenths as f64) / (count as f64) + 0.5).floor() as i32;
+            assert_eq!(
+                Temperature::mean_half_up(tenths as i128, count.into()),
+                Temperature { tenths: expected },
+                "{tenths} / {count}"
+            );
+        }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_parse_lut() {
+    // every string of up to 5 of these bytes, valid or not
+    let alphabet = b"-0159.,x ";
+    let mut inputs = vec![Vec::new()];
+    let mut longest = inputs.clone();
+    for _ in 0..5 {
+        longest = longest
+            .iter()
+            .flat_map(|s| alphabet.iter().map(move |&b| [&s[..], &[b]].concat()))
+            .collect();
+        inputs.extend_from_slice(&longest);
+    }
+    inputs.push(b"-1234567890123456789.0".to_vec());
+    inputs.push(b"99999999999".to_vec());
+
+    for s in &inputs {
+        let lut = Temperature::parse_lut(s);
+        assert_eq!(lut, Temperature::parse_match(s), "{s:?}");
+        if let Ok(strict) = Temperature::parse_strict(s) {
+            assert_eq!(lut, strict, "{s:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_negative_zero() {
+    let zero = Temperature { tenths: 0 };
+    assert_eq!(Temperature::parse("-0.0"), zero);
+    assert_eq!(Temperature::parse_strict("-0.0"), Ok(zero));
+    assert_eq!(Temperature::parse_allow_integer("-0"), zero);
+
+    // tiny negative means round to plain zero
+    assert_eq!(Temperature::mean(-1, 3), zero);
+    assert_eq!(Temperature::mean(-4, 10), zero);
+    assert_eq!(Temperature::mean(0, 2).to_string(), "0.0");
+    assert_eq!((Temperature { tenths: -1 } / 3).to_string(), "0.0");
+    let mut sum = Temperature::parse("-0.1");
+    sum += Temperature::parse("0.1");
+    assert_eq!((sum / 2).to_string(), "0.0");
+
+    assert_eq!(
+        Temperature::from_tenths(i32::MIN).to_string(),
+        "-214748364.8"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_compact() {
+    let compact = |s| Temperature::parse_strict(s).unwrap().compact().to_string();
+    assert_eq!(compact("12.0"), "12");
+    assert_eq!(compact("12.3"), "12.3");
+    assert_eq!(compact("-12.0"), "-12");
+    assert_eq!(compact("-12.3"), "-12.3");
+    assert_eq!(compact("-0.5"), "-0.5");
+    assert_eq!(compact("0.0"), "0");
+    // negative zero has no sign once parsed
+    assert_eq!(compact("-0.0"), "0");
+    assert_eq!(Temperature::parse("-0.0").compact().to_string(), "0");
+}
@@ -0,0 +1,132 @@
+use std::time::Instant;
+
+use tracing::span::EnteredSpan;
+use tracing::Span;
+
+use crate::ResultsMap;
+
+/// Keeps a span entered until it's dropped, and then emits an event with how long that was, in
+/// microseconds as `elapsed_us`
+pub(crate) struct Timer {
+    start: Instant,
+    _span: EnteredSpan,
+}
+
+impl Timer {
+    pub(crate) fn new(span: Span) -> Self {
+        Self {
+            _span: span.entered(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // the span is a field, so it's still entered here
+        let elapsed_us = self.start.elapsed().as_micros() as u64;
+        tracing::info!(elapsed_us, "done");
+    }
+}
+
+/// Emit an event with how many rows and unique cities are in `results`
+// these casts are no-ops with the wide-total feature
+#[cfg_attr(feature = "wide-total", allow(clippy::unnecessary_cast))]
+pub(crate) fn results<K>(results: &ResultsMap<K>) {
+    let rows: u64 = results.map.values().map(|stats| stats.count as u64).sum();
+    tracing::info!(rows, cities = results.map.len(), "results");
+}
+
+#[cfg(test)]
+#[test]
+fn test_tracing() {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::{process_buffers, summarize, Config};
+
+    /// Logs every span and event as a line like `span name field=value ...`
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!(" {}={value:?}", field.name());
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut fields = Fields(format!("span {}", span.metadata().name()));
+            span.record(&mut fields);
+            let mut log = self.0.lock().unwrap();
+            log.push(fields.0);
+            Id::from_u64(log.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = Fields("event".into());
+            event.record(&mut fields);
+            // durations vary, just check that they're there
+            let line = match fields.0.split_once(" elapsed_us=") {
+                Some((before, us)) => {
+                    assert!(us.parse::<u64>().is_ok(), "{us}");
+                    format!("{before} elapsed_us=N")
+                }
+                None => fields.0,
+            };
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    let recorder = Recorder::default();
+    let a: &[u8] = b"Paris;12.3\nOslo;-3.0\nParis;15.1\n";
+    let b: &[u8] = b"Rome;20.5\nParis;9.9\n";
+    // only this thread has the subscriber, so keep everything on it
+    let config = Config {
+        single_threaded: true,
+        ..Config::default()
+    };
+    tracing::subscriber::with_default(recorder.clone(), || {
+        summarize(process_buffers(&[a, b], config, None).unwrap());
+    });
+    let log = recorder.0.lock().unwrap();
+    assert_eq!(
+        *log,
+        [
+            "span process_data bytes=32",
+            "event message=results rows=3 cities=2",
+            "event message=done elapsed_us=N",
+            "span merge cities_a=0 cities_b=2",
+            "event message=results rows=3 cities=2",
+            "event message=done elapsed_us=N",
+            "span process_data bytes=20",
+            "event message=results rows=2 cities=2",
+            "event message=done elapsed_us=N",
+            "span merge cities_a=2 cities_b=2",
+            "event message=results rows=5 cities=3",
+            "event message=done elapsed_us=N",
+            "span finalize cities=3",
+            "event message=done elapsed_us=N",
+            "span sort cities=3",
+            "event message=done elapsed_us=N",
+        ]
+    );
+}
@@ -0,0 +1,110 @@
+use crate::Temperature;
+
+/// Temperature unit for output. Everything is parsed and aggregated in Celsius, other units are
+/// converted to at the very end.
+///
+/// Conversions round to the nearest tenth, with halves rounding away from zero (same as the mean
+/// does) unless another `Rounding` is given. For example 0.0°C is exactly 273.15K, which is
+/// output as 273.2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl std::str::FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" | "celsius" => Ok(Self::Celsius),
+            "f" | "fahrenheit" => Ok(Self::Fahrenheit),
+            "k" | "kelvin" => Ok(Self::Kelvin),
+            _ => Err(format!("invalid unit '{s}'")),
+        }
+    }
+}
+
+/// How to round a temperature that's exactly halfway between two tenths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// like -1.25 to -1.3 and 1.25 to 1.3, the same as `Temperature::mean`
+    #[default]
+    HalfAwayFromZero,
+    /// towards +∞, like -1.25 to -1.2 and 1.25 to 1.3, the same as `Temperature::mean_half_up`
+    /// and Java's `Math.round`
+    HalfUp,
+}
+
+impl Rounding {
+    /// `num / den` tenths, rounded to a whole number of tenths
+    pub(crate) fn divide(self, num: i128, den: u64) -> Temperature {
+        match self {
+            Self::HalfAwayFromZero => Temperature::mean(num, den),
+            Self::HalfUp => Temperature::mean_half_up(num, den),
+        }
+    }
+}
+
+impl Unit {
+    /// Convert a Celsius temperature to this unit. Conversion is monotonic, so min and max stay
+    /// min and max.
+    pub fn convert(self, temp: Temperature) -> Temperature {
+        self.convert_rounded(temp, Rounding::default())
+    }
+
+    /// `convert`, with halves rounded according to `rounding`
+    pub fn convert_rounded(self, temp: Temperature, rounding: Rounding) -> Temperature {
+        let (a, b, c) = self.coefficients();
+        rounding.divide(
+            i128::from(a) * i128::from(temp.to_tenths()) + i128::from(b),
+            c as u64,
+        )
+    }
+
+    /// Integer coefficients `(a, b, c)` for the conversion, such that tenths of a degree in this
+    /// unit are `(a * celsius_tenths + b) / c`.
+    pub(crate) fn coefficients(self) -> (i32, i32, i32) {
+        match self {
+            Self::Celsius => (1, 0, 1),
+            // F = C * 9/5 + 32
+            Self::Fahrenheit => (9, 1600, 5),
+            // K = C + 273.15
+            Self::Kelvin => (10, 27315, 10),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_unit() {
+    let convert = |unit: Unit, s: &str| unit.convert(Temperature::parse(s)).to_string();
+
+    assert_eq!(convert(Unit::Celsius, "-12.3"), "-12.3");
+
+    assert_eq!(convert(Unit::Fahrenheit, "0.0"), "32.0");
+    assert_eq!(convert(Unit::Fahrenheit, "100.0"), "212.0");
+    assert_eq!(convert(Unit::Fahrenheit, "-40.0"), "-40.0");
+    // 12.3C is 54.14F
+    assert_eq!(convert(Unit::Fahrenheit, "12.3"), "54.1");
+    // -17.5C is 0.5F, -17.9C is -0.22F
+    assert_eq!(convert(Unit::Fahrenheit, "-17.5"), "0.5");
+    assert_eq!(convert(Unit::Fahrenheit, "-17.9"), "-0.2");
+
+    // 273.15 is a tie, which rounds away from zero
+    assert_eq!(convert(Unit::Kelvin, "0.0"), "273.2");
+    assert_eq!(convert(Unit::Kelvin, "-273.1"), "0.1");
+    assert_eq!(convert(Unit::Kelvin, "12.3"), "285.5");
+
+    // ties only round differently below zero
+    let half_up = |unit: Unit, s: &str| {
+        unit.convert_rounded(Temperature::parse(s), Rounding::HalfUp)
+            .to_string()
+    };
+    assert_eq!(half_up(Unit::Kelvin, "0.0"), "273.2");
+    assert_eq!(half_up(Unit::Kelvin, "-273.2"), "0.0");
+    assert_eq!(convert(Unit::Kelvin, "-273.2"), "-0.1");
+    assert_eq!(half_up(Unit::Fahrenheit, "-17.9"), "-0.2");
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use onebrc::{process_buffers, strip_bom, summarize, summarize_as_seen, ResultsMap};
+
+use crate::options::Options;
+use crate::{filter_results, map_file, write_output};
+
+/// how often `run` looks for new files
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Polls a directory for new files, for `--watch-dir`.
+///
+/// A file counts as complete once its size and modification time are the same on two polls in a
+/// row, so anything writing files straight into the directory shouldn't pause halfway. It's
+/// better to write them somewhere else, or under a name starting with `.` (which are ignored),
+/// and rename them into place. Each file is only returned once, even if it changes afterwards.
+#[derive(Debug)]
+pub struct DirWatcher {
+    dir: PathBuf,
+    /// files that have already been returned
+    done: HashSet<PathBuf>,
+    /// size and modification time of the files seen on the last poll that aren't done yet
+    pending: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            done: HashSet::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Never return `path`, if it's in the directory being watched, like the output file
+    pub fn ignore(&mut self, path: &Path) {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // the same directory can be spelled lots of different ways
+        let same_dir = match (fs::canonicalize(parent), fs::canonicalize(&self.dir)) {
+            (Ok(parent), Ok(dir)) => parent == dir,
+            _ => false,
+        };
+        if let (true, Some(name)) = (same_dir, path.file_name()) {
+            self.done.insert(self.dir.join(name));
+        }
+    }
+
+    /// The files that have become complete since the last poll, sorted by name
+    pub fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut complete = Vec::new();
+        let mut pending = HashMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_name().as_encoded_bytes().starts_with(b".") {
+                continue;
+            }
+            let path = entry.path();
+            if self.done.contains(&path) {
+                continue;
+            }
+            // it could have been removed since listing the directory, which is fine
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let state = (metadata.len(), metadata.modified().ok());
+            if self.pending.get(&path) == Some(&state) {
+                complete.push(path);
+            } else {
+                pending.insert(path, state);
+            }
+        }
+        // anything that's been removed is forgotten about
+        self.pending = pending;
+        self.done.extend(complete.iter().cloned());
+        complete.sort_unstable();
+        Ok(complete)
+    }
+}
+
+/// Aggregate one file for `run`, None if it's empty
+fn process_file(path: &Path, opts: &Options) -> Result<Option<ResultsMap>, String> {
+    let Some(mmap) = map_file(path)? else {
+        return Ok(None);
+    };
+    process_buffers(&[strip_bom(&mmap)], opts.config(), None)
+        .map(Some)
+        .map_err(|err| format!("'{}': {err}", path.display()))
+}
+
+/// Process every file in `dir` as it's completed, merging them all together, and write the
+/// results so far after each one. A file that can't be processed is reported and left out, but
+/// the rest carry on. Only returns if the directory can't be read.
+pub fn run(opts: &Options, dir: &Path) -> Result<Infallible, String> {
+    let mut watcher = DirWatcher::new(dir);
+    // the output is rewritten after each file, so if it's in there it mustn't count as one
+    if let Some(output) = &opts.output {
+        watcher.ignore(output);
+    }
+    let mut merged: Option<ResultsMap> = None;
+    loop {
+        let paths = watcher
+            .poll()
+            .map_err(|err| format!("failed to read '{}': {err}", dir.display()))?;
+        for path in paths {
+            match process_file(&path, opts) {
+                Ok(Some(results)) => {
+                    merged = Some(match merged.take() {
+                        Some(merged) => merged + results,
+                        None => results,
+                    });
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    continue;
+                }
+            }
+            let mut summary = match merged.clone() {
+                Some(results) if opts.order_as_seen => summarize_as_seen(results),
+                Some(results) => summarize(results),
+                None => Vec::new(),
+            };
+            filter_results(&mut summary, opts);
+            write_output(&summary, opts).map_err(|err| match &opts.output {
+                Some(output) => format!("failed to write '{}': {err}", output.display()),
+                None => format!("failed to write output: {err}"),
+            })?;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_dir_watcher() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut watcher = DirWatcher::new(dir.path());
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+
+    let a = dir.path().join("a.txt");
+    let b = dir.path().join("b.txt");
+    fs::write(&b, "Oslo;-3.0\n").unwrap();
+    fs::write(&a, "Paris;12.3\n").unwrap();
+    fs::write(dir.path().join(".hidden.txt"), "Rome;17.0\n").unwrap();
+    fs::create_dir(dir.path().join("subdir")).unwrap();
+    // not complete until they're the same on a second poll
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+    assert_eq!(watcher.poll().unwrap(), [a.clone(), b.clone()]);
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+
+    // still growing
+    let c = dir.path().join("c.txt");
+    fs::write(&c, "Rome;17.0\n").unwrap();
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+    fs::write(&c, "Rome;17.0\nRome;18.0\n").unwrap();
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+    assert_eq!(watcher.poll().unwrap(), [c]);
+
+    // ignored files never are, however they're spelled
+    let d = dir.path().join("d.txt");
+    watcher.ignore(&dir.path().join("subdir/../d.txt"));
+    fs::write(&d, "Oslo;-3.0\n").unwrap();
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+
+    // done files aren't returned again, even if they change
+    fs::write(&a, "Paris;12.3\nParis;14.1\n").unwrap();
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+    assert_eq!(watcher.poll().unwrap(), Vec::<PathBuf>::new());
+}
@@ -0,0 +1,44 @@
+//! `--bench-log` appends a CSV row for every timed run, so that repeated invocations build up a
+//! dataset of timings.
+
+use std::process::Command;
+
+#[test]
+fn test_bench_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("results.csv");
+    let input = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/measurements-3.txt");
+    let input_bytes = std::fs::metadata(input).unwrap().len();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--bench-log")
+            .arg(&log)
+            .args(args)
+            .arg(input)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}");
+    };
+    run(&[]);
+    run(&["--single-threaded", "--strategy", "chunked"]);
+    run(&["--quiet", "--repeat", "3"]);
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(
+        lines.next(),
+        Some("timestamp,version,input_bytes,threads,strategy,elapsed_ms")
+    );
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 5, "{log}");
+    for row in &rows {
+        assert_eq!(row.len(), 6, "{row:?}");
+        assert!(row[0].parse::<u64>().unwrap() > 0);
+        assert_eq!(row[2].parse::<u64>().unwrap(), input_bytes);
+        assert!(row[3].parse::<usize>().unwrap() >= 1);
+        assert!(row[5].parse::<f64>().unwrap() > 0.0, "{row:?}");
+    }
+    assert_eq!(rows[0][4], "lines");
+    assert_eq!((rows[1][3], rows[1][4]), ("1", "chunked"));
+}
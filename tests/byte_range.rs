@@ -0,0 +1,43 @@
+//! `--offset` and `--bytes` aggregate just the lines starting within a byte range of the input,
+//! for benchmarking on controlled input sizes without making separate files.
+
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.txt"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(args)
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{args:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_byte_range() {
+    let len = std::fs::metadata(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.txt"
+    ))
+    .unwrap()
+    .len()
+    .to_string();
+    let full = run(&[]);
+    assert_eq!(run(&["--bytes", &len]), full);
+    assert_eq!(run(&["--bytes", "1000000"]), full);
+    assert_eq!(run(&["--offset", "0"]), full);
+    assert_eq!(run(&["--quiet", "--repeat", "2", "--bytes", &len]), full);
+
+    // the first lines are "Honolulu;19.6\n", "Vostok;-45.3\n", and "Abha;23.7\n", starting at 0,
+    // 14, and 27
+    assert_eq!(run(&["--bytes", "15"]), run(&["--limit", "2"]));
+    assert_eq!(
+        run(&["--offset", "1", "--bytes", "27"]),
+        "{Abha=23.7/23.7/23.7, Vostok=-45.3/-45.3/-45.3}\n"
+    );
+    assert_eq!(run(&["--offset", &len]), "{}\n");
+}
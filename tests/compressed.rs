@@ -0,0 +1,41 @@
+//! Compressed input is recognized by its magic bytes whatever the file is called, and fails with
+//! a clear error rather than being aggregated as garbage.
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_compressed() {
+    for name in ["gzip", "zstd", "xz"] {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "testdata",
+            &format!("compressed-{name}.txt"),
+        ]
+        .iter()
+        .collect();
+
+        let run = |arg: &str, stdin: Stdio| {
+            let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+                .arg(arg)
+                .stdin(stdin)
+                .output()
+                .unwrap();
+            assert_eq!(output.status.code(), Some(1), "{name}");
+            assert!(output.stdout.is_empty(), "{name}");
+            String::from_utf8(output.stderr).unwrap()
+        };
+
+        let stderr = run(path.to_str().unwrap(), Stdio::null());
+        assert!(
+            stderr.contains(&format!("is {name} compressed, which isn't supported")),
+            "{stderr}"
+        );
+        let stderr = run("-", File::open(&path).unwrap().into());
+        assert_eq!(
+            stderr,
+            format!("error: input is {name} compressed, which isn't supported\n")
+        );
+    }
+}
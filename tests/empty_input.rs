@@ -0,0 +1,38 @@
+//! An empty input file prints `{}` and succeeds, whichever way the input gets read.
+
+use std::fs::File;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_empty_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.txt");
+    File::create(&path).unwrap();
+    let path = path.to_str().unwrap();
+
+    let run = |args: &[&str], stdin: Stdio| {
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(args)
+            .stdin(stdin)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{args:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "{}\n",
+            "{args:?}"
+        );
+    };
+
+    for args in [
+        &[path][..],
+        &["--strategy", "lines", path],
+        &["--strategy", "chunked", path],
+        &["--single-threaded", "--header", path],
+    ] {
+        run(args, Stdio::null());
+    }
+    #[cfg(feature = "glob")]
+    run(&["--glob", path], Stdio::null());
+    run(&["-"], File::open(path).unwrap().into());
+}
@@ -0,0 +1,63 @@
+//! Defaults can be set with `ONEBRC_*` environment variables, and command line options take
+//! precedence over them.
+
+use std::process::Command;
+
+#[test]
+fn test_env_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("measurements.txt");
+    std::fs::write(&path, "Paris|12.3\nOslo|-3.0\nParis|14.1\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    let run = |env: &[(&str, &str)], args: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_onebrc"));
+        for name in ["ONEBRC_THREADS", "ONEBRC_FORMAT", "ONEBRC_DELIM"] {
+            command.env_remove(name);
+        }
+        let output = command
+            .envs(env.iter().copied())
+            .args(args)
+            .arg(path)
+            .output()
+            .unwrap();
+        (
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+    let brace = "{Oslo=-3.0/-3.0/-3.0, Paris=12.3/13.2/14.1}\n";
+    let csv = "city,min,mean,max\nOslo,-3.0,-3.0,-3.0\nParis,12.3,13.2,14.1\n";
+
+    // the environment takes effect without an option
+    let delim = [("ONEBRC_DELIM", "|")];
+    assert_eq!(run(&delim, &[]), (Some(0), brace.into()));
+    let format = [("ONEBRC_DELIM", "|"), ("ONEBRC_FORMAT", "csv")];
+    assert_eq!(run(&format, &[]), (Some(0), csv.into()));
+    let threads = [("ONEBRC_DELIM", "|"), ("ONEBRC_THREADS", "2")];
+    assert_eq!(run(&threads, &[]), (Some(0), brace.into()));
+
+    // and options override it
+    assert_eq!(
+        run(&format, &["--format", "brace"]),
+        (Some(0), brace.into())
+    );
+    let semicolon = [("ONEBRC_DELIM", ";")];
+    assert_eq!(
+        run(&semicolon, &["--delimiter", "|"]),
+        (Some(0), brace.into())
+    );
+    assert_eq!(run(&[], &["--delimiter", "|"]), (Some(0), brace.into()));
+
+    // empty is the same as unset
+    let empty = [("ONEBRC_DELIM", "|"), ("ONEBRC_FORMAT", "")];
+    assert_eq!(run(&empty, &[]), (Some(0), brace.into()));
+
+    for bad in [
+        ("ONEBRC_THREADS", "0"),
+        ("ONEBRC_FORMAT", "xml"),
+        ("ONEBRC_DELIM", "||"),
+    ] {
+        assert_eq!(run(&[bad], &[]).0, Some(2), "{bad:?}");
+    }
+}
@@ -0,0 +1,43 @@
+//! A stream on stdin without any newlines fails cleanly once a line gets too long, rather than
+//! being buffered until memory runs out.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run(args: &[&str], input: Vec<u8>) -> (Option<i32>, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // it may well stop reading and exit before all of this has been written
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_long_line() {
+    let (code, stderr) = run(&[], vec![b'x'; 3 << 20]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stderr,
+        "error: failed to read input: line longer than 1048576 bytes\n"
+    );
+
+    let (code, stderr) = run(
+        &["--max-line-bytes", "10"],
+        b"Paris;12.3\nOslo;-3.0\n".to_vec(),
+    );
+    assert_eq!((code, stderr.as_str()), (Some(0), ""));
+}
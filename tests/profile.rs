@@ -0,0 +1,57 @@
+//! `--profile` prints how long each phase took to stderr, without changing the output.
+
+use std::process::Command;
+
+#[test]
+fn test_profile() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.txt"
+    );
+    let expected = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.out"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--profile", input])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string(expected).unwrap()
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let phases: Vec<(&str, f64)> = stderr
+        .lines()
+        .map(|line| {
+            let (name, time) = line
+                .strip_prefix("profile: ")
+                .and_then(|line| line.split_once(": "))
+                .unwrap_or_else(|| panic!("unexpected line {line:?}"));
+            let ms: f64 = time.strip_suffix("ms").unwrap().parse().unwrap();
+            assert!(ms >= 0.0, "{line}");
+            (name, ms)
+        })
+        .collect();
+    let names: Vec<&str> = phases.iter().map(|&(name, _)| name).collect();
+    assert_eq!(
+        names,
+        ["open", "aggregate", "finalize", "sort", "output", "total"]
+    );
+}
+
+#[test]
+fn test_no_profile() {
+    let input = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.txt"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
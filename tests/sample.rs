@@ -0,0 +1,82 @@
+//! End to end check of a small sample in the official input format against its known-good
+//! output, through both the library and the binary. The fixture has a few dozen cities with
+//! mixed signs, repeated and single-observation cities, and a mean exactly halfway between two
+//! tenths. It runs with whichever features the tests are built with, so `cargo test` and
+//! `cargo test --no-default-features` cover both the rayon and single-threaded builds.
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Command;
+
+use onebrc::{process_data, summarize, Config, Strategy};
+
+fn testdata(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "testdata", name]
+        .iter()
+        .collect()
+}
+
+fn expected() -> String {
+    std::fs::read_to_string(testdata("measurements-sample.out")).unwrap()
+}
+
+#[test]
+fn test_sample_library() {
+    let data = std::fs::read(testdata("measurements-sample.txt")).unwrap();
+    for config in [
+        Config::default(),
+        Config {
+            strategy: Strategy::Chunked,
+            ..Config::default()
+        },
+        Config {
+            single_threaded: true,
+            ..Config::default()
+        },
+        Config {
+            strict: true,
+            ..Config::default()
+        },
+    ] {
+        let results = process_data(&data, config, None).unwrap();
+        let cities = summarize(results)
+            .into_iter()
+            .map(|(city, stats)| format!("{city}={stats}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            format!("{{{}}}\n", cities.join(", ")),
+            expected(),
+            "{config:?}"
+        );
+    }
+}
+
+#[test]
+fn test_sample_binary() {
+    let path = testdata("measurements-sample.txt");
+    let run = |args: &[&str], stdin: Option<File>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_onebrc"));
+        command.args(args);
+        match stdin {
+            Some(file) => command.arg("-").stdin(file),
+            None => command.arg(&path),
+        };
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{args:?}");
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected(),
+            "{args:?}"
+        );
+    };
+
+    for args in [
+        &[][..],
+        &["--strategy", "chunked"],
+        &["--single-threaded"],
+        &["--strict"],
+    ] {
+        run(args, None);
+    }
+    run(&[], Some(File::open(&path).unwrap()));
+}
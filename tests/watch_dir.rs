@@ -0,0 +1,135 @@
+//! `--watch-dir` keeps processing files as they show up in a directory, and rewrites the output
+//! with the results for all of them so far after each one.
+
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// kills the watcher when the test finishes, even if it fails
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// the output of a normal run over `input`
+fn run(input: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg(input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Write `data` to `name` in `dir` all at once, the way `--watch-dir` wants files to show up
+fn drop_file(dir: &Path, name: &str, data: &[u8]) {
+    let tmp = dir.join(format!(".{name}"));
+    std::fs::write(&tmp, data).unwrap();
+    std::fs::rename(tmp, dir.join(name)).unwrap();
+}
+
+/// wait for `path` to have `expected` in it
+fn wait_for(path: &Path, expected: &str) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let contents = std::fs::read_to_string(path).ok();
+        if contents.as_deref() == Some(expected) {
+            return;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for {expected:?}, got {contents:?}"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_watch_dir() {
+    let data = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/measurements-sample.txt"
+    ))
+    .unwrap();
+    let tmp = tempfile::tempdir().unwrap();
+    // split after the line that crosses the middle
+    let split = data[data.len() / 2..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .unwrap();
+    let (first, second) = data.split_at(data.len() / 2 + split + 1);
+
+    let expected_path = tmp.path().join("first.txt");
+    std::fs::write(&expected_path, first).unwrap();
+    let expected_first = run(&expected_path);
+    std::fs::write(&expected_path, &data).unwrap();
+    let expected_both = run(&expected_path);
+    assert_ne!(expected_first, expected_both);
+
+    let dir = tmp.path().join("in");
+    std::fs::create_dir(&dir).unwrap();
+    let output = tmp.path().join("out.txt");
+    let _watcher = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--watch-dir")
+            .arg(&dir)
+            .arg("--output")
+            .arg(&output)
+            .spawn()
+            .unwrap(),
+    );
+
+    drop_file(&dir, "1.txt", first);
+    wait_for(&output, &expected_first);
+    drop_file(&dir, "2.txt", second);
+    wait_for(&output, &expected_both);
+}
+
+#[test]
+fn test_watch_dir_output_inside() {
+    let tmp = tempfile::tempdir().unwrap();
+    let expected_path = tmp.path().join("expected.txt");
+    std::fs::write(&expected_path, "Paris;12.3\n").unwrap();
+    let expected = run(&expected_path);
+
+    // the output is rewritten after every file, but it isn't an input itself
+    let dir = tmp.path().join("in");
+    std::fs::create_dir(&dir).unwrap();
+    let output = dir.join("out.txt");
+    // in strict mode, reading the output back in would be an error
+    let mut watcher = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(["--strict", "--watch-dir"])
+            .arg(&dir)
+            .arg("--output")
+            .arg(&output)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+
+    drop_file(&dir, "1.txt", b"Paris;12.3\n");
+    wait_for(&output, &expected);
+    // long enough for it to have been picked up, if it was going to be
+    std::thread::sleep(Duration::from_millis(500));
+    drop_file(&dir, "2.txt", b"Oslo;-3.0\n");
+    std::fs::write(&expected_path, "Paris;12.3\nOslo;-3.0\n").unwrap();
+    wait_for(&output, &run(&expected_path));
+    std::thread::sleep(Duration::from_millis(500));
+
+    watcher.0.kill().unwrap();
+    let mut stderr = String::new();
+    watcher
+        .0
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,55 @@
+//! End to end checks that the binary behaves the same on Windows: files are mmapped and read
+//! through Windows-style paths, and the output ends lines with '\n' rather than "\r\n", exactly
+//! matching the expected output fixtures.
+#![cfg(windows)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn run(path: &Path) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg(path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", path.display());
+    output.stdout
+}
+
+fn testdata() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata")
+}
+
+#[test]
+fn test_fixtures() {
+    for entry in std::fs::read_dir(testdata()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if !(name.starts_with("measurements-") && name.ends_with(".txt")) {
+            continue;
+        }
+        let expected = std::fs::read(path.with_extension("out")).unwrap();
+        assert!(
+            !expected.contains(&b'\r'),
+            "{name} was checked out with CRLF"
+        );
+
+        let output = run(&path);
+        assert!(!output.contains(&b'\r'), "{name}");
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(&expected),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn test_paths() {
+    let path = testdata().join("measurements-3.txt");
+    let expected = run(&path);
+
+    // forward slashes, and the \\?\ verbatim form that canonicalize gives
+    let forward = PathBuf::from(path.to_str().unwrap().replace('\\', "/"));
+    assert_eq!(run(&forward), expected);
+    assert_eq!(run(&path.canonicalize().unwrap()), expected);
+}